@@ -2,11 +2,20 @@ use std::path::PathBuf;
 
 use pico_args::Arguments;
 
-use crate::error::ThumbnailerError;
+use crate::{
+    error::ThumbnailerError,
+    utils::{animation::AnimationFormat, compression::CompressionFormat},
+};
 
 #[derive(Debug)]
 pub enum ThumbnailerCommand {
     ShowVersion,
+    ShowInfo(ThumbnailerInfoParams),
+    VerifyHeader(ThumbnailerInfoParams),
+    FixHeader(ThumbnailerFixHeaderParams),
+    Keys(ThumbnailerKeysCommand),
+    Decompress(ThumbnailerDecompressParams),
+    Compress(ThumbnailerCompressParams),
     GenerateThumbnail(ThumbnailerFileParams),
 }
 
@@ -18,16 +27,43 @@ impl TryFrom<&mut Arguments> for ThumbnailerCommand {
             return Ok(Self::ShowVersion);
         }
 
-        Ok(Self::GenerateThumbnail(ThumbnailerFileParams::try_from(
-            args,
-        )?))
+        // Subcommands are only consumed when recognized, otherwise the first free argument
+        // is the input file for thumbnail generation
+        let mut subcommand_args = args.clone();
+        let command = match subcommand_args.subcommand()?.as_deref() {
+            Some("info") => Self::ShowInfo(ThumbnailerInfoParams::try_from(&mut subcommand_args)?),
+            Some("verify") => {
+                Self::VerifyHeader(ThumbnailerInfoParams::try_from(&mut subcommand_args)?)
+            }
+            Some("fix-header") => {
+                Self::FixHeader(ThumbnailerFixHeaderParams::try_from(&mut subcommand_args)?)
+            }
+            Some("keys") => Self::Keys(ThumbnailerKeysCommand::try_from(&mut subcommand_args)?),
+            Some("decompress") => {
+                Self::Decompress(ThumbnailerDecompressParams::try_from(&mut subcommand_args)?)
+            }
+            Some("compress") => {
+                Self::Compress(ThumbnailerCompressParams::try_from(&mut subcommand_args)?)
+            }
+            _ => {
+                return Ok(Self::GenerateThumbnail(ThumbnailerFileParams::try_from(
+                    args,
+                )?))
+            }
+        };
+        Ok(command)
     }
 }
 
 #[derive(Debug)]
 pub struct ThumbnailerFileParams {
     pub is_dry_run: bool,
+    pub is_lenient: bool,
+    pub borrow_icon: bool,
+    pub is_banner: bool,
+    pub sub_texture: Option<usize>,
     pub size: Option<u32>,
+    pub animation_format: Option<AnimationFormat>,
     pub input_file: PathBuf,
     pub output_file: Option<PathBuf>,
 }
@@ -37,13 +73,119 @@ impl TryFrom<&mut Arguments> for ThumbnailerFileParams {
 
     fn try_from(args: &mut Arguments) -> Result<Self, Self::Error> {
         let is_dry_run = args.contains("-n");
+        let is_lenient = args.contains("--lenient");
+        let borrow_icon = args.contains("--borrow-icon");
+        let is_banner = args.contains("--banner");
+        let sub_texture = args.opt_value_from_str("--subtexture")?;
         let size = args.opt_value_from_str("-s")?;
+        let animation_format = args.opt_value_from_str("--animated")?;
         let input_file = args.free_from_str()?;
         let output_file = args.opt_free_from_str()?;
 
         Ok(Self {
             is_dry_run,
+            is_lenient,
+            borrow_icon,
+            is_banner,
+            sub_texture,
             size,
+            animation_format,
+            input_file,
+            output_file,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ThumbnailerInfoParams {
+    pub input_file: PathBuf,
+}
+
+impl TryFrom<&mut Arguments> for ThumbnailerInfoParams {
+    type Error = ThumbnailerError;
+
+    fn try_from(args: &mut Arguments) -> Result<Self, Self::Error> {
+        let input_file = args.free_from_str()?;
+
+        Ok(Self { input_file })
+    }
+}
+
+#[derive(Debug)]
+pub struct ThumbnailerFixHeaderParams {
+    pub assume_yes: bool,
+    pub input_file: PathBuf,
+}
+
+impl TryFrom<&mut Arguments> for ThumbnailerFixHeaderParams {
+    type Error = ThumbnailerError;
+
+    fn try_from(args: &mut Arguments) -> Result<Self, Self::Error> {
+        let assume_yes = args.contains("-y");
+        let input_file = args.free_from_str()?;
+
+        Ok(Self {
+            assume_yes,
+            input_file,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ThumbnailerKeysCommand {
+    Check,
+    Import(PathBuf),
+}
+
+impl TryFrom<&mut Arguments> for ThumbnailerKeysCommand {
+    type Error = ThumbnailerError;
+
+    fn try_from(args: &mut Arguments) -> Result<Self, Self::Error> {
+        match args.subcommand()?.as_deref() {
+            Some("check") | None => Ok(Self::Check),
+            Some("import") => Ok(Self::Import(args.free_from_str()?)),
+            Some(command) => Err(ThumbnailerError::UnknownKeysCommand(command.to_string())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ThumbnailerDecompressParams {
+    pub input_file: PathBuf,
+    pub output_file: Option<PathBuf>,
+}
+
+impl TryFrom<&mut Arguments> for ThumbnailerDecompressParams {
+    type Error = ThumbnailerError;
+
+    fn try_from(args: &mut Arguments) -> Result<Self, Self::Error> {
+        let input_file = args.free_from_str()?;
+        let output_file = args.opt_free_from_str()?;
+
+        Ok(Self {
+            input_file,
+            output_file,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ThumbnailerCompressParams {
+    pub format: CompressionFormat,
+    pub input_file: PathBuf,
+    pub output_file: PathBuf,
+}
+
+impl TryFrom<&mut Arguments> for ThumbnailerCompressParams {
+    type Error = ThumbnailerError;
+
+    fn try_from(args: &mut Arguments) -> Result<Self, Self::Error> {
+        let format = args.free_from_str()?;
+        let input_file = args.free_from_str()?;
+        let output_file = args.free_from_str()?;
+
+        Ok(Self {
+            format,
             input_file,
             output_file,
         })
@@ -1,7 +1,10 @@
+use std::path::PathBuf;
+
 use thiserror::Error;
 
 use crate::n3ds::errors::N3DSParsingError;
 use crate::nds::errors::NDSParsingError;
+use crate::utils::compression::DecompressionError;
 
 #[derive(Error, Debug)]
 pub enum ThumbnailerError {
@@ -13,10 +16,28 @@ pub enum ThumbnailerError {
     MimeTypeDetectionFailure,
     #[error("Incompatible mime type, {0} is not a supported Nintendo DS or 3DS file.")]
     IncompatibleMimeType(String),
+    #[error("No metadata can be shown for {0} files.")]
+    NoMetadataAvailable(String),
+    #[error("{} is not a CDN title directory.", .0.display())]
+    NotACdnDirectory(PathBuf),
+    #[error("NDS header verification failed.")]
+    HeaderVerificationFailed,
+    #[error("Unknown animation format {0}, supported formats are apng and gif.")]
+    UnknownAnimationFormat(String),
+    #[error("Unknown keys command {0}, supported commands are check and import.")]
+    UnknownKeysCommand(String),
+    #[error("Unknown compression format {0}, supported formats are lz10, lz11, huff4, huff8, rle and blz.")]
+    UnknownCompressionFormat(String),
+    #[error("No animation frames available to be saved.")]
+    NoAnimationFrames,
     #[error(transparent)]
     IoError(#[from] std::io::Error),
     #[error(transparent)]
     ImageError(#[from] image::ImageError),
+    #[error(transparent)]
+    PngEncodingError(#[from] png::EncodingError),
+    #[error("Decompression error: {0}")]
+    DecompressionError(#[from] DecompressionError),
     #[error("NDS format parsing error: {0}")]
     NDSParsingError(#[from] NDSParsingError),
     #[error("3DS format parsing error: {0}")]
@@ -4,19 +4,74 @@ mod n3ds;
 mod nds;
 mod utils;
 
-use image::DynamicImage;
-use n3ds::structures::SMDHIcon;
-use nds::extract_nds_banner;
+use image::{DynamicImage, Frame, RgbaImage};
+use n3ds::{
+    crypto::KeyStore,
+    errors::N3DSParsingError,
+    structures::{
+        is_cdn_dir, CIACertificate, CIAContainer, CIAIcon, CIATicket, CIATitleMetadata, Cbmd,
+        Ctpk, LayoutImage, NCCHHeader, Smdh, T3x, Theme, TitleId, TitleKind, Unistore,
+    },
+};
+use nds::{
+    extract_nds_banner, extract_nds_header, extract_tad_banner, extract_twl_header, fix_nds_header,
+    is_twl_srl,
+    structures::{NDSBannerChecksumMode, NDSBannerDetails, NDSHeaderVerification, TwlRegionFlags},
+    verify_nds_header,
+};
 use pico_args::Arguments;
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
+use std::io::{stdin, stdout, Seek, SeekFrom, Write};
+use std::path::Path;
 use std::process::ExitCode;
-use utils::get_mime_type;
+use utils::{
+    animation::save_animation,
+    compression::{self, CompressionFormat},
+    emblem::{draw_emblem, Emblem},
+    get_mime_type,
+};
 
 use crate::{
-    args::{ThumbnailerCommand, ThumbnailerFileParams},
+    args::{
+        ThumbnailerCommand, ThumbnailerCompressParams, ThumbnailerDecompressParams,
+        ThumbnailerFileParams, ThumbnailerFixHeaderParams, ThumbnailerInfoParams,
+        ThumbnailerKeysCommand,
+    },
     error::ThumbnailerError,
 };
 
+/* There are currently two supported file types:
+ * .nds roms, indicated by the application/x-nintendo-ds-rom mime type
+ * and .cia files, indicated by the application/x-ctr-cia mime type
+ *
+ * Note that application/x-ctr-cia is the same mime type used by Citra
+ * and might not be defined on the user system
+ * Therefore .cia support might require shipping .cia mime type support
+ */
+
+// You might want to check https://github.com/citra-emu/citra/blob/master/dist/citra.xml
+// for the Nintendo 3DS-related mime types as defined by the Citra emulator
+
+const MIME_TYPE_NDS: &str = "application/x-nintendo-ds-rom";
+const MIME_TYPE_TWL_SRL: &str = "application/x-twl-srl";
+const MIME_TYPE_TWL_TAD: &str = "application/x-twl-tad";
+const MIME_TYPE_N3DS_CIA: &str = "application/x-ctr-cia";
+const MIME_TYPE_N3DS_SMDH: &str = "application/x-ctr-smdh";
+const MIME_TYPE_N3DS_CBMD: &str = "application/x-ctr-cbmd";
+const MIME_TYPE_N3DS_THEME: &str = "application/x-ctr-theme";
+const MIME_TYPE_ZIP: &str = "application/zip";
+const MIME_TYPE_N3DS_BCLIM: &str = "application/x-ctr-bclim";
+const MIME_TYPE_N3DS_BFLIM: &str = "application/x-ctr-bflim";
+const MIME_TYPE_N3DS_CTPK: &str = "application/x-ctr-ctpk";
+const MIME_TYPE_N3DS_T3X: &str = "application/x-ctr-t3x";
+const MIME_TYPE_N3DS_UNISTORE: &str = "application/x-ctr-unistore";
+const MIME_TYPE_N3DS_3DSX: &str = "application/x-ctr-3dsx";
+const MIME_TYPE_N3DS_3DSX_GENERIC: &str = "application/x-nintendo-3ds-executable";
+const MIME_TYPE_N3DS_CXI: &str = "application/x-ctr-cxi";
+const MIME_TYPE_N3DS_CCI: &str = "application/x-ctr-cci";
+const MIME_TYPE_N3DS_CCI_GENERIC: &str = "application/x-nintendo-3ds-rom";
+const MIME_TYPE_DIRECTORY: &str = "inode/directory";
+
 fn main() -> ExitCode {
     let mut args = Arguments::from_env();
 
@@ -31,6 +86,12 @@ fn main() -> ExitCode {
 fn bign_handheld_thumbnailer(cmd: ThumbnailerCommand) -> Result<(), ThumbnailerError> {
     match cmd {
         ThumbnailerCommand::ShowVersion => show_version(),
+        ThumbnailerCommand::ShowInfo(info_params) => show_info(info_params),
+        ThumbnailerCommand::VerifyHeader(info_params) => verify_header(info_params),
+        ThumbnailerCommand::FixHeader(fix_header_params) => fix_header(fix_header_params),
+        ThumbnailerCommand::Keys(keys_command) => keys(keys_command),
+        ThumbnailerCommand::Decompress(decompress_params) => decompress(decompress_params),
+        ThumbnailerCommand::Compress(compress_params) => compress(compress_params),
         ThumbnailerCommand::GenerateThumbnail(file_params) => generate_thumbnail(file_params),
     }
 }
@@ -43,6 +104,582 @@ fn show_version() -> Result<(), ThumbnailerError> {
     Ok(())
 }
 
+/// Directories are only supported when they are CDN title directories,
+/// any other directory is rejected before trying to open it
+fn check_cdn_dir(path: &Path, mime_type: &str) -> Result<(), ThumbnailerError> {
+    if mime_type == MIME_TYPE_DIRECTORY && !is_cdn_dir(path) {
+        return Err(ThumbnailerError::NotACdnDirectory(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+fn show_info(info_params: ThumbnailerInfoParams) -> Result<(), ThumbnailerError> {
+    let path = info_params.input_file.as_path();
+
+    let mime_type = get_mime_type(path)?;
+    check_cdn_dir(path, &mime_type)?;
+    let mut input = File::open(path)?;
+
+    match &mime_type[..] {
+        MIME_TYPE_NDS | MIME_TYPE_TWL_SRL => {
+            let header = extract_nds_header(&mut input)?;
+            println!("Game title: {}", header.game_title);
+            println!("Game code: {}", header.game_code);
+            println!("Maker code: {}", header.maker_code);
+            println!("Unit code: {:?}", header.unit_code);
+            if let Some(region) = header.region() {
+                println!("Region: {region:?}");
+            }
+            println!("ROM version: {}", header.rom_version);
+            println!("Device capacity: {} bytes", header.device_capacity);
+            println!("Total used ROM size: {} bytes", header.total_used_rom_size);
+
+            let twl_header = extract_twl_header(&mut input, &header)?;
+            if let Some(twl_header) = &twl_header {
+                let title_id = TitleId(twl_header.title_id);
+                print_title_id("DSi title ID", title_id, title_id.kind());
+                println!("DSiWare: {}", twl_header.is_dsiware());
+                println!("DSi regions: {:?}", twl_header.region_flags);
+                for rating in &twl_header.age_ratings {
+                    let pending = if rating.is_pending { " (pending)" } else { "" };
+                    println!("Age rating ({:?}): {}{pending}", rating.board, rating.age);
+                }
+            }
+
+            let banner_details = extract_nds_banner(&mut input, NDSBannerChecksumMode::Lenient)?;
+            let region_flags = twl_header.map(|twl_header| twl_header.region_flags);
+            print_nds_banner_details(&banner_details, region_flags);
+        }
+        MIME_TYPE_N3DS_CIA => {
+            let container = CIAContainer::from_file(&mut input)?;
+            print_cia_container(&container);
+            match container.certificates(&mut input) {
+                Ok(certificates) => print_cia_certificates(&certificates),
+                Err(err) => eprintln!("Failed to parse CIA certificate chain: {err}"),
+            }
+            match container.ticket(&mut input) {
+                Ok(ticket) => print_cia_ticket(&ticket),
+                Err(err) => eprintln!("Failed to parse CIA ticket: {err}"),
+            }
+            match container.title_metadata(&mut input) {
+                Ok(title_metadata) => print_cia_title_metadata(&title_metadata),
+                Err(err) => eprintln!("Failed to parse CIA title metadata: {err}"),
+            }
+            print_cia_icon_details(CIAIcon::from_cia(
+                &mut input,
+                NDSBannerChecksumMode::Lenient,
+            )?);
+        }
+        MIME_TYPE_DIRECTORY => {
+            if let Some(ticket) = CIATicket::from_cdn(path)? {
+                print_cia_ticket(&ticket);
+            }
+            print_cia_title_metadata(&CIATitleMetadata::from_cdn(path)?);
+            print_cia_icon_details(CIAIcon::from_cdn(path, NDSBannerChecksumMode::Lenient)?);
+        }
+        MIME_TYPE_N3DS_SMDH => print_smdh_details(&Smdh::from_smdh(&mut input)?),
+        MIME_TYPE_N3DS_CBMD => print_cbmd_details(&Cbmd::from_cbmd(&mut input)?),
+        MIME_TYPE_N3DS_THEME => print_theme_details(&Theme::from_body_lz(&mut input)?),
+        MIME_TYPE_ZIP => print_theme_details(&Theme::from_zip(&mut input)?),
+        MIME_TYPE_N3DS_BCLIM | MIME_TYPE_N3DS_BFLIM => {
+            print_layout_image_details(&LayoutImage::from_layout_image(&mut input)?);
+        }
+        MIME_TYPE_N3DS_CTPK => print_ctpk_details(&Ctpk::from_ctpk(&mut input)?),
+        MIME_TYPE_N3DS_T3X => print_t3x_details(&T3x::from_t3x(&mut input)?),
+        MIME_TYPE_N3DS_UNISTORE => {
+            let store = Unistore::from_unistore(&mut input)?;
+            print_unistore_details(&store);
+            match store.first_sheet(path) {
+                Ok(sheet) => print_t3x_details(&sheet),
+                Err(err) => eprintln!("{err}"),
+            }
+        }
+        MIME_TYPE_N3DS_3DSX | MIME_TYPE_N3DS_3DSX_GENERIC => {
+            print_smdh_details(&Smdh::from_n3dsx_with_sidecar(&mut input, path)?);
+        }
+        MIME_TYPE_N3DS_CXI => {
+            print_ncch_header(&NCCHHeader::from_cxi(&mut input)?);
+            input.seek(SeekFrom::Start(0))?;
+            print_smdh_details(&Smdh::from_cxi(&mut input)?);
+        }
+        MIME_TYPE_N3DS_CCI | MIME_TYPE_N3DS_CCI_GENERIC => {
+            print_ncch_header(&NCCHHeader::from_cci(&mut input)?);
+            print_smdh_details(&Smdh::from_cci(&mut input)?);
+        }
+        _ => return Err(ThumbnailerError::NoMetadataAvailable(mime_type)),
+    }
+
+    Ok(())
+}
+
+fn print_nds_banner_details(
+    banner_details: &NDSBannerDetails,
+    region_flags: Option<TwlRegionFlags>,
+) {
+    println!("Icon version: {:?}", banner_details.icon_version);
+    if banner_details.is_suspect() {
+        for mismatch in &banner_details.checksum_mismatches {
+            println!("Banner checksum: {mismatch}");
+        }
+    } else {
+        println!("Banner checksum: OK");
+    }
+
+    if let Some(title) = banner_details.best_title(region_flags) {
+        println!("Title: {}", title.title);
+    }
+    for title in &banner_details.titles {
+        let mut lines = vec![title.title.as_str()];
+        lines.extend(title.subtitle.as_deref());
+        lines.extend(title.publisher.as_deref());
+        println!("Title ({:?}): {}", title.language, lines.join(" / "));
+    }
+}
+
+fn print_title_id(label: &str, title_id: TitleId, kind: TitleKind) {
+    println!(
+        "{label}: {title_id} ({kind}, {:?}, unique ID {:05X}, variation {:02X})",
+        title_id.platform(),
+        title_id.unique_id(),
+        title_id.variation()
+    );
+}
+
+fn print_ncch_header(ncch_header: &NCCHHeader) {
+    print_title_id(
+        "Program ID",
+        ncch_header.program_id,
+        ncch_header.title_kind(),
+    );
+    println!("Partition ID: {}", ncch_header.partition_id);
+    println!("Product code: {}", ncch_header.product_code);
+}
+
+fn print_cia_container(container: &CIAContainer) {
+    println!(
+        "Certificate chain: {:#X} ({:#X} bytes)",
+        container.certificate_chain_offset, container.header.certificate_chain_size
+    );
+    println!(
+        "Ticket: {:#X} ({:#X} bytes)",
+        container.ticket_offset, container.header.ticket_size
+    );
+    println!(
+        "Title metadata: {:#X} ({:#X} bytes)",
+        container.tmd_offset, container.header.tmd_size
+    );
+    println!(
+        "Contents: {:#X} ({:#X} bytes)",
+        container.content_offset, container.header.content_size
+    );
+    match container.meta_offset {
+        Some(meta_offset) => println!("Meta: {meta_offset:#X}"),
+        None => println!("Meta: {:?}", container.header.meta_size),
+    }
+}
+
+fn print_cia_certificates(certificates: &[CIACertificate]) {
+    for certificate in certificates {
+        println!(
+            "Certificate: {}-{} ({:?}, {} bytes public key, signed with {:?}, expires {})",
+            certificate.issuer,
+            certificate.name,
+            certificate.key_type,
+            certificate.public_key.len(),
+            certificate.signature.signature_type,
+            certificate.expiration_time
+        );
+    }
+}
+
+fn print_cia_ticket(ticket: &CIATicket) {
+    print_title_id("Title ID", ticket.title_id, ticket.title_id.kind());
+    println!("Ticket ID: {:016X}", ticket.ticket_id);
+    println!("Ticket version: {}", ticket.version);
+    println!("Ticket title version: {}", ticket.title_version);
+    println!("Ticket issuer: {}", ticket.issuer);
+    println!(
+        "Ticket signature: {:?} ({} bytes)",
+        ticket.signature.signature_type,
+        ticket.signature.data.len()
+    );
+    println!("Console ID: {:08X}", ticket.console_id);
+    println!("Common key index: {}", ticket.common_key_index);
+}
+
+fn print_cia_title_metadata(title_metadata: &CIATitleMetadata) {
+    print_title_id(
+        "TMD title ID",
+        title_metadata.title_id,
+        title_metadata.title_id.kind(),
+    );
+    println!("TMD version: {}", title_metadata.version);
+    println!("TMD issuer: {}", title_metadata.issuer);
+    println!(
+        "TMD signature: {:?} ({} bytes)",
+        title_metadata.signature.signature_type,
+        title_metadata.signature.data.len()
+    );
+    println!("Title version: {}", title_metadata.title_version);
+    println!("System version: {:016X}", title_metadata.system_version);
+    println!("Title type: {:08X}", title_metadata.title_type);
+    println!("Group ID: {:04X}", title_metadata.group_id);
+    println!("Save data size: {:#X}", title_metadata.save_data_size);
+    println!("Access rights: {:08X}", title_metadata.access_rights);
+    println!("Boot content: {}", title_metadata.boot_content);
+    for record in &title_metadata.content_info_records {
+        println!(
+            "Content info: offset {}, {} contents, SHA-256 {}",
+            record.content_index_offset,
+            record.content_command_count,
+            hex_string(&record.sha256_hash)
+        );
+    }
+    for record in &title_metadata.content_chunk_records {
+        println!(
+            "Content {:08X}: {:?}, {:?}, {:#X} bytes, SHA-256 {}",
+            record.content_id,
+            record.content_index,
+            record.content_type,
+            record.content_size,
+            hex_string(&record.sha256_hash)
+        );
+    }
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn print_cia_icon_details(cia_icon: CIAIcon) {
+    match cia_icon {
+        CIAIcon::Twl(banner_details) => {
+            println!("DSiWare: true");
+            print_nds_banner_details(&banner_details, None);
+        }
+        CIAIcon::Ctr(smdh) => print_smdh_details(&smdh),
+    }
+}
+
+fn print_theme_details(theme: &Theme) {
+    println!("Theme version: {}", theme.version);
+    println!("BGM: {}", theme.has_bgm);
+    println!(
+        "Top screen: {:?} (frame type {}), solid color at {:#X}, texture at {:#X}, additional texture at {:#X}",
+        theme.top_draw_type,
+        theme.top_frame_type,
+        theme.top_solid_color_offset,
+        theme.top_texture_offset,
+        theme.top_additional_texture_offset
+    );
+    println!(
+        "Bottom screen: {:?} (frame type {}), texture at {:#X}",
+        theme.bottom_draw_type, theme.bottom_frame_type, theme.bottom_texture_offset
+    );
+}
+
+fn print_layout_image_details(layout_image: &LayoutImage) {
+    let (texture_width, texture_height) = layout_image.texture_dimensions();
+    println!("Layout image: {:?}", layout_image.kind);
+    println!(
+        "Texture: {}x{} {:?} (stored as {}x{}, swizzle {:?}, {:#X} bytes)",
+        layout_image.width,
+        layout_image.height,
+        layout_image.format,
+        texture_width,
+        texture_height,
+        layout_image.swizzle,
+        layout_image.data.len()
+    );
+}
+
+fn print_ctpk_details(ctpk: &Ctpk) {
+    println!("CTPK version: {}", ctpk.version);
+    for texture in &ctpk.textures {
+        println!(
+            "Texture {}: {}x{} {:?} ({} mipmap levels, {:#X} bytes)",
+            texture.name,
+            texture.width,
+            texture.height,
+            texture.format,
+            texture.mipmap_levels,
+            texture.data.len()
+        );
+    }
+}
+
+fn print_t3x_details(t3x: &T3x) {
+    println!(
+        "Texture: {}x{} {:?} ({} mipmap levels, cube map: {})",
+        t3x.width, t3x.height, t3x.format, t3x.mipmap_levels, t3x.is_cube_map
+    );
+    match t3x.compression {
+        Some(compression) => println!("Compression: {compression}"),
+        None => println!("Compression: none"),
+    }
+    for (index, sub_texture) in t3x.sub_textures.iter().enumerate() {
+        println!(
+            "Sub-texture {index}: {}x{} (left {}, top {}, right {}, bottom {}, rotated: {})",
+            sub_texture.width,
+            sub_texture.height,
+            sub_texture.left,
+            sub_texture.top,
+            sub_texture.right,
+            sub_texture.bottom,
+            sub_texture.is_rotated()
+        );
+    }
+}
+
+fn print_unistore_details(store: &Unistore) {
+    if let Some(title) = &store.title {
+        println!("Store title: {title}");
+    }
+    if let Some(author) = &store.author {
+        println!("Store author: {author}");
+    }
+    if let Some(revision) = store.revision {
+        println!("Store revision: {revision}");
+    }
+    println!("Entries: {}", store.entry_count);
+    println!("Icon sheets: {}", store.sheets.join(", "));
+}
+
+fn print_cbmd_details(cbmd: &Cbmd) {
+    for texture in &cbmd.textures {
+        println!(
+            "Banner texture {}: {}x{} {:?} ({:#X} bytes)",
+            texture.name,
+            texture.width,
+            texture.height,
+            texture.format,
+            texture.data.len()
+        );
+    }
+}
+
+fn print_smdh_details(smdh: &Smdh) {
+    println!("SMDH version: {}", smdh.version);
+    if let Some(title) = smdh.best_title() {
+        println!("Title: {}", title.short_description);
+    }
+    for title in smdh.titles.iter().filter(|title| !title.is_empty()) {
+        println!(
+            "Title ({:?}): {} / {} / {}",
+            title.language, title.short_description, title.long_description, title.publisher
+        );
+    }
+
+    if smdh.region_lockout.is_region_free() {
+        println!("Regions: region free");
+    } else {
+        println!("Regions: {:?}", smdh.region_lockout);
+    }
+    for rating in &smdh.age_ratings {
+        let mut rating_details = if rating.is_unrestricted {
+            String::from("no restriction")
+        } else {
+            rating.age.to_string()
+        };
+        if rating.is_pending {
+            rating_details.push_str(" (pending)");
+        }
+        println!("Age rating ({:?}): {rating_details}", rating.board);
+    }
+    println!("Flags: {:?}", smdh.flags);
+    println!(
+        "EULA version: {}.{}",
+        smdh.eula_version.0, smdh.eula_version.1
+    );
+    println!("Optimal banner frame: {}", smdh.optimal_banner_frame);
+    println!("Match maker ID: {:08X}", smdh.match_maker_id);
+    println!("Match maker BIT ID: {:016X}", smdh.match_maker_bit_id);
+    println!("CEC ID: {:08X}", smdh.cec_id);
+}
+
+/// Updates and DLCs have no icon of their own, so when requested the icon is borrowed
+/// from their base title found in the same directory, with an emblem on top
+fn icon_or_base_title_icon(
+    icon: Result<RgbaImage, N3DSParsingError>,
+    file_params: &ThumbnailerFileParams,
+) -> Result<RgbaImage, ThumbnailerError> {
+    match icon {
+        Err(N3DSParsingError::TitleHasNoIcon(title_id)) if file_params.borrow_icon => {
+            let mut icon = Smdh::from_base_title_sibling(&file_params.input_file, title_id)?
+                .icon
+                .best_icon_for_size(file_params.size);
+            let emblem = if title_id.kind() == TitleKind::UpdatePatch {
+                Emblem::Update
+            } else {
+                Emblem::Dlc
+            };
+            draw_emblem(&mut icon, emblem);
+            Ok(icon)
+        }
+        icon => Ok(icon?),
+    }
+}
+
+/// Spritesheets are thumbnailed whole, unless a single sub-texture was requested
+fn t3x_image(t3x: &T3x, sub_texture: Option<usize>) -> Result<RgbaImage, N3DSParsingError> {
+    let img = match sub_texture {
+        Some(index) => t3x.sub_texture(index),
+        None => t3x.sheet(),
+    };
+    Ok(img?)
+}
+
+fn nds_banner_icon(
+    banner_details: NDSBannerDetails,
+    animation_frames: &mut Option<Vec<Frame>>,
+) -> RgbaImage {
+    if banner_details.is_suspect() {
+        eprintln!("NDS banner checksums don't match, the icon might be corrupt!");
+    }
+    *animation_frames = banner_details.animated_icon.map(|icon| icon.to_frames());
+    banner_details.icon
+}
+
+fn verify_header(info_params: ThumbnailerInfoParams) -> Result<(), ThumbnailerError> {
+    let path = info_params.input_file.as_path();
+
+    let mime_type = get_mime_type(path)?;
+    if mime_type != MIME_TYPE_NDS && mime_type != MIME_TYPE_TWL_SRL {
+        return Err(ThumbnailerError::IncompatibleMimeType(mime_type));
+    }
+
+    let mut input = File::open(path)?;
+    let verification = verify_nds_header(&mut input)?;
+    print_header_verification(&verification);
+
+    if !verification.is_valid() {
+        return Err(ThumbnailerError::HeaderVerificationFailed);
+    }
+    Ok(())
+}
+
+fn fix_header(fix_header_params: ThumbnailerFixHeaderParams) -> Result<(), ThumbnailerError> {
+    let path = fix_header_params.input_file.as_path();
+
+    let mime_type = get_mime_type(path)?;
+    if mime_type != MIME_TYPE_NDS && mime_type != MIME_TYPE_TWL_SRL {
+        return Err(ThumbnailerError::IncompatibleMimeType(mime_type));
+    }
+
+    let mut input = OpenOptions::new().read(true).write(true).open(path)?;
+    let verification = verify_nds_header(&mut input)?;
+    print_header_verification(&verification);
+
+    if verification.header_crc.is_valid() && verification.logo_crc.is_valid() {
+        println!("Header checksums are already valid, nothing to fix.");
+        return Ok(());
+    }
+
+    if !fix_header_params.assume_yes {
+        print!("Rewrite the header checksums of {}? [y/N] ", path.display());
+        stdout().flush()?;
+
+        let mut answer = String::new();
+        stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Header left untouched.");
+            return Ok(());
+        }
+    }
+
+    let verification = fix_nds_header(&mut input)?;
+    println!("Header checksums rewritten.");
+    print_header_verification(&verification);
+
+    if !verification.is_logo_valid {
+        eprintln!("The Nintendo logo is modified, the header will still be rejected.");
+    }
+    Ok(())
+}
+
+fn print_header_verification(verification: &NDSHeaderVerification) {
+    println!("Header CRC16: {}", verification.header_crc);
+    println!("Logo CRC16: {}", verification.logo_crc);
+    if verification.is_logo_valid {
+        println!("Logo bitmap: OK");
+    } else {
+        println!("Logo bitmap: INVALID");
+    }
+}
+
+fn keys(keys_command: ThumbnailerKeysCommand) -> Result<(), ThumbnailerError> {
+    let key_file_path = KeyStore::default_path();
+
+    match keys_command {
+        ThumbnailerKeysCommand::Check => {
+            let key_store = KeyStore::from_default_path().unwrap_or_else(|err| {
+                eprintln!("{err}");
+                KeyStore::default()
+            });
+
+            println!("Key file: {}", key_file_path.display());
+            for (name, description) in KeyStore::KNOWN_KEYS {
+                let status = if key_store.contains(name) {
+                    "available"
+                } else {
+                    "missing"
+                };
+                println!("{name} ({description}): {status}");
+            }
+        }
+        ThumbnailerKeysCommand::Import(boot9_path) => {
+            let boot9 = fs::read(boot9_path)?;
+            let key_store = KeyStore::from_boot9(&boot9).map_err(N3DSParsingError::from)?;
+
+            let added_keys = key_store.append_missing_to(&key_file_path)?;
+            println!(
+                "Added {} keys derived from boot9.bin to {}",
+                added_keys.len(),
+                key_file_path.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Nothing on a DS or 3DS decompresses to more than the 256 MiB of a New 3DS memory
+const MAX_DECOMPRESSED_FILE_SIZE: usize = 0x1000_0000;
+
+fn decompress(decompress_params: ThumbnailerDecompressParams) -> Result<(), ThumbnailerError> {
+    let input = fs::read(&decompress_params.input_file)?;
+    let (format, output) = compression::decompress(&input, MAX_DECOMPRESSED_FILE_SIZE)?;
+    println!(
+        "Decompressed {format} data from {} to {} bytes",
+        input.len(),
+        output.len()
+    );
+
+    let Some(output_file) = decompress_params.output_file else {
+        eprintln!("No output path, not saving decompressed data.");
+        return Ok(());
+    };
+    fs::write(output_file, output)?;
+    Ok(())
+}
+
+fn compress(compress_params: ThumbnailerCompressParams) -> Result<(), ThumbnailerError> {
+    let input = fs::read(&compress_params.input_file)?;
+    let output = compress_params.format.compress(&input);
+    if compress_params.format == CompressionFormat::Blz && output == input {
+        eprintln!("BLZ compression doesn't make the data smaller, saving it uncompressed.");
+    }
+    println!(
+        "Compressed data as {} from {} to {} bytes",
+        compress_params.format,
+        input.len(),
+        output.len()
+    );
+
+    fs::write(compress_params.output_file, output)?;
+    Ok(())
+}
+
 fn generate_thumbnail(file_params: ThumbnailerFileParams) -> Result<(), ThumbnailerError> {
     if file_params.is_dry_run {
         eprintln!("Dry run mode, extracted icon will not be saved to a file!");
@@ -50,40 +687,101 @@ fn generate_thumbnail(file_params: ThumbnailerFileParams) -> Result<(), Thumbnai
 
     let path = file_params.input_file.as_path();
 
-    /* There are currently two supported file types:
-     * .nds roms, indicated by the application/x-nintendo-ds-rom mime type
-     * and .cia files, indicated by the application/x-ctr-cia mime type
-     *
-     * Note that application/x-ctr-cia is the same mime type used by Citra
-     * and might not be defined on the user system
-     * Therefore .cia support might require shipping .cia mime type support
-     */
-
-    // You might want to check https://github.com/citra-emu/citra/blob/master/dist/citra.xml
-    // for the Nintendo 3DS-related mime types as defined by the Citra emulator
-
     let mime_type = get_mime_type(path)?;
+    check_cdn_dir(path, &mime_type)?;
     let mut input = File::open(path)?;
 
-    const MIME_TYPE_NDS: &str = "application/x-nintendo-ds-rom";
-    const MIME_TYPE_N3DS_CIA: &str = "application/x-ctr-cia";
-    const MIME_TYPE_N3DS_SMDH: &str = "application/x-ctr-smdh";
-    const MIME_TYPE_N3DS_3DSX: &str = "application/x-ctr-3dsx";
-    const MIME_TYPE_N3DS_3DSX_GENERIC: &str = "application/x-nintendo-3ds-executable";
-    const MIME_TYPE_N3DS_CXI: &str = "application/x-ctr-cxi";
-    const MIME_TYPE_N3DS_CCI: &str = "application/x-ctr-cci";
-    const MIME_TYPE_N3DS_CCI_GENERIC: &str = "application/x-nintendo-3ds-rom";
+    let checksum_mode = if file_params.is_lenient {
+        NDSBannerChecksumMode::Lenient
+    } else {
+        NDSBannerChecksumMode::Strict
+    };
 
+    let mut animation_frames = None;
     let img = match &mime_type[..] {
-        MIME_TYPE_NDS => extract_nds_banner(&mut input)?.icon,
-        MIME_TYPE_N3DS_CIA => SMDHIcon::from_cia(&mut input)?.large_icon,
-        MIME_TYPE_N3DS_SMDH => SMDHIcon::from_smdh(&mut input)?.large_icon,
+        MIME_TYPE_NDS | MIME_TYPE_TWL_SRL => {
+            let banner_details = extract_nds_banner(&mut input, checksum_mode)?;
+            nds_banner_icon(banner_details, &mut animation_frames)
+        }
+        MIME_TYPE_TWL_TAD => {
+            let banner_details = extract_tad_banner(&mut input, checksum_mode)?;
+            nds_banner_icon(banner_details, &mut animation_frames)
+        }
+        // Banners replace icons only when requested, as they aren't square
+        MIME_TYPE_N3DS_CIA if file_params.is_banner => Cbmd::from_cia(&mut input)?
+            .banner()
+            .map_err(N3DSParsingError::from)?,
+        MIME_TYPE_N3DS_CXI if file_params.is_banner => Cbmd::from_cxi(&mut input)?
+            .banner()
+            .map_err(N3DSParsingError::from)?,
+        MIME_TYPE_N3DS_CCI | MIME_TYPE_N3DS_CCI_GENERIC if file_params.is_banner => {
+            Cbmd::from_cci(&mut input)?
+                .banner()
+                .map_err(N3DSParsingError::from)?
+        }
+        MIME_TYPE_N3DS_CIA => {
+            let icon =
+                CIAIcon::from_cia(&mut input, checksum_mode).map(|cia_icon| match cia_icon {
+                    CIAIcon::Ctr(smdh) => smdh.icon.best_icon_for_size(file_params.size),
+                    CIAIcon::Twl(banner_details) => {
+                        nds_banner_icon(banner_details, &mut animation_frames)
+                    }
+                });
+            icon_or_base_title_icon(icon, &file_params)?
+        }
+        // CDN title directories are only recognized by their contents, checked beforehand
+        MIME_TYPE_DIRECTORY => {
+            let icon = CIAIcon::from_cdn(path, checksum_mode).map(|cia_icon| match cia_icon {
+                CIAIcon::Ctr(smdh) => smdh.icon.best_icon_for_size(file_params.size),
+                CIAIcon::Twl(banner_details) => {
+                    nds_banner_icon(banner_details, &mut animation_frames)
+                }
+            });
+            icon_or_base_title_icon(icon, &file_params)?
+        }
+        MIME_TYPE_N3DS_SMDH => Smdh::from_smdh(&mut input)?
+            .icon
+            .best_icon_for_size(file_params.size),
+        MIME_TYPE_N3DS_THEME => Theme::from_body_lz(&mut input)?
+            .top_screen()
+            .map_err(N3DSParsingError::from)?,
+        // Zip files are only thumbnailed when they hold a theme, which is why the thumbnailer
+        // isn't registered for them and they can only be thumbnailed from the command line
+        MIME_TYPE_ZIP => Theme::preview_from_zip(&mut input)?,
+        MIME_TYPE_N3DS_CBMD => Cbmd::from_cbmd(&mut input)?
+            .banner()
+            .map_err(N3DSParsingError::from)?,
+        MIME_TYPE_N3DS_BCLIM | MIME_TYPE_N3DS_BFLIM => LayoutImage::from_layout_image(&mut input)?
+            .decode()
+            .map_err(N3DSParsingError::from)?,
+        MIME_TYPE_N3DS_CTPK => Ctpk::from_ctpk(&mut input)?
+            .largest_texture()
+            .map_err(N3DSParsingError::from)?,
+        MIME_TYPE_N3DS_T3X => t3x_image(&T3x::from_t3x(&mut input)?, file_params.sub_texture)?,
+        MIME_TYPE_N3DS_UNISTORE => {
+            let sheet = Unistore::from_unistore(&mut input)?.first_sheet(path)?;
+            t3x_image(&sheet, file_params.sub_texture)?
+        }
         MIME_TYPE_N3DS_3DSX | MIME_TYPE_N3DS_3DSX_GENERIC => {
-            SMDHIcon::from_n3dsx(&mut input)?.large_icon
+            Smdh::from_n3dsx_with_sidecar(&mut input, path)?
+                .icon
+                .best_icon_for_size(file_params.size)
+        }
+        MIME_TYPE_N3DS_CXI => {
+            let icon = Smdh::from_cxi(&mut input)
+                .map(|smdh| smdh.icon.best_icon_for_size(file_params.size));
+            icon_or_base_title_icon(icon, &file_params)?
         }
-        MIME_TYPE_N3DS_CXI => SMDHIcon::from_cxi(&mut input)?.large_icon,
         MIME_TYPE_N3DS_CCI | MIME_TYPE_N3DS_CCI_GENERIC => {
-            SMDHIcon::from_cci(&mut input)?.large_icon
+            let icon = Smdh::from_cci(&mut input)
+                .map(|smdh| smdh.icon.best_icon_for_size(file_params.size));
+            icon_or_base_title_icon(icon, &file_params)?
+        }
+        // DSi NAND title contents (.app) have no mime type of their own,
+        // so they are detected by their header instead
+        _ if is_twl_srl(&mut input).unwrap_or(false) => {
+            let banner_details = extract_nds_banner(&mut input, checksum_mode)?;
+            nds_banner_icon(banner_details, &mut animation_frames)
         }
         _ => return Err(ThumbnailerError::IncompatibleMimeType(mime_type)),
     };
@@ -97,6 +795,35 @@ fn generate_thumbnail(file_params: ThumbnailerFileParams) -> Result<(), Thumbnai
         return Ok(());
     };
 
+    // Animations are only saved when explicitly requested, as thumbnails must be static
+    if let Some(animation_format) = file_params.animation_format {
+        let frames = animation_frames.unwrap_or_else(|| {
+            eprintln!("No animated icon available, saving static icon as a single frame.");
+            vec![Frame::new(img)]
+        });
+
+        // Whether to do optional scaling or save as-is
+        let frames = if let Some(size) = file_params.size {
+            frames
+                .into_iter()
+                .map(|frame| {
+                    let (left, top, delay) = (frame.left(), frame.top(), frame.delay());
+                    let buffer = image::imageops::resize(
+                        frame.buffer(),
+                        size,
+                        size,
+                        image::imageops::FilterType::Lanczos3,
+                    );
+                    Frame::from_parts(buffer, left, top, delay)
+                })
+                .collect()
+        } else {
+            frames
+        };
+
+        return save_animation(frames, animation_format, output);
+    }
+
     // Whether to do optional scaling or save as-is
     let img = if let Some(size) = file_params.size {
         DynamicImage::ImageRgba8(img).resize(size, size, image::imageops::FilterType::Lanczos3)
@@ -0,0 +1,629 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    io::{self, ErrorKind, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use aes::{
+    cipher::{BlockDecrypt, KeyInit},
+    Aes128,
+};
+use ctr::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+
+use crate::n3ds::{errors::N3DSCryptoError, structures::TitleId};
+
+mod boot9;
+
+/*
+ * Retail 3DS contents are encrypted with AES-128, using keys that are never bundled
+ * with this thumbnailer: users have to provide their own keys, dumped from their console.
+ *
+ * Keys are kept on a key file following the format used by Citra, a `name=value` pair per line,
+ * where the value is the hex encoded key, e.g.:
+ * slot0x2CKeyX=...
+ * common0=...
+ *
+ * The key file can be filled by hand or with keys derived from a boot9.bin dump.
+ *
+ * Most keys are generated by the 3DS hardware from a KeyX and a KeyY through the key scrambler:
+ * NormalKey = (((KeyX ROL 2) XOR KeyY) + C) ROL 87
+ * where the constant C is provided as the `generator` entry of the key file.
+ *
+ * See https://www.3dbrew.org/wiki/AES_Registers for more info
+*/
+
+pub type AesKey = [u8; 16];
+pub type Seed = [u8; 16];
+
+/// User provided keys and seeds live on `~/.3ds`
+pub fn config_dir() -> PathBuf {
+    let home = env::var_os("HOME").unwrap_or_else(|| "~".into());
+    Path::new(&home).join(".3ds")
+}
+
+#[derive(Debug, Default)]
+pub struct KeyStore {
+    keys: BTreeMap<String, AesKey>,
+}
+
+impl KeyStore {
+    /// Keys needed for NCCH and CIA decryption, along with what they are used for
+    pub const KNOWN_KEYS: [(&str, &str); 13] = [
+        ("generator", "key scrambler constant"),
+        ("slot0x2CKeyX", "NCCH primary key, needed for icons"),
+        ("slot0x25KeyX", "NCCH secondary key, 7.x titles"),
+        ("slot0x18KeyX", "NCCH secondary key, New 3DS 9.3 titles"),
+        ("slot0x1BKeyX", "NCCH secondary key, New 3DS 9.6 titles"),
+        ("fixedSystemKey", "NCCH fixed key, system titles"),
+        ("slot0x3DKeyX", "CIA common key"),
+        ("common0", "CIA common key, eShop titles"),
+        ("common1", "CIA common key, system titles"),
+        ("common2", "CIA common key, unknown"),
+        ("common3", "CIA common key, unknown"),
+        ("common4", "CIA common key, unknown"),
+        ("common5", "CIA common key, unknown"),
+    ];
+
+    /// The key file is expected at `~/.3ds/aes_keys.txt`
+    pub fn default_path() -> PathBuf {
+        config_dir().join("aes_keys.txt")
+    }
+
+    pub fn from_default_path() -> Result<Self, N3DSCryptoError> {
+        Self::from_path(&Self::default_path())
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, N3DSCryptoError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|_| N3DSCryptoError::KeyFileNotFound(path.to_path_buf()))?;
+        Ok(Self::from_contents(&contents))
+    }
+
+    /// Lines that aren't a 16 bytes hex encoded key (e.g. comments or NFC secrets) are skipped
+    pub fn from_contents(contents: &str) -> Self {
+        let keys = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .filter_map(|(name, value)| {
+                let key = parse_hex_key(value.trim())?;
+                Some((name.trim().to_string(), key))
+            })
+            .collect();
+
+        KeyStore { keys }
+    }
+
+    /// Key names are case insensitive
+    pub fn contains(&self, name: &str) -> bool {
+        self.keys
+            .keys()
+            .any(|key_name| key_name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn key(&self, name: &str) -> Result<AesKey, N3DSCryptoError> {
+        self.keys
+            .iter()
+            .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+            .map(|(_, key)| *key)
+            .ok_or_else(|| N3DSCryptoError::MissingKey(name.to_string()))
+    }
+
+    pub fn key_x(&self, slot: u8) -> Result<AesKey, N3DSCryptoError> {
+        self.key(&format!("slot0x{slot:02X}KeyX"))
+    }
+
+    /// Generates a normal key from the KeyX on the given keyslot and the given KeyY
+    pub fn scrambled_key(&self, slot: u8, key_y: &AesKey) -> Result<AesKey, N3DSCryptoError> {
+        let key_x = self.key_x(slot)?;
+        let generator = self.key("generator")?;
+        Ok(scramble_key(&key_x, key_y, &generator))
+    }
+
+    /// Appends the keys missing from the key file at `path`, creating it if needed,
+    /// while leaving existing entries untouched. Returns the names of the appended keys
+    pub fn append_missing_to(&self, path: &Path) -> io::Result<Vec<String>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let existing_keys = Self::from_contents(&contents);
+
+        let missing_keys: Vec<_> = self
+            .keys
+            .iter()
+            .filter(|(name, _)| !existing_keys.contains(name))
+            .collect();
+        if missing_keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut new_contents = contents;
+        if !new_contents.is_empty() && !new_contents.ends_with('\n') {
+            new_contents.push('\n');
+        }
+        for (name, key) in &missing_keys {
+            let hex_key: String = key.iter().map(|byte| format!("{byte:02X}")).collect();
+            new_contents.push_str(&format!("{name}={hex_key}\n"));
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, new_contents)?;
+
+        Ok(missing_keys
+            .into_iter()
+            .map(|(name, _)| name.clone())
+            .collect())
+    }
+}
+
+fn parse_hex_key(value: &str) -> Option<AesKey> {
+    if value.len() != 32 || !value.is_ascii() {
+        return None;
+    }
+
+    let mut key = [0u8; 16];
+    for (byte, hex) in key.iter_mut().zip(value.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?;
+    }
+    Some(key)
+}
+
+/*
+ * Titles released after system version 9.6 may use seed crypto, where the KeyY of the
+ * secondary key is derived from the NCCH KeyY and a per-title seed.
+ * Seeds are taken from a seeddb.bin, with a 0x10 bytes header (the seed count)
+ * followed by 0x20 bytes entries (title ID, seed and padding),
+ * or from a seeds directory with a `<title ID>.bin` file per seed.
+ *
+ * See https://www.3dbrew.org/wiki/Seed for more info
+*/
+
+#[derive(Debug, Default)]
+pub struct SeedDatabase {
+    seeds: HashMap<u64, Seed>,
+    seeds_dir: Option<PathBuf>,
+}
+
+impl SeedDatabase {
+    const HEADER_SIZE: usize = 0x10;
+    const ENTRY_SIZE: usize = 0x20;
+
+    /// Seeds are looked up on `~/.3ds/seeddb.bin` and `~/.3ds/seeds/`, both optional
+    pub fn from_default_paths() -> Result<Self, N3DSCryptoError> {
+        let config_dir = config_dir();
+
+        let mut seed_database = match fs::read(config_dir.join("seeddb.bin")) {
+            Ok(seeddb_bytes) => Self::from_seeddb(&seeddb_bytes)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Self::default(),
+            Err(err) => return Err(N3DSCryptoError::InvalidSeedDatabase(err.to_string())),
+        };
+        seed_database.seeds_dir = Some(config_dir.join("seeds"));
+        Ok(seed_database)
+    }
+
+    pub fn from_seeddb(seeddb_bytes: &[u8]) -> Result<Self, N3DSCryptoError> {
+        let Some(seed_count) = seeddb_bytes.get(..4) else {
+            return Err(N3DSCryptoError::InvalidSeedDatabase(
+                "missing header".to_string(),
+            ));
+        };
+        let seed_count = u32::from_le_bytes(seed_count.try_into().unwrap());
+
+        let entries_size = usize::try_from(seed_count).unwrap_or(usize::MAX) * Self::ENTRY_SIZE;
+        let Some(entries) = seeddb_bytes
+            .get(Self::HEADER_SIZE..)
+            .and_then(|entries| entries.get(..entries_size))
+        else {
+            return Err(N3DSCryptoError::InvalidSeedDatabase(format!(
+                "truncated, expected {seed_count} seeds"
+            )));
+        };
+
+        let seeds = entries
+            .chunks_exact(Self::ENTRY_SIZE)
+            .map(|entry| {
+                let title_id = u64::from_le_bytes(entry[..8].try_into().unwrap());
+                let seed: Seed = entry[8..8 + 16].try_into().unwrap();
+                (title_id, seed)
+            })
+            .collect();
+
+        Ok(SeedDatabase {
+            seeds,
+            seeds_dir: None,
+        })
+    }
+
+    pub fn seed(&self, title_id: TitleId) -> Option<Seed> {
+        if let Some(seed) = self.seeds.get(&title_id.0) {
+            return Some(*seed);
+        }
+
+        let seed_path = self.seeds_dir.as_ref()?.join(format!("{title_id}.bin"));
+        fs::read(seed_path).ok()?.try_into().ok()
+    }
+}
+
+/// The 3DS key scrambler, keys are handled as big endian 128 bit integers
+pub fn scramble_key(key_x: &AesKey, key_y: &AesKey, generator: &AesKey) -> AesKey {
+    let key_x = u128::from_be_bytes(*key_x);
+    let key_y = u128::from_be_bytes(*key_y);
+    let generator = u128::from_be_bytes(*generator);
+
+    let normal_key = (key_x.rotate_left(2) ^ key_y)
+        .wrapping_add(generator)
+        .rotate_left(87);
+    normal_key.to_be_bytes()
+}
+
+/// AES-128-CTR with a big endian 128 bit counter, as used by NCCH contents
+#[derive(Debug, Clone)]
+pub struct AesCtrCipher {
+    key: AesKey,
+    counter: [u8; 16],
+}
+
+impl AesCtrCipher {
+    pub fn new(key: AesKey, counter: [u8; 16]) -> Self {
+        Self { key, counter }
+    }
+
+    /// Decrypts (or encrypts) data located at `offset` bytes from where the counter starts
+    pub fn apply_keystream(&self, offset: u64, data: &mut [u8]) {
+        let mut cipher = ctr::Ctr128BE::<Aes128>::new(&self.key.into(), &self.counter.into());
+        cipher.seek(offset);
+        cipher.apply_keystream(data);
+    }
+}
+
+/// AES-128-CBC block by block decryption, as used by CIA contents and ticket title keys
+pub fn aes_cbc_decrypt(key: &AesKey, iv: &[u8; 16], data: &mut [u8]) {
+    let cipher = Aes128::new(key.into());
+    let mut previous_block = *iv;
+
+    for block in data.chunks_exact_mut(0x10) {
+        let ciphertext: [u8; 16] = (&*block).try_into().unwrap();
+        cipher.decrypt_block(block.into());
+        for (byte, previous_byte) in block.iter_mut().zip(previous_block) {
+            *byte ^= previous_byte;
+        }
+        previous_block = ciphertext;
+    }
+}
+
+/// Decrypts an AES-128-CBC encrypted section of the inner reader on the fly,
+/// with positions being relative to the start of the section.
+/// Random access is possible as each block only depends on the previous ciphertext block
+#[derive(Debug)]
+pub struct AesCbcReader<'a, T: Read + Seek> {
+    inner: &'a mut T,
+    key: AesKey,
+    iv: [u8; 16],
+    offset: u64,
+    size: u64,
+    position: u64,
+}
+
+impl<'a, T: Read + Seek> AesCbcReader<'a, T> {
+    pub fn new(inner: &'a mut T, key: AesKey, iv: [u8; 16], offset: u64, size: u64) -> Self {
+        Self {
+            inner,
+            key,
+            iv,
+            offset,
+            size,
+            position: 0,
+        }
+    }
+}
+
+impl<T: Read + Seek> Read for AesCbcReader<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        const BLOCK_SIZE: u64 = 0x10;
+
+        let remaining = self.size.saturating_sub(self.position);
+        let len =
+            usize::try_from(remaining).map_or(buf.len(), |remaining| remaining.min(buf.len()));
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let first_block = self.position / BLOCK_SIZE;
+        let end_position = self.position + len as u64;
+        let blocks_size = end_position.next_multiple_of(BLOCK_SIZE) - first_block * BLOCK_SIZE;
+
+        let iv = if first_block == 0 {
+            self.iv
+        } else {
+            let mut iv = [0u8; 16];
+            self.inner.seek(SeekFrom::Start(
+                self.offset + (first_block - 1) * BLOCK_SIZE,
+            ))?;
+            self.inner.read_exact(&mut iv)?;
+            iv
+        };
+
+        let mut blocks = vec![0u8; usize::try_from(blocks_size).unwrap()];
+        self.inner
+            .seek(SeekFrom::Start(self.offset + first_block * BLOCK_SIZE))?;
+        self.inner.read_exact(&mut blocks)?;
+        aes_cbc_decrypt(&self.key, &iv, &mut blocks);
+
+        let skip = usize::try_from(self.position % BLOCK_SIZE).unwrap();
+        buf[..len].copy_from_slice(&blocks[skip..skip + len]);
+        self.position = end_position;
+        Ok(len)
+    }
+}
+
+impl<T: Read + Seek> Seek for AesCbcReader<'_, T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(hex: &str) -> AesKey {
+        parse_hex_key(hex).unwrap()
+    }
+
+    #[test]
+    fn scramble_key_known_answer() {
+        assert_eq!(
+            scramble_key(
+                &key("000102030405060708090A0B0C0D0E0F"),
+                &key("F0E1D2C3B4A5968778695A4B3C2D1E0F"),
+                &key("0123456789ABCDEFFEDCBA9876543210"),
+            ),
+            key("7FC136AC21F904901B972EAE45AB9516")
+        );
+    }
+
+    #[test]
+    fn scramble_key_wraps_around() {
+        // ((KeyX ROL 2) XOR KeyY) + C overflows to zero, which stays zero after the rotation
+        assert_eq!(
+            scramble_key(&[0xFF; 16], &[0x00; 16], &(1u128.to_be_bytes())),
+            [0x00; 16]
+        );
+    }
+
+    #[test]
+    fn key_file_parsing() {
+        let key_store = KeyStore::from_contents(
+            "# comment=000102030405060708090A0B0C0D0E0F\n\
+             slot0x2CKeyX = 000102030405060708090a0b0c0d0e0f \n\
+             generator=0123456789ABCDEFFEDCBA9876543210\n\
+             slot0x25KeyX=000102030405060708090A0B0C0D0E\n\
+             slot0x18KeyX=000102030405060708090A0B0C0D0E0G\n\
+             slot0x1BKeyX=000102030405060708090A0B0C0D0E0F00\n\
+             common0\n\
+             nfcSecret=0102\n",
+        );
+
+        assert_eq!(
+            key_store.key_x(0x2C).unwrap(),
+            key("000102030405060708090A0B0C0D0E0F")
+        );
+        assert!(key_store.contains("GENERATOR"));
+        assert!(!key_store.contains("comment"));
+        for name in [
+            "slot0x25KeyX",
+            "slot0x18KeyX",
+            "slot0x1BKeyX",
+            "common0",
+            "nfcSecret",
+        ] {
+            assert!(!key_store.contains(name), "{name} should have been skipped");
+        }
+        assert!(matches!(
+            key_store.key_x(0x25),
+            Err(N3DSCryptoError::MissingKey(name)) if name == "slot0x25KeyX"
+        ));
+    }
+
+    #[test]
+    fn scrambled_key_needs_key_x_and_generator() {
+        let key_y = key("F0E1D2C3B4A5968778695A4B3C2D1E0F");
+
+        let key_store = KeyStore::from_contents(
+            "slot0x2CKeyX=000102030405060708090A0B0C0D0E0F\n\
+             generator=0123456789ABCDEFFEDCBA9876543210\n",
+        );
+        assert_eq!(
+            key_store.scrambled_key(0x2C, &key_y).unwrap(),
+            key("7FC136AC21F904901B972EAE45AB9516")
+        );
+        assert!(matches!(
+            key_store.scrambled_key(0x25, &key_y),
+            Err(N3DSCryptoError::MissingKey(_))
+        ));
+
+        let key_store = KeyStore::from_contents("slot0x2CKeyX=000102030405060708090A0B0C0D0E0F");
+        assert!(matches!(
+            key_store.scrambled_key(0x2C, &key_y),
+            Err(N3DSCryptoError::MissingKey(name)) if name == "generator"
+        ));
+    }
+
+    #[test]
+    fn missing_key_file() {
+        let path = Path::new("/nonexistent/aes_keys.txt");
+        assert!(matches!(
+            KeyStore::from_path(path),
+            Err(N3DSCryptoError::KeyFileNotFound(not_found)) if not_found == path
+        ));
+    }
+
+    #[test]
+    fn aes_ctr_keystream_is_seekable() {
+        let cipher = AesCtrCipher::new(
+            key("000102030405060708090A0B0C0D0E0F"),
+            key("0706050403020100FFFFFFFFFFFFFFFF"),
+        );
+        let plaintext: Vec<u8> = (0..0x60u8).collect();
+
+        let mut whole = plaintext.clone();
+        cipher.apply_keystream(0, &mut whole);
+        assert_ne!(whole, plaintext);
+
+        // Decrypting from an unaligned offset matches the same range of the whole stream,
+        // including the counter carrying into its upper half
+        let mut part = plaintext[0x25..0x47].to_vec();
+        cipher.apply_keystream(0x25, &mut part);
+        assert_eq!(part, whole[0x25..0x47]);
+
+        cipher.apply_keystream(0, &mut whole);
+        assert_eq!(whole, plaintext);
+    }
+
+    /// Deterministic bytes standing in for a randomly generated key, IV or plaintext
+    fn generated_bytes(len: usize, seed: u8) -> Vec<u8> {
+        let mut state = u32::from(seed) | 0x100;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn aes_cbc_encrypt(key: &AesKey, iv: &[u8; 16], data: &mut [u8]) {
+        use aes::cipher::BlockEncrypt;
+
+        let cipher = Aes128::new(key.into());
+        let mut previous_block = *iv;
+        for block in data.chunks_exact_mut(0x10) {
+            for (byte, previous_byte) in block.iter_mut().zip(previous_block) {
+                *byte ^= previous_byte;
+            }
+            cipher.encrypt_block(block.into());
+            previous_block = (&*block).try_into().unwrap();
+        }
+    }
+
+    #[test]
+    fn aes_cbc_reader_random_access() {
+        const SECTION_OFFSET: usize = 0x30;
+        const SECTION_SIZE: usize = 0x100;
+
+        let key: AesKey = generated_bytes(16, 1).try_into().unwrap();
+        let iv: [u8; 16] = generated_bytes(16, 2).try_into().unwrap();
+        let plaintext = generated_bytes(SECTION_SIZE, 3);
+
+        let mut ciphertext = plaintext.clone();
+        aes_cbc_encrypt(&key, &iv, &mut ciphertext);
+        let mut decrypted = ciphertext.clone();
+        aes_cbc_decrypt(&key, &iv, &mut decrypted);
+        assert_eq!(decrypted, plaintext);
+
+        // The section sits between unrelated data, which must never be returned
+        let mut file = vec![0xEE; SECTION_OFFSET];
+        file.extend_from_slice(&ciphertext);
+        file.extend_from_slice(&[0xEE; 0x20]);
+        let mut inner = io::Cursor::new(file);
+        let mut reader = AesCbcReader::new(
+            &mut inner,
+            key,
+            iv,
+            SECTION_OFFSET as u64,
+            SECTION_SIZE as u64,
+        );
+
+        // Unaligned reads within the first block, and across several block boundaries
+        for (position, len) in [(0x3, 0x5), (0xF, 0x2), (0x13, 0x25), (0x40, 0x10)] {
+            reader.seek(SeekFrom::Start(position as u64)).unwrap();
+            let mut buf = vec![0u8; len];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, plaintext[position..position + len]);
+            assert_eq!(reader.stream_position().unwrap(), (position + len) as u64);
+        }
+
+        // Reads are clamped to the end of the section
+        reader.seek(SeekFrom::End(-3)).unwrap();
+        let mut buf = [0u8; 0x10];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(buf[..3], plaintext[SECTION_SIZE - 3..]);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        reader
+            .seek(SeekFrom::Start(SECTION_SIZE as u64 + 0x10))
+            .unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+        let mut whole = Vec::new();
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        reader.read_to_end(&mut whole).unwrap();
+        assert_eq!(whole, plaintext);
+
+        assert!(reader.seek(SeekFrom::Current(-0x101)).is_err());
+    }
+
+    fn seeddb(seed_count: u32, entries: &[(u64, Seed)]) -> Vec<u8> {
+        let mut seeddb = vec![0u8; SeedDatabase::HEADER_SIZE];
+        seeddb[..4].copy_from_slice(&seed_count.to_le_bytes());
+        for (title_id, seed) in entries {
+            seeddb.extend_from_slice(&title_id.to_le_bytes());
+            seeddb.extend_from_slice(seed);
+            seeddb.extend_from_slice(&[0u8; 8]);
+        }
+        seeddb
+    }
+
+    #[test]
+    fn seed_database_parsing() {
+        let seed_database = SeedDatabase::from_seeddb(&seeddb(
+            2,
+            &[
+                (0x0004_0000_0012_3400, [0x11; 16]),
+                (0x0004_0000_0056_7800, [0x22; 16]),
+            ],
+        ))
+        .unwrap();
+        assert_eq!(
+            seed_database.seed(TitleId(0x0004_0000_0012_3400)),
+            Some([0x11; 16])
+        );
+        assert_eq!(
+            seed_database.seed(TitleId(0x0004_0000_0056_7800)),
+            Some([0x22; 16])
+        );
+        assert_eq!(seed_database.seed(TitleId(0x0004_0000_0099_9900)), None);
+    }
+
+    #[test]
+    fn invalid_seed_databases() {
+        let mut truncated = seeddb(1, &[(0x0004_0000_0012_3400, [0x11; 16])]);
+        truncated.pop();
+        let overstated_count = seeddb(3, &[(0x0004_0000_0012_3400, [0x11; 16])]);
+
+        for seeddb_bytes in [&[0x01, 0x00][..], &truncated, &overstated_count] {
+            assert!(matches!(
+                SeedDatabase::from_seeddb(seeddb_bytes),
+                Err(N3DSCryptoError::InvalidSeedDatabase(_))
+            ));
+        }
+    }
+}
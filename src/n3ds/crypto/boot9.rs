@@ -0,0 +1,156 @@
+use crate::n3ds::errors::N3DSCryptoError;
+
+use super::{AesKey, KeyStore};
+
+/*
+ * The ARM9 bootrom (boot9.bin) initializes most keyslots from a key area on its protected half.
+ * The retail key area is a list of 16 bytes keys, in the order below, where a key
+ * marked as shared reuses the previous key instead of being stored again.
+ *
+ * This is the same layout used by Citra and GodMode9, note that the NCCH secondary KeyX values
+ * and the CIA common key KeyY values aren't part of the bootrom.
+ *
+ * See https://www.3dbrew.org/wiki/Bootloader for more info
+*/
+
+#[derive(Debug, Clone, Copy)]
+enum Boot9KeyType {
+    X,
+    Y,
+    Normal,
+}
+
+const BOOT9_FULL_SIZE: usize = 0x10000;
+const BOOT9_PROTECTED_SIZE: usize = 0x8000;
+const BOOT9_PROTECTED_KEY_AREA_OFFSET: usize = 0x59D0;
+
+#[rustfmt::skip]
+const BOOT9_KEYS: [(Boot9KeyType, u8, bool); 80] = {
+    use Boot9KeyType::{Normal as N, X, Y};
+    [
+        (X, 0x2C, false), (X, 0x2D, true), (X, 0x2E, true), (X, 0x2F, true),
+        (X, 0x30, false), (X, 0x31, true), (X, 0x32, true), (X, 0x33, true),
+        (X, 0x34, false), (X, 0x35, true), (X, 0x36, true), (X, 0x37, true),
+        (X, 0x38, false), (X, 0x39, true), (X, 0x3A, true), (X, 0x3B, true),
+        (X, 0x3C, false), (X, 0x3D, false), (X, 0x3E, false), (X, 0x3F, false),
+        (Y, 0x04, false), (Y, 0x05, false), (Y, 0x06, false), (Y, 0x07, false),
+        (Y, 0x08, false), (Y, 0x09, false), (Y, 0x0A, false), (Y, 0x0B, false),
+        (N, 0x0C, false), (N, 0x0D, true), (N, 0x0E, true), (N, 0x0F, true),
+        (N, 0x10, false), (N, 0x11, true), (N, 0x12, true), (N, 0x13, true),
+        (N, 0x14, false), (N, 0x15, false), (N, 0x16, false), (N, 0x17, false),
+        (N, 0x18, false), (N, 0x19, true), (N, 0x1A, true), (N, 0x1B, true),
+        (N, 0x1C, false), (N, 0x1D, true), (N, 0x1E, true), (N, 0x1F, true),
+        (N, 0x20, false), (N, 0x21, true), (N, 0x22, true), (N, 0x23, true),
+        (N, 0x24, false), (N, 0x25, true), (N, 0x26, true), (N, 0x27, true),
+        (N, 0x28, true), (N, 0x29, false), (N, 0x2A, true), (N, 0x2B, true),
+        (N, 0x2C, false), (N, 0x2D, true), (N, 0x2E, true), (N, 0x2F, true),
+        (N, 0x30, false), (N, 0x31, true), (N, 0x32, true), (N, 0x33, true),
+        (N, 0x34, false), (N, 0x35, true), (N, 0x36, true), (N, 0x37, true),
+        (N, 0x38, false), (N, 0x39, true), (N, 0x3A, true), (N, 0x3B, true),
+        (N, 0x3C, true), (N, 0x3D, false), (N, 0x3E, true), (N, 0x3F, true),
+    ]
+};
+
+impl KeyStore {
+    /// Derives the bootrom keys from either a full boot9.bin dump or its protected half
+    pub fn from_boot9(boot9: &[u8]) -> Result<Self, N3DSCryptoError> {
+        let key_area_offset = match boot9.len() {
+            BOOT9_FULL_SIZE => BOOT9_PROTECTED_SIZE + BOOT9_PROTECTED_KEY_AREA_OFFSET,
+            BOOT9_PROTECTED_SIZE => BOOT9_PROTECTED_KEY_AREA_OFFSET,
+            size => return Err(N3DSCryptoError::InvalidBoot9Size(size)),
+        };
+
+        let mut key_store = KeyStore::default();
+        let mut key_chunks = boot9[key_area_offset..].chunks_exact(0x10);
+        let mut key: AesKey = [0u8; 16];
+
+        for (key_type, slot, is_shared) in BOOT9_KEYS {
+            if !is_shared {
+                // this unwrap will never fail: the key area is well within the bootrom
+                key = key_chunks.next().unwrap().try_into().unwrap();
+            }
+
+            let key_name = match key_type {
+                Boot9KeyType::X => format!("slot0x{slot:02X}KeyX"),
+                Boot9KeyType::Y => format!("slot0x{slot:02X}KeyY"),
+                Boot9KeyType::Normal => format!("slot0x{slot:02X}KeyN"),
+            };
+            key_store.keys.insert(key_name, key);
+        }
+
+        Ok(key_store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a boot9.bin whose key area chunks are filled with their own index,
+    /// so each derived key tells which chunk it was read from
+    fn synthetic_boot9(size: usize, key_area_offset: usize) -> Vec<u8> {
+        let mut boot9 = vec![0xEE; size];
+        for (index, chunk) in boot9[key_area_offset..].chunks_exact_mut(0x10).enumerate() {
+            chunk.fill(index as u8);
+        }
+        boot9
+    }
+
+    fn chunk_of(key_store: &KeyStore, name: &str) -> u8 {
+        let key = key_store.key(name).unwrap();
+        assert!(
+            key.iter().all(|byte| *byte == key[0]),
+            "{name} is not a whole chunk"
+        );
+        key[0]
+    }
+
+    #[test]
+    fn key_area_layout() {
+        let boot9 = synthetic_boot9(
+            BOOT9_FULL_SIZE,
+            BOOT9_PROTECTED_SIZE + BOOT9_PROTECTED_KEY_AREA_OFFSET,
+        );
+        let key_store = KeyStore::from_boot9(&boot9).unwrap();
+
+        assert_eq!(chunk_of(&key_store, "slot0x2CKeyX"), 0);
+        // Shared keys reuse the previous key
+        assert_eq!(chunk_of(&key_store, "slot0x2DKeyX"), 0);
+        assert_eq!(chunk_of(&key_store, "slot0x30KeyX"), 1);
+        assert_eq!(chunk_of(&key_store, "slot0x3DKeyX"), 5);
+        assert_eq!(chunk_of(&key_store, "slot0x04KeyY"), 8);
+        assert_eq!(chunk_of(&key_store, "slot0x0CKeyN"), 16);
+        assert_eq!(chunk_of(&key_store, "slot0x25KeyN"), 25);
+        assert_eq!(chunk_of(&key_store, "slot0x28KeyN"), 25);
+        assert_eq!(chunk_of(&key_store, "slot0x29KeyN"), 26);
+        assert_eq!(chunk_of(&key_store, "slot0x3CKeyN"), 30);
+        assert_eq!(chunk_of(&key_store, "slot0x3DKeyN"), 31);
+        assert_eq!(chunk_of(&key_store, "slot0x3FKeyN"), 31);
+
+        assert_eq!(key_store.keys.len(), BOOT9_KEYS.len());
+    }
+
+    #[test]
+    fn protected_half_matches_full_dump() {
+        let full = KeyStore::from_boot9(&synthetic_boot9(
+            BOOT9_FULL_SIZE,
+            BOOT9_PROTECTED_SIZE + BOOT9_PROTECTED_KEY_AREA_OFFSET,
+        ))
+        .unwrap();
+        let protected = KeyStore::from_boot9(&synthetic_boot9(
+            BOOT9_PROTECTED_SIZE,
+            BOOT9_PROTECTED_KEY_AREA_OFFSET,
+        ))
+        .unwrap();
+
+        assert_eq!(full.keys, protected.keys);
+    }
+
+    #[test]
+    fn rejects_unexpected_sizes() {
+        assert!(matches!(
+            KeyStore::from_boot9(&[0; 0x1234]),
+            Err(N3DSCryptoError::InvalidBoot9Size(0x1234))
+        ));
+    }
+}
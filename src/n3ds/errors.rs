@@ -1,19 +1,60 @@
+use std::path::PathBuf;
+
 use thiserror::Error;
 
+use crate::n3ds::structures::{ThemeDrawType, TitleId};
+use crate::nds::errors::NDSParsingError;
+use crate::utils::compression::DecompressionError;
+
 #[derive(Error, Debug)]
 pub enum N3DSParsingError {
     #[error("{0} magic not found! Found {1:X?}")]
     FileMagicNotFound(&'static str, [u8; 4]),
     #[error("No extended header on 3DSX file. Found header size is {0}")]
     N3DSXParsingError3DSXNoExtendedHeader(u16),
+    #[error("No SMDH embedded on 3DSX file.")]
+    N3DSXParsingError3DSXNoEmbeddedSmdh,
     #[error(transparent)]
     CXIParsingError(#[from] CXIParsingError),
     #[error(transparent)]
     CIAParsingError(#[from] CIAParsingError),
     #[error(transparent)]
+    CryptoError(#[from] N3DSCryptoError),
+    #[error("Error parsing banner: {0}")]
+    CBMDParsingError(#[from] CBMDParsingError),
+    #[error("Error parsing theme: {0}")]
+    ThemeParsingError(#[from] ThemeParsingError),
+    #[error("Error parsing texture: {0}")]
+    TextureParsingError(#[from] TextureParsingError),
+    #[error("Error parsing Universal-Updater store: {0}")]
+    UnistoreParsingError(#[from] UnistoreParsingError),
+    #[error("Title {0} ({kind}) has no icon of its own, it only works on top of its base title.", kind = .0.kind())]
+    TitleHasNoIcon(TitleId),
+    #[error("No base title sharing the unique ID of {0} was found in the same directory.")]
+    BaseTitleNotFound(TitleId),
+    #[error("Error parsing DSiWare content: {0}")]
+    TwlParsingError(#[from] NDSParsingError),
+    #[error(transparent)]
     IoError(#[from] std::io::Error),
 }
 
+impl N3DSParsingError {
+    /// Whether the error means there's no icon at all, rather than it failing to be read,
+    /// which is expected for update and DLC titles
+    pub fn is_missing_icon(&self) -> bool {
+        matches!(
+            self,
+            N3DSParsingError::CXIParsingError(
+                CXIParsingError::NoCXIContent
+                    | CXIParsingError::NoExeFS
+                    | CXIParsingError::ExeFSFileNotFound("icon")
+            ) | N3DSParsingError::CIAParsingError(CIAParsingError::NoIconAvailable(
+                CXIParsingError::NoCXIContent
+            ))
+        )
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum CIAParsingError {
     #[error("CIA Meta block size is invalid. Found {0:#X}")]
@@ -22,18 +63,106 @@ pub enum CIAParsingError {
     NoIconAvailable(#[from] CXIParsingError),
     #[error("CIA Title Metadata contains no signature or a invalid value. Found {0:#X}")]
     SignatureTypeInvalidValue(u32),
-    #[error("CIA Content Index contains invalid value. Found {0}")]
-    ContentIndexInvalidValue(u16),
+    #[error("CIA Certificate contains an invalid key type. Found {0:#X}")]
+    CertificateKeyTypeInvalidValue(u32),
+    #[error("Content is encrypted but no ticket is available to decrypt it.")]
+    TicketNotAvailable,
+    #[error("No title metadata (tmd) found on CDN directory.")]
+    CdnTitleMetadataNotFound,
+    #[error("Content {0:08x} not found on CDN directory.")]
+    CdnContentNotFound(u32),
 }
 
 #[derive(Error, Debug)]
 pub enum CXIParsingError {
     #[error("No CXI found.")]
     NoCXIContent,
-    #[error("CXI file (usually internal to a CCI or CIA) is encrypted, consider using decrypted files instead.")]
-    FileEncrypted,
+    #[error("NCCH has no ExeFS.")]
+    NoExeFS,
     #[error("Invalid NCCH Crypto Method Flags. Found: {0:#X}")]
     InvalidNCCHCryptoMethodFlags(u8),
-    #[error("Error finding icon file inside ExeFS!")]
-    ExeFSIconFileNotFound,
+    #[error("Error finding {0} file inside ExeFS!")]
+    ExeFSFileNotFound(&'static str),
+    #[error("ExeFS {0} file goes past the end of the ExeFS.")]
+    ExeFSFileOutOfBounds(&'static str),
+}
+
+#[derive(Error, Debug)]
+pub enum CBMDParsingError {
+    #[error("Failed to decompress the CGFX model: {0}")]
+    DecompressionError(#[from] DecompressionError),
+    #[error("CGFX data is truncated, offset {0:#X} is out of bounds.")]
+    TruncatedCgfx(usize),
+    #[error("CGFX texture has an unsupported format. Found {0:#X}")]
+    UnsupportedTextureFormat(u32),
+    #[error("No decodable texture found in the banner.")]
+    NoTexturesFound,
+}
+
+#[derive(Error, Debug)]
+pub enum ThemeParsingError {
+    #[error("Failed to decompress the theme body: {0}")]
+    DecompressionError(#[from] DecompressionError),
+    #[error("Theme version is invalid. Found {0}")]
+    VersionInvalidValue(u32),
+    #[error("Theme screen draw type is invalid. Found {0}")]
+    DrawTypeInvalidValue(u32),
+    #[error("Theme top screen has no texture, it's drawn as {0:?}.")]
+    TopScreenHasNoTexture(ThemeDrawType),
+    #[error("Theme texture at {0:#X} is out of bounds.")]
+    TextureOutOfBounds(u32),
+    #[error("No body_LZ.bin found in the zip file, it's not a theme.")]
+    NoThemeInZip,
+    #[error("Zip entry {0} is too large for a theme.")]
+    ZipEntryTooLarge(String),
+    #[error(transparent)]
+    ZipError(#[from] zip::result::ZipError),
+}
+
+#[derive(Error, Debug)]
+pub enum TextureParsingError {
+    #[error("{0} byte order mark is invalid, only little endian (3DS) files are supported. Found {1:#X}")]
+    ByteOrderMarkInvalidValue(&'static str, u16),
+    #[error("{0} block is truncated, offset {1:#X} is out of bounds.")]
+    TruncatedBlock(&'static str, usize),
+    #[error("Texture has an unsupported format. Found {0:#X}")]
+    UnsupportedTextureFormat(u32),
+    #[error("Texture swizzle mode is invalid. Found {0:#X}")]
+    SwizzleInvalidValue(u8),
+    #[error("Texture of {0}x{1} doesn't fit in its {2:#X} bytes of data.")]
+    TextureTooLarge(usize, usize, usize),
+    #[error("Failed to decompress the texture data: {0}")]
+    DecompressionError(#[from] DecompressionError),
+    #[error("Sub-texture {0} not found, the texture has {1} sub-textures.")]
+    SubTextureNotFound(usize, usize),
+    #[error("Sub-texture {0} is out of the texture bounds.")]
+    SubTextureOutOfBounds(usize),
+    #[error("No decodable texture found in the texture package.")]
+    NoTexturesFound,
+}
+
+#[derive(Error, Debug)]
+pub enum UnistoreParsingError {
+    #[error("Store is not valid JSON: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Store has no icon sheet.")]
+    NoSheet,
+    #[error("Icon sheet {} of the store was not found next to it.", .0.display())]
+    SheetNotFound(PathBuf),
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum N3DSCryptoError {
+    #[error("File is encrypted but no AES key file was found at {}, consider providing one or using decrypted files instead.", .0.display())]
+    KeyFileNotFound(PathBuf),
+    #[error("File is encrypted but key {0} is missing from the AES key file.")]
+    MissingKey(String),
+    #[error("boot9.bin has an unexpected size of {0:#X} bytes, expected a full (0x10000) or protected (0x8000) dump.")]
+    InvalidBoot9Size(usize),
+    #[error("Seed database is invalid: {0}")]
+    InvalidSeedDatabase(String),
+    #[error("File uses seed crypto but no seed was found for title {0}, consider adding it to the seed database.")]
+    MissingSeed(TitleId),
+    #[error("Seed found for title {0} doesn't match the NCCH seed check hash.")]
+    SeedMismatch(TitleId),
 }
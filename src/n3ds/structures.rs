@@ -1,12 +1,36 @@
+mod cbmd;
 mod cci;
+mod cdn;
 mod cia;
+mod clim;
+mod ctpk;
 mod cxi;
+mod siblings;
+mod smdh;
+mod t3x;
+mod theme;
+mod title_id;
+mod unistore;
+
+pub use cbmd::Cbmd;
+pub use cdn::is_cdn_dir;
+pub use cia::{CIACertificate, CIAContainer, CIAIcon, CIATicket, CIATitleMetadata};
+pub use clim::LayoutImage;
+pub use ctpk::Ctpk;
+pub use cxi::NCCHHeader;
+pub use smdh::Smdh;
+pub use t3x::T3x;
+pub use theme::{Theme, ThemeDrawType};
+pub use title_id::{TitleCategory, TitleId, TitleKind};
+pub use unistore::Unistore;
 
 use image::{ImageBuffer, Rgba, RgbaImage};
+use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
 
 use crate::n3ds::errors::N3DSParsingError;
-use crate::utils::rgb888::{Rgb565, Rgb888};
+use crate::utils::pica::{decode_texture, PicaTextureFormat};
 
 /*
  * Intially SMDH, 3DSX and CIA files were supported.
@@ -39,80 +63,51 @@ use crate::utils::rgb888::{Rgb565, Rgb888};
 
 #[derive(Debug)]
 pub struct SMDHIcon {
+    pub small_icon: ImageBuffer<Rgba<u8>, Vec<u8>>,
     pub large_icon: ImageBuffer<Rgba<u8>, Vec<u8>>,
 }
 
 impl SMDHIcon {
-    fn generate_icon_from_bytes(large_icon_bytes: &[u8; 0x1200]) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-        /*
-         * The large 3DS icon is 48x48 px and divided in tiles according to Morton order
-         * Each color will usually be RGB565 although it's not the only supported color enconding
-         */
+    const SMALL_ICON_SIZE: usize = 24;
+    const LARGE_ICON_SIZE: usize = 48;
 
-        const LARGE_ICON_SIZE: usize = 48;
-        const LARGE_ICON_WIDTH: usize = LARGE_ICON_SIZE;
-        const LARGE_ICON_HEIGHT: usize = LARGE_ICON_SIZE;
+    pub fn from_bytes(small_icon_bytes: &[u8; 0x480], large_icon_bytes: &[u8; 0x1200]) -> Self {
+        Self {
+            small_icon: Self::generate_icon_from_bytes(small_icon_bytes, Self::SMALL_ICON_SIZE),
+            large_icon: Self::generate_icon_from_bytes(large_icon_bytes, Self::LARGE_ICON_SIZE),
+        }
+    }
 
-        let large_icon_data: [Rgb888; 0x1200 / 2] = large_icon_bytes
-            .chunks_exact(2)
-            .map(|chunk| {
-                let bytes: [u8; 2] = chunk.try_into().unwrap();
-                Rgb888::from(Rgb565::from(bytes))
-            })
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
+    /// Picks the icon that best fits the requested thumbnail size:
+    /// the hand-tuned small icon looks better than a downscaled large one
+    /// for sizes up to 24px, otherwise the large icon is used
+    pub fn best_icon_for_size(self, size: Option<u32>) -> RgbaImage {
+        match size {
+            Some(size) if size <= self.small_icon.width() => self.small_icon,
+            _ => self.large_icon,
+        }
+    }
 
+    fn generate_icon_from_bytes(
+        icon_bytes: &[u8],
+        icon_size: usize,
+    ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
         /*
-         * Due to the Morton order, the code for the coordinates of the pixels is oxided from
+         * The 3DS icons are 24x24 px (small) and 48x48 px (large), divided in tiles
+         * according to Morton order like any other PICA200 texture
+         * Each color will usually be RGB565 although it's not the only supported color enconding
+         *
+         * The Morton order code was originally oxided from
          * https://github.com/ihaveamac/pyctr/blob/master/pyctr/type/smdh.py
          * Many thanks to ihaveamac from the Nintendo Homebrew Discord for the help
          */
 
-        #[allow(clippy::cast_possible_truncation)]
-        let mut img = RgbaImage::new(LARGE_ICON_WIDTH as u32, LARGE_ICON_HEIGHT as u32);
-
-        for y in 0..LARGE_ICON_HEIGHT {
-            for x in 0..LARGE_ICON_WIDTH {
-                let pixel_offset = (((y >> 3) * (LARGE_ICON_WIDTH >> 3) + (x >> 3)) << 6)
-                    + ((x & 1)
-                        | ((y & 1) << 1)
-                        | ((x & 2) << 1)
-                        | ((y & 2) << 2)
-                        | ((x & 4) << 2)
-                        | ((y & 4) << 3));
-
-                let pixel = &large_icon_data[pixel_offset];
-                #[allow(clippy::cast_possible_truncation)]
-                img.put_pixel(x as u32, y as u32, Rgba([pixel.r, pixel.g, pixel.b, 0xFF]));
-            }
-        }
-
-        img
+        // this unwrap will never fail: both icon sizes are multiples of 8 and the input is sized
+        decode_texture(icon_bytes, icon_size, icon_size, PicaTextureFormat::Rgb565).unwrap()
     }
 }
 
-impl SMDHIcon {
-    pub fn from_smdh<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
-        const SMDH_LARGE_ICON_OFFSET: u64 = 0x24C0;
-        const SMDH_LARGE_ICON_SIZE: usize = 0x1200;
-
-        let smdh_start_pos = f.stream_position()?;
-
-        let mut smdh_magic = [0u8; 4];
-        f.read_exact(&mut smdh_magic)?;
-        if b"SMDH" != &smdh_magic {
-            return Err(N3DSParsingError::FileMagicNotFound("SMDH", smdh_magic));
-        }
-
-        f.seek(SeekFrom::Start(smdh_start_pos + SMDH_LARGE_ICON_OFFSET))?;
-        let mut large_icon_bytes = [0u8; SMDH_LARGE_ICON_SIZE];
-        f.read_exact(&mut large_icon_bytes)?;
-        Ok(Self {
-            large_icon: Self::generate_icon_from_bytes(&large_icon_bytes),
-        })
-    }
-
+impl Smdh {
     pub fn from_n3dsx<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
         const N3DSX_EXTENDED_HEADER_OFFSET: u64 = 0x20;
 
@@ -138,9 +133,153 @@ impl SMDHIcon {
 
         let mut smdh_size = [0u8; 4];
         f.read_exact(&mut smdh_size)?;
-        let _smdh_size = u32::from_le_bytes(smdh_size);
+        let smdh_size = u32::from_le_bytes(smdh_size);
+        // The extended header may only be there for the RomFS
+        if smdh_offset == 0 || smdh_size == 0 {
+            return Err(N3DSParsingError::N3DSXParsingError3DSXNoEmbeddedSmdh);
+        }
 
         f.seek(SeekFrom::Start(smdh_offset.into()))?;
         Self::from_smdh(f)
     }
+
+    /// Old homebrew ships the SMDH next to the .3dsx instead of embedding it,
+    /// in which case the same lookup as the Homebrew Launcher is used:
+    /// first <name>.smdh, then icon.smdh, both in the same directory as the .3dsx
+    pub fn from_n3dsx_with_sidecar<T: Read + Seek>(
+        f: &mut T,
+        path: &Path,
+    ) -> Result<Self, N3DSParsingError> {
+        match Self::from_n3dsx(f) {
+            Err(
+                err @ (N3DSParsingError::N3DSXParsingError3DSXNoExtendedHeader(_)
+                | N3DSParsingError::N3DSXParsingError3DSXNoEmbeddedSmdh),
+            ) => Self::from_n3dsx_sidecar(path).ok_or(err),
+            result => result,
+        }
+    }
+
+    fn from_n3dsx_sidecar(path: &Path) -> Option<Self> {
+        [
+            path.with_extension("smdh"),
+            path.with_file_name("icon.smdh"),
+        ]
+        .into_iter()
+        .find_map(|sidecar_path| {
+            let mut sidecar = File::open(&sidecar_path).ok()?;
+            match Self::from_smdh(&mut sidecar) {
+                Ok(smdh) => {
+                    eprintln!("Using sidecar SMDH {}", sidecar_path.display());
+                    Some(smdh)
+                }
+                Err(err) => {
+                    eprintln!("Ignoring sidecar SMDH {}: {err}", sidecar_path.display());
+                    None
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    fn synthetic_smdh(match_maker_id: u32) -> Vec<u8> {
+        let mut smdh = vec![0u8; Smdh::SIZE];
+        smdh[..4].copy_from_slice(b"SMDH");
+        smdh[0x201C..0x2020].copy_from_slice(&match_maker_id.to_le_bytes());
+        smdh
+    }
+
+    /// Builds a 3DSX with an extended header when `smdh` is given,
+    /// the SMDH being embedded when it isn't empty
+    fn synthetic_n3dsx(smdh: Option<&[u8]>) -> Vec<u8> {
+        let mut n3dsx = vec![0u8; 0x20];
+        n3dsx[..4].copy_from_slice(b"3DSX");
+        let Some(smdh) = smdh else {
+            n3dsx[4..6].copy_from_slice(&0x20u16.to_le_bytes());
+            return n3dsx;
+        };
+
+        n3dsx[4..6].copy_from_slice(&0x2Cu16.to_le_bytes());
+        n3dsx.resize(0x2C, 0);
+        if !smdh.is_empty() {
+            n3dsx[0x20..0x24].copy_from_slice(&0x2Cu32.to_le_bytes());
+            n3dsx[0x24..0x28].copy_from_slice(&(smdh.len() as u32).to_le_bytes());
+            n3dsx.extend_from_slice(smdh);
+        }
+        n3dsx
+    }
+
+    /// A directory of its own for each test, as the sidecars are looked up next to the 3DSX
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bign-handheld-thumbnailer-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn embedded_smdh() {
+        let n3dsx = synthetic_n3dsx(Some(&synthetic_smdh(1)));
+        let smdh = Smdh::from_n3dsx(&mut Cursor::new(n3dsx)).unwrap();
+        assert_eq!(smdh.match_maker_id, 1);
+    }
+
+    #[test]
+    fn sidecar_without_extended_header() {
+        let dir = test_dir("no-extended-header");
+        let path = dir.join("app.3dsx");
+        std::fs::write(dir.join("app.smdh"), synthetic_smdh(2)).unwrap();
+        std::fs::write(dir.join("icon.smdh"), synthetic_smdh(3)).unwrap();
+
+        let n3dsx = synthetic_n3dsx(None);
+        let smdh = Smdh::from_n3dsx_with_sidecar(&mut Cursor::new(n3dsx), &path).unwrap();
+        assert_eq!(smdh.match_maker_id, 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sidecar_without_embedded_smdh() {
+        let dir = test_dir("no-embedded-smdh");
+        let path = dir.join("app.3dsx");
+        let n3dsx = synthetic_n3dsx(Some(&[]));
+        assert!(matches!(
+            Smdh::from_n3dsx_with_sidecar(&mut Cursor::new(&n3dsx), &path),
+            Err(N3DSParsingError::N3DSXParsingError3DSXNoEmbeddedSmdh)
+        ));
+
+        std::fs::write(dir.join("icon.smdh"), synthetic_smdh(3)).unwrap();
+        let smdh = Smdh::from_n3dsx_with_sidecar(&mut Cursor::new(&n3dsx), &path).unwrap();
+        assert_eq!(smdh.match_maker_id, 3);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_sidecar_is_ignored() {
+        let dir = test_dir("invalid-sidecar");
+        let path = dir.join("app.3dsx");
+        let mut invalid_smdh = synthetic_smdh(2);
+        invalid_smdh[..4].copy_from_slice(b"HDMS");
+        std::fs::write(dir.join("app.smdh"), invalid_smdh).unwrap();
+
+        let n3dsx = synthetic_n3dsx(None);
+        assert!(matches!(
+            Smdh::from_n3dsx_with_sidecar(&mut Cursor::new(&n3dsx), &path),
+            Err(N3DSParsingError::N3DSXParsingError3DSXNoExtendedHeader(
+                0x20
+            ))
+        ));
+
+        std::fs::write(dir.join("icon.smdh"), synthetic_smdh(3)).unwrap();
+        let smdh = Smdh::from_n3dsx_with_sidecar(&mut Cursor::new(&n3dsx), &path).unwrap();
+        assert_eq!(smdh.match_maker_id, 3);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,80 @@
+mod cgfx;
+
+use image::RgbaImage;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+pub use cgfx::CGFXTexture;
+
+use crate::n3ds::errors::{CBMDParsingError, N3DSParsingError};
+use crate::utils::compression::lz11_decompress;
+
+/*
+ * The CBMD is the banner shown on the top screen of the HOME Menu,
+ * stored as the ExeFS banner file next to the icon.
+ * It holds a LZ11 compressed CGFX model shared by all regions,
+ * optional region specific CGFX models and the banner sound (BCWAV).
+ *
+ * The banner is an animated 3D model, so the thumbnail uses its largest texture instead,
+ * which is usually the flat artwork mapped on the model.
+ *
+ * See https://www.3dbrew.org/wiki/CBMD for more info
+*/
+
+#[derive(Debug)]
+pub struct Cbmd {
+    pub textures: Vec<CGFXTexture>,
+}
+
+impl Cbmd {
+    pub fn from_cbmd<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        const CBMD_COMMON_CGFX_OFFSET: u64 = 0x08;
+        const CBMD_CWAV_OFFSET: u64 = 0x84;
+        // Banner models with their textures take a few hundred KiB
+        const MAX_CGFX_SIZE: usize = 0x40_0000;
+
+        let cbmd_start_pos = f.stream_position()?;
+
+        let mut cbmd_magic = [0u8; 4];
+        f.read_exact(&mut cbmd_magic)?;
+        if b"CBMD" != &cbmd_magic {
+            return Err(N3DSParsingError::FileMagicNotFound("CBMD", cbmd_magic));
+        }
+
+        f.seek(SeekFrom::Start(cbmd_start_pos + CBMD_COMMON_CGFX_OFFSET))?;
+        let mut common_cgfx_offset = [0u8; 4];
+        f.read_exact(&mut common_cgfx_offset)?;
+        let common_cgfx_offset: u64 = u32::from_le_bytes(common_cgfx_offset).into();
+
+        f.seek(SeekFrom::Start(cbmd_start_pos + CBMD_CWAV_OFFSET))?;
+        let mut cwav_offset = [0u8; 4];
+        f.read_exact(&mut cwav_offset)?;
+        let cwav_offset: u64 = u32::from_le_bytes(cwav_offset).into();
+
+        // The compressed CGFX is followed by the region specific ones or the BCWAV,
+        // the decompressed size in the LZ11 header tells where it actually ends
+        f.seek(SeekFrom::Start(cbmd_start_pos + common_cgfx_offset))?;
+        let compressed_cgfx_size = if cwav_offset > common_cgfx_offset {
+            cwav_offset - common_cgfx_offset
+        } else {
+            u64::MAX
+        };
+
+        let mut compressed_cgfx = BufReader::new(f.take(compressed_cgfx_size));
+        let cgfx =
+            lz11_decompress(&mut compressed_cgfx, MAX_CGFX_SIZE).map_err(CBMDParsingError::from)?;
+        Ok(Cbmd {
+            textures: cgfx::textures_from_cgfx(&cgfx)?,
+        })
+    }
+
+    /// Decodes the largest texture of the banner
+    pub fn banner(&self) -> Result<RgbaImage, CBMDParsingError> {
+        let mut textures = self.textures.iter().collect::<Vec<_>>();
+        textures.sort_by_key(|texture| std::cmp::Reverse(texture.width * texture.height));
+
+        textures
+            .into_iter()
+            .find_map(CGFXTexture::decode)
+            .ok_or(CBMDParsingError::NoTexturesFound)
+    }
+}
@@ -0,0 +1,139 @@
+use image::{imageops, RgbaImage};
+
+use crate::n3ds::errors::{CBMDParsingError, N3DSParsingError};
+use crate::utils::pica::{decode_texture, PicaTextureFormat};
+
+/*
+ * CGFX is the 3D model container used by banners, made of a header followed by a DATA block.
+ * The DATA block starts with 16 (count, offset) pairs pointing to dictionaries (DICT)
+ * of each kind of object, the second one being textures (TXOB).
+ * Offsets inside CGFX files are relative to the position they are read from.
+ *
+ * See https://www.3dbrew.org/wiki/CGFX for more info
+*/
+
+const CGFX_HEADER_SIZE_OFFSET: usize = 0x6;
+const DATA_TEXTURES_DICT_OFFSET: usize = 0x8 + 0x8;
+const DICT_ENTRY_COUNT_OFFSET: usize = 0x8;
+const DICT_ENTRIES_OFFSET: usize = 0x1C;
+const DICT_ENTRY_SIZE: usize = 0x10;
+const TXOB_IMAGE_TEXTURE_TYPE: u32 = 0x2000_0011;
+
+#[derive(Debug)]
+pub struct CGFXTexture {
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    pub format: PicaTextureFormat,
+    pub data: Vec<u8>,
+}
+
+impl CGFXTexture {
+    /// CGFX textures are stored upside down, following OpenGL conventions
+    pub fn decode(&self) -> Option<RgbaImage> {
+        let mut img = decode_texture(&self.data, self.width, self.height, self.format)?;
+        imageops::flip_vertical_in_place(&mut img);
+        Some(img)
+    }
+}
+
+struct CGFXReader<'a> {
+    data: &'a [u8],
+}
+
+impl CGFXReader<'_> {
+    fn bytes_at(&self, offset: usize, len: usize) -> Result<&[u8], CBMDParsingError> {
+        self.data
+            .get(offset..offset + len)
+            .ok_or(CBMDParsingError::TruncatedCgfx(offset))
+    }
+
+    fn u32_at(&self, offset: usize) -> Result<u32, CBMDParsingError> {
+        Ok(u32::from_le_bytes(
+            self.bytes_at(offset, 4)?.try_into().unwrap(),
+        ))
+    }
+
+    fn relative_offset_at(&self, offset: usize) -> Result<usize, CBMDParsingError> {
+        Ok(offset + self.u32_at(offset)? as usize)
+    }
+
+    fn check_magic(&self, offset: usize, magic: &'static str) -> Result<(), N3DSParsingError> {
+        let found: [u8; 4] = self.bytes_at(offset, 4)?.try_into().unwrap();
+        if magic.as_bytes() != found {
+            return Err(N3DSParsingError::FileMagicNotFound(magic, found));
+        }
+        Ok(())
+    }
+
+    fn string_at(&self, offset: usize) -> Result<String, CBMDParsingError> {
+        let bytes = self
+            .data
+            .get(offset..)
+            .ok_or(CBMDParsingError::TruncatedCgfx(offset))?;
+        let len = bytes
+            .iter()
+            .position(|c| *c == b'\0')
+            .unwrap_or(bytes.len());
+        Ok(String::from_utf8_lossy(&bytes[..len]).to_string())
+    }
+
+    fn texture_at(&self, txob_offset: usize) -> Result<Option<CGFXTexture>, N3DSParsingError> {
+        // Cube and reference textures don't hold any image data
+        if self.u32_at(txob_offset)? != TXOB_IMAGE_TEXTURE_TYPE {
+            return Ok(None);
+        }
+        self.check_magic(txob_offset + 0x4, "TXOB")?;
+
+        let name = self.string_at(self.relative_offset_at(txob_offset + 0xC)?)?;
+        let height = self.u32_at(txob_offset + 0x18)? as usize;
+        let width = self.u32_at(txob_offset + 0x1C)? as usize;
+        let format = self.u32_at(txob_offset + 0x34)?;
+        let format = PicaTextureFormat::try_from(format)
+            .map_err(CBMDParsingError::UnsupportedTextureFormat)?;
+
+        let data_size = self.u32_at(txob_offset + 0x44)? as usize;
+        let data_offset = self.relative_offset_at(txob_offset + 0x48)?;
+        let data = self.bytes_at(data_offset, data_size)?.to_vec();
+
+        Ok(Some(CGFXTexture {
+            name,
+            width,
+            height,
+            format,
+            data,
+        }))
+    }
+}
+
+pub fn textures_from_cgfx(cgfx: &[u8]) -> Result<Vec<CGFXTexture>, N3DSParsingError> {
+    let reader = CGFXReader { data: cgfx };
+    reader.check_magic(0, "CGFX")?;
+
+    let header_size = u16::from_le_bytes(
+        reader
+            .bytes_at(CGFX_HEADER_SIZE_OFFSET, 2)?
+            .try_into()
+            .unwrap(),
+    );
+    let data_offset = usize::from(header_size);
+    reader.check_magic(data_offset, "DATA")?;
+
+    let texture_count = reader.u32_at(data_offset + DATA_TEXTURES_DICT_OFFSET)?;
+    if texture_count == 0 {
+        return Ok(Vec::new());
+    }
+
+    let dict_offset = reader.relative_offset_at(data_offset + DATA_TEXTURES_DICT_OFFSET + 4)?;
+    reader.check_magic(dict_offset, "DICT")?;
+    let entry_count = reader.u32_at(dict_offset + DICT_ENTRY_COUNT_OFFSET)? as usize;
+
+    (0..entry_count)
+        .map(|index| {
+            let entry_offset = dict_offset + DICT_ENTRIES_OFFSET + index * DICT_ENTRY_SIZE;
+            let txob_offset = reader.relative_offset_at(entry_offset + 0xC)?;
+            reader.texture_at(txob_offset)
+        })
+        .filter_map(Result::transpose)
+        .collect()
+}
@@ -1,6 +1,9 @@
 use std::io::{Read, Seek, SeekFrom};
 
-use crate::n3ds::{errors::N3DSParsingError, structures::SMDHIcon};
+use crate::n3ds::{
+    errors::N3DSParsingError,
+    structures::{Cbmd, NCCHHeader, Smdh},
+};
 
 #[derive(Debug, Clone, Copy)]
 pub struct CCIPartition {
@@ -22,35 +25,55 @@ impl CCIPartition {
     }
 }
 
-impl SMDHIcon {
+/// Seeks to the first partition of a CCI, which holds the main CXI
+fn seek_to_first_partition<T: Read + Seek>(f: &mut T) -> Result<(), N3DSParsingError> {
+    const CCI_HEADER_MAGIC_OFFSET: u64 = 0x100;
+    const CCI_HEADER_PARTITION_TABLE_OFFSET: u64 = 0x120;
+    const CCI_HEADER_PARTITION_TABLE_SIZE: usize = 0x40;
+    const CCI_MAGIC_STR: &str = "NCSD";
+
+    f.seek(SeekFrom::Start(CCI_HEADER_MAGIC_OFFSET))?;
+    let mut cci_magic = [0u8; 4];
+    f.read_exact(&mut cci_magic)?;
+    if CCI_MAGIC_STR.as_bytes() != cci_magic {
+        return Err(N3DSParsingError::FileMagicNotFound(
+            CCI_MAGIC_STR,
+            cci_magic,
+        ));
+    }
+    f.seek(SeekFrom::Start(CCI_HEADER_PARTITION_TABLE_OFFSET))?;
+    let mut partition_table = [0u8; CCI_HEADER_PARTITION_TABLE_SIZE];
+    f.read_exact(&mut partition_table)?;
+
+    let partition_table: [CCIPartition; CCI_HEADER_PARTITION_TABLE_SIZE / 8] = partition_table
+        .chunks_exact(8)
+        .map(|chunk| CCIPartition::from_bytes(chunk.try_into().unwrap()))
+        .collect::<Vec<_>>()
+        .try_into()
+        .unwrap();
+    let first_partition = &partition_table[0];
+
+    f.seek(SeekFrom::Start(first_partition.offset.into()))?;
+    Ok(())
+}
+
+impl Smdh {
+    pub fn from_cci<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        seek_to_first_partition(f)?;
+        Self::from_cxi(f)
+    }
+}
+
+impl Cbmd {
+    pub fn from_cci<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        seek_to_first_partition(f)?;
+        Self::from_cxi(f)
+    }
+}
+
+impl NCCHHeader {
     pub fn from_cci<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
-        const CCI_HEADER_MAGIC_OFFSET: u64 = 0x100;
-        const CCI_HEADER_PARTITION_TABLE_OFFSET: u64 = 0x120;
-        const CCI_HEADER_PARTITION_TABLE_SIZE: usize = 0x40;
-        const CCI_MAGIC_STR: &str = "NCSD";
-
-        f.seek(SeekFrom::Start(CCI_HEADER_MAGIC_OFFSET))?;
-        let mut cci_magic = [0u8; 4];
-        f.read_exact(&mut cci_magic)?;
-        if CCI_MAGIC_STR.as_bytes() != cci_magic {
-            return Err(N3DSParsingError::FileMagicNotFound(
-                CCI_MAGIC_STR,
-                cci_magic,
-            ));
-        }
-        f.seek(SeekFrom::Start(CCI_HEADER_PARTITION_TABLE_OFFSET))?;
-        let mut partition_table = [0u8; CCI_HEADER_PARTITION_TABLE_SIZE];
-        f.read_exact(&mut partition_table)?;
-
-        let partition_table: [CCIPartition; CCI_HEADER_PARTITION_TABLE_SIZE / 8] = partition_table
-            .chunks_exact(8)
-            .map(|chunk| CCIPartition::from_bytes(chunk.try_into().unwrap()))
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
-        let first_partition = &partition_table[0];
-
-        f.seek(SeekFrom::Start(first_partition.offset.into()))?;
+        seek_to_first_partition(f)?;
         Self::from_cxi(f)
     }
 }
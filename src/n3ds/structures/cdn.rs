@@ -0,0 +1,107 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use crate::n3ds::errors::{CIAParsingError, N3DSParsingError};
+use crate::nds::structures::NDSBannerChecksumMode;
+
+use super::cia::{CIAIcon, CIATicket, CIATitleMetadata};
+
+/*
+ * Titles downloaded from Nintendo's CDN (also known as NUS) are kept as a directory containing:
+ * tmd (or tmd.<version>) - the title metadata, same as the one inside a CIA
+ * cetk - the ticket followed by its certificate chain, optional
+ * <content ID> or <content ID>.app - each content, named after its ID in hex
+ *
+ * It's essentially an unpacked CIA without the Meta section,
+ * so the icon always comes from the main content.
+*/
+
+impl CIAIcon {
+    pub fn from_cdn(
+        dir: &Path,
+        checksum_mode: NDSBannerChecksumMode,
+    ) -> Result<Self, N3DSParsingError> {
+        let title_metadata = CIATitleMetadata::from_cdn(dir)?;
+        Self::from_cdn_main_content(dir, &title_metadata, checksum_mode)
+            .map_err(|err| title_metadata.explain_missing_icon(err))
+    }
+
+    fn from_cdn_main_content(
+        dir: &Path,
+        title_metadata: &CIATitleMetadata,
+        checksum_mode: NDSBannerChecksumMode,
+    ) -> Result<Self, N3DSParsingError> {
+        let main_content = title_metadata.main_content()?;
+
+        let content_path = cdn_content_path(dir, main_content.content_id)
+            .ok_or(CIAParsingError::CdnContentNotFound(main_content.content_id))?;
+
+        Self::from_content(
+            &mut File::open(content_path)?,
+            main_content,
+            0,
+            |_| Ok(CIATicket::from_cdn(dir)?.ok_or(CIAParsingError::TicketNotAvailable)?),
+            checksum_mode,
+        )
+    }
+}
+
+impl CIATitleMetadata {
+    pub fn from_cdn(dir: &Path) -> Result<Self, N3DSParsingError> {
+        let tmd_path = cdn_tmd_path(dir).ok_or(CIAParsingError::CdnTitleMetadataNotFound)?;
+        Self::from_file(&mut File::open(tmd_path)?)
+    }
+}
+
+impl CIATicket {
+    /// The ticket is optional on CDN directories, as unencrypted contents don't need one
+    pub fn from_cdn(dir: &Path) -> Result<Option<Self>, N3DSParsingError> {
+        let cetk_path = dir.join("cetk");
+        if !cetk_path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(Self::from_file(&mut File::open(cetk_path)?)?))
+    }
+}
+
+/// Checks whether a directory looks like a CDN title directory, without parsing anything
+pub fn is_cdn_dir(dir: &Path) -> bool {
+    cdn_tmd_path(dir).is_some()
+}
+
+/// When only versioned title metadata files are present, the latest version is used
+fn cdn_tmd_path(dir: &Path) -> Option<PathBuf> {
+    let tmd_path = dir.join("tmd");
+    if tmd_path.is_file() {
+        return Some(tmd_path);
+    }
+
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let file_name = entry.file_name();
+            let version = file_name
+                .to_str()?
+                .strip_prefix("tmd.")?
+                .parse::<u16>()
+                .ok()?;
+            Some((version, entry.path()))
+        })
+        .max_by_key(|(version, _)| *version)
+        .map(|(_, path)| path)
+}
+
+fn cdn_content_path(dir: &Path, content_id: u32) -> Option<PathBuf> {
+    [
+        format!("{content_id:08x}"),
+        format!("{content_id:08x}.app"),
+        format!("{content_id:08X}"),
+        format!("{content_id:08X}.app"),
+    ]
+    .into_iter()
+    .map(|file_name| dir.join(file_name))
+    .find(|path| path.is_file())
+}
@@ -1,9 +1,22 @@
+mod certificate;
+mod ticket;
+
 use bitflags::bitflags;
 use std::io::{Read, Seek, SeekFrom};
 
-use crate::n3ds::errors::{CIAParsingError, CXIParsingError, N3DSParsingError};
+pub use certificate::CIACertificate;
+pub use ticket::CIATicket;
+
+use crate::n3ds::{
+    crypto::{AesCbcReader, KeyStore},
+    errors::{CIAParsingError, CXIParsingError, N3DSParsingError},
+};
+use crate::nds::{
+    extract_nds_banner, is_twl_srl,
+    structures::{NDSBannerChecksumMode, NDSBannerDetails},
+};
 
-use super::SMDHIcon;
+use super::{Cbmd, Smdh, TitleId};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum CIAMetaSize {
@@ -28,7 +41,7 @@ impl TryFrom<u32> for CIAMetaSize {
 }
 
 #[derive(Debug)]
-enum CIASignatureType {
+pub enum CIASignatureType {
     Rsa4096Sha1,
     Rsa2048Sha1,
     EllipticCurveWithSHA1,
@@ -73,74 +86,177 @@ impl CIASignatureType {
     }
 }
 
+/// Signature block shared by the certificates, the ticket and the title metadata
 #[derive(Debug)]
-pub struct CIATitleMetadata {
-    content_chunk_records: Vec<CIAContentChunkRecord>,
+pub struct CIASignature {
+    pub signature_type: CIASignatureType,
+    pub data: Vec<u8>,
 }
 
-impl CIATitleMetadata {
+impl CIASignature {
     pub fn from_file<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
-        const TITLE_METADATA_HEADER_CONTENT_COUNT_OFFSET: u64 = 0x9E;
-        const CONTENT_CHUNK_RECORDS_OFFSET: u64 = 0x9C4;
-        const CONTENT_CHUNK_RECORD_SIZE: usize = 0x30;
-
-        let tmd_start_pos = f.stream_position()?;
-
         let mut signature_type = [0u8; 4];
         f.read_exact(&mut signature_type)?;
         let signature_type = u32::from_be_bytes(signature_type);
         let signature_type = CIASignatureType::try_from(signature_type)?;
 
-        let signature_full_size: u64 = (signature_type.size() + signature_type.padding_size())
-            .try_into()
-            .unwrap();
-        let header_position = tmd_start_pos + signature_full_size;
-
-        f.seek(SeekFrom::Start(
-            header_position + TITLE_METADATA_HEADER_CONTENT_COUNT_OFFSET,
+        let mut data = vec![0u8; signature_type.size()];
+        f.read_exact(&mut data)?;
+        f.seek(SeekFrom::Current(
+            signature_type.padding_size().try_into().unwrap(),
         ))?;
-        let mut content_count = [0u8; 2];
-        f.read_exact(&mut content_count)?;
-        let content_count = u16::from_be_bytes(content_count);
 
-        f.seek(SeekFrom::Start(
-            header_position + CONTENT_CHUNK_RECORDS_OFFSET,
-        ))?;
+        Ok(CIASignature {
+            signature_type,
+            data,
+        })
+    }
+}
+
+/// Reads a NUL padded string such as a signature issuer or a certificate name
+fn string_from_padded_bytes(bytes: &[u8]) -> String {
+    let len = bytes
+        .iter()
+        .position(|c| *c == b'\0')
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).to_string()
+}
+
+#[derive(Debug)]
+pub struct CIAContentInfoRecord {
+    pub content_index_offset: u16,
+    pub content_command_count: u16,
+    pub sha256_hash: [u8; 0x20],
+}
+
+impl CIAContentInfoRecord {
+    pub fn from_bytes(content_info_record_bytes: &[u8; 0x24]) -> Self {
+        CIAContentInfoRecord {
+            content_index_offset: u16::from_be_bytes(
+                content_info_record_bytes[..2].try_into().unwrap(),
+            ),
+            content_command_count: u16::from_be_bytes(
+                content_info_record_bytes[0x2..0x2 + 2].try_into().unwrap(),
+            ),
+            sha256_hash: content_info_record_bytes[0x4..].try_into().unwrap(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CIATitleMetadata {
+    pub signature: CIASignature,
+    pub issuer: String,
+    pub version: u8,
+    pub system_version: u64,
+    pub title_id: TitleId,
+    pub title_type: u32,
+    pub group_id: u16,
+    pub save_data_size: u32,
+    pub access_rights: u32,
+    pub title_version: u16,
+    pub boot_content: u16,
+    /// Only the records actually in use, i.e. with a non zero command count
+    pub content_info_records: Vec<CIAContentInfoRecord>,
+    pub content_chunk_records: Vec<CIAContentChunkRecord>,
+}
+
+impl CIATitleMetadata {
+    pub fn from_file<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        const TITLE_METADATA_HEADER_SIZE: usize = 0xC4;
+        const CONTENT_INFO_RECORD_COUNT: usize = 64;
+        const CONTENT_INFO_RECORD_SIZE: usize = 0x24;
+        const CONTENT_CHUNK_RECORD_SIZE: usize = 0x30;
+
+        let signature = CIASignature::from_file(f)?;
+
+        let mut header = [0u8; TITLE_METADATA_HEADER_SIZE];
+        f.read_exact(&mut header)?;
+        let content_count = u16::from_be_bytes(header[0x9E..0x9E + 2].try_into().unwrap());
+
+        let mut content_info_records = [0u8; CONTENT_INFO_RECORD_COUNT * CONTENT_INFO_RECORD_SIZE];
+        f.read_exact(&mut content_info_records)?;
+        let content_info_records = content_info_records
+            .chunks_exact(CONTENT_INFO_RECORD_SIZE)
+            .map(|record| CIAContentInfoRecord::from_bytes(record.try_into().unwrap()))
+            .filter(|record| record.content_command_count != 0)
+            .collect();
 
         let content_chunk_records = (0..content_count)
             .map(|_| {
                 let mut content_chunk_record = [0u8; CONTENT_CHUNK_RECORD_SIZE];
                 f.read_exact(&mut content_chunk_record)?;
-                Ok(CIAContentChunkRecord::from_bytes(&content_chunk_record)?)
+                Ok(CIAContentChunkRecord::from_bytes(&content_chunk_record))
             })
             .collect::<Result<Vec<_>, N3DSParsingError>>()?;
 
         Ok(CIATitleMetadata {
+            signature,
+            issuer: string_from_padded_bytes(&header[..0x40]),
+            version: header[0x40],
+            system_version: u64::from_be_bytes(header[0x44..0x44 + 8].try_into().unwrap()),
+            title_id: TitleId(u64::from_be_bytes(
+                header[0x4C..0x4C + 8].try_into().unwrap(),
+            )),
+            title_type: u32::from_be_bytes(header[0x54..0x54 + 4].try_into().unwrap()),
+            group_id: u16::from_be_bytes(header[0x58..0x58 + 2].try_into().unwrap()),
+            // Unlike every other field, the save data size is stored as little endian
+            save_data_size: u32::from_le_bytes(header[0x5A..0x5A + 4].try_into().unwrap()),
+            access_rights: u32::from_be_bytes(header[0x98..0x98 + 4].try_into().unwrap()),
+            title_version: u16::from_be_bytes(header[0x9C..0x9C + 2].try_into().unwrap()),
+            boot_content: u16::from_be_bytes(header[0xA0..0xA0 + 2].try_into().unwrap()),
+            content_info_records,
             content_chunk_records,
         })
     }
 
-    pub fn content_chunk_records(&self) -> &[CIAContentChunkRecord] {
-        &self.content_chunk_records
+    /// Updates and DLCs don't come with an icon, so errors about the icon not being there
+    /// are replaced by one explaining why
+    pub fn explain_missing_icon(&self, err: N3DSParsingError) -> N3DSParsingError {
+        if err.is_missing_icon() && self.title_id.kind().is_base_title_dependent() {
+            N3DSParsingError::TitleHasNoIcon(self.title_id)
+        } else {
+            err
+        }
+    }
+
+    pub fn main_content(&self) -> Result<&CIAContentChunkRecord, CIAParsingError> {
+        self.content_chunk_records
+            .iter()
+            .find(|item| item.content_index == CIAContentIndex::MainContent)
+            .ok_or(CIAParsingError::NoIconAvailable(
+                CXIParsingError::NoCXIContent,
+            ))
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CIAContentIndex {
     MainContent,
     HomeMenuManual,
     DlpChildContainer,
+    /// DLCs have one content per index, often hundreds of them
+    Other(u16),
 }
 
-impl TryFrom<u16> for CIAContentIndex {
-    type Error = CIAParsingError;
+impl From<u16> for CIAContentIndex {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => CIAContentIndex::MainContent,
+            1 => CIAContentIndex::HomeMenuManual,
+            2 => CIAContentIndex::DlpChildContainer,
+            _ => CIAContentIndex::Other(value),
+        }
+    }
+}
 
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
+impl From<CIAContentIndex> for u16 {
+    fn from(value: CIAContentIndex) -> Self {
         match value {
-            0 => Ok(CIAContentIndex::MainContent),
-            1 => Ok(CIAContentIndex::HomeMenuManual),
-            2 => Ok(CIAContentIndex::DlpChildContainer),
-            _ => Err(Self::Error::ContentIndexInvalidValue(value)),
+            CIAContentIndex::MainContent => 0,
+            CIAContentIndex::HomeMenuManual => 1,
+            CIAContentIndex::DlpChildContainer => 2,
+            CIAContentIndex::Other(value) => value,
         }
     }
 }
@@ -173,7 +289,7 @@ pub struct CIAContentChunkRecord {
 }
 
 impl CIAContentChunkRecord {
-    pub fn from_bytes(content_chunk_record_bytes: &[u8; 0x30]) -> Result<Self, CIAParsingError> {
+    pub fn from_bytes(content_chunk_record_bytes: &[u8; 0x30]) -> Self {
         let content_id = u32::from_be_bytes(content_chunk_record_bytes[..4].try_into().unwrap());
         let content_index =
             u16::from_be_bytes(content_chunk_record_bytes[0x4..0x4 + 2].try_into().unwrap());
@@ -183,30 +299,44 @@ impl CIAContentChunkRecord {
             u64::from_be_bytes(content_chunk_record_bytes[0x8..0x8 + 8].try_into().unwrap());
         let sha256_hash: [u8; 0x20] = content_chunk_record_bytes[0x10..].try_into().unwrap();
 
-        let content_index = CIAContentIndex::try_from(content_index)?;
+        let content_index = CIAContentIndex::from(content_index);
         let content_type = CIAContentType::from_bits_truncate(content_type);
 
-        Ok(CIAContentChunkRecord {
+        CIAContentChunkRecord {
             content_id,
             content_index,
             content_type,
             content_size,
             sha256_hash,
-        })
+        }
     }
 }
 
-impl SMDHIcon {
-    pub fn from_cia<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
-        /*
-         * The meta section isn't in a fixed place and is located after a bunch of sections whose
-         * size can vary, therefore it's needed to at the very last fetch the other sizes and
-         * take the padding into account
-         */
+/// CIAs usually contain a 3DS (CTR) title with a SMDH,
+/// but DSiWare CIAs contain a TWL SRL (a DSi rom) with a NDS banner instead
+#[derive(Debug)]
+pub enum CIAIcon {
+    Ctr(Smdh),
+    Twl(NDSBannerDetails),
+}
+
+/// The CIA header holds the sizes of each section, with every section starting
+/// at a 0x40 bytes boundary right after the previous one
+#[derive(Debug)]
+pub struct CIAHeader {
+    pub certificate_chain_size: u64,
+    pub ticket_size: u64,
+    pub tmd_size: u64,
+    pub meta_size: CIAMetaSize,
+    pub content_size: u64,
+}
+
+impl CIAHeader {
+    const SIZE: u64 = 0x2040;
+    const PADDING_SIZE: u64 = 0x40;
 
+    pub fn from_file<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
         const CIA_HEADER_CERTIFICATE_CHAIN_SIZE_OFFSET: u64 = 0x08;
-        const CIA_HEADER_SIZE: u64 = 0x2040;
-        const CIA_PADDING_SIZE: u64 = 0x40;
 
         f.seek(SeekFrom::Start(CIA_HEADER_CERTIFICATE_CHAIN_SIZE_OFFSET))?;
         let mut certificate_chain_size = [0u8; 4];
@@ -230,38 +360,197 @@ impl SMDHIcon {
         f.read_exact(&mut content_size)?;
         let content_size: u64 = u64::from_le_bytes(content_size);
 
-        let certificate_chain_size_with_padding =
-            certificate_chain_size.next_multiple_of(CIA_PADDING_SIZE);
-        let ticket_size_with_padding = ticket_size.next_multiple_of(CIA_PADDING_SIZE);
-        let tmd_size_with_padding = tmd_size.next_multiple_of(CIA_PADDING_SIZE);
-        let content_size_with_padding = content_size.next_multiple_of(CIA_PADDING_SIZE);
-
-        if meta_size == CIAMetaSize::Present {
-            let offset_meta: u64 = CIA_HEADER_SIZE
-                + certificate_chain_size_with_padding
-                + ticket_size_with_padding
-                + tmd_size_with_padding
-                + content_size_with_padding;
-
-            f.seek(SeekFrom::Start(offset_meta))?;
-            return Self::from_cia_meta(f);
+        Ok(CIAHeader {
+            certificate_chain_size,
+            ticket_size,
+            tmd_size,
+            meta_size,
+            content_size,
+        })
+    }
+
+    pub fn ticket_offset(&self) -> u64 {
+        Self::SIZE
+            + self
+                .certificate_chain_size
+                .next_multiple_of(Self::PADDING_SIZE)
+    }
+
+    pub fn tmd_offset(&self) -> u64 {
+        self.ticket_offset() + self.ticket_size.next_multiple_of(Self::PADDING_SIZE)
+    }
+
+    pub fn content_offset(&self) -> u64 {
+        self.tmd_offset() + self.tmd_size.next_multiple_of(Self::PADDING_SIZE)
+    }
+
+    pub fn meta_offset(&self) -> u64 {
+        self.content_offset() + self.content_size.next_multiple_of(Self::PADDING_SIZE)
+    }
+}
+
+/// The offset of every CIA section, computed from the header
+///
+/// Only the header is parsed upfront, the other sections are parsed when needed,
+/// so CIAs with unusual certificates or tickets can still be thumbnailed from their Meta section
+#[derive(Debug)]
+pub struct CIAContainer {
+    pub header: CIAHeader,
+    pub certificate_chain_offset: u64,
+    pub ticket_offset: u64,
+    pub tmd_offset: u64,
+    pub content_offset: u64,
+    /// Only available when the Meta section is present
+    pub meta_offset: Option<u64>,
+}
+
+impl CIAContainer {
+    pub fn from_file<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        let header = CIAHeader::from_file(f)?;
+
+        Ok(CIAContainer {
+            certificate_chain_offset: CIAHeader::SIZE,
+            ticket_offset: header.ticket_offset(),
+            tmd_offset: header.tmd_offset(),
+            content_offset: header.content_offset(),
+            meta_offset: (header.meta_size == CIAMetaSize::Present).then(|| header.meta_offset()),
+            header,
+        })
+    }
+
+    pub fn certificates<T: Read + Seek>(
+        &self,
+        f: &mut T,
+    ) -> Result<Vec<CIACertificate>, N3DSParsingError> {
+        f.seek(SeekFrom::Start(self.certificate_chain_offset))?;
+        CIACertificate::chain_from_file(f, self.header.certificate_chain_size)
+    }
+
+    pub fn ticket<T: Read + Seek>(&self, f: &mut T) -> Result<CIATicket, N3DSParsingError> {
+        f.seek(SeekFrom::Start(self.ticket_offset))?;
+        CIATicket::from_file(f)
+    }
+
+    pub fn title_metadata<T: Read + Seek>(
+        &self,
+        f: &mut T,
+    ) -> Result<CIATitleMetadata, N3DSParsingError> {
+        f.seek(SeekFrom::Start(self.tmd_offset))?;
+        CIATitleMetadata::from_file(f)
+    }
+}
+
+impl CIAIcon {
+    pub fn from_cia<T: Read + Seek>(
+        f: &mut T,
+        checksum_mode: NDSBannerChecksumMode,
+    ) -> Result<Self, N3DSParsingError> {
+        /*
+         * The meta section isn't in a fixed place and is located after a bunch of sections whose
+         * size can vary, therefore it's needed to at the very last fetch the other sizes and
+         * take the padding into account
+         */
+
+        let container = CIAContainer::from_file(f)?;
+
+        if let Some(meta_offset) = container.meta_offset {
+            f.seek(SeekFrom::Start(meta_offset))?;
+            return Ok(Self::Ctr(Smdh::from_cia_meta(f)?));
         }
         eprintln!("CIA Meta section not present, attempting CIA's CXI..");
 
-        let offset_tmd: u64 =
-            CIA_HEADER_SIZE + certificate_chain_size_with_padding + ticket_size_with_padding;
-        f.seek(SeekFrom::Start(offset_tmd))?;
-
-        let offset_content: u64 = CIA_HEADER_SIZE
-            + certificate_chain_size_with_padding
-            + ticket_size_with_padding
-            + tmd_size_with_padding;
+        let title_metadata = container.title_metadata(f)?;
+        title_metadata
+            .main_content()
+            .map_err(N3DSParsingError::from)
+            .and_then(|main_content| {
+                Self::from_content(
+                    f,
+                    main_content,
+                    container.content_offset,
+                    |f| container.ticket(f),
+                    checksum_mode,
+                )
+            })
+            .map_err(|err| title_metadata.explain_missing_icon(err))
+            .inspect_err(|_| {
+                eprintln!("Failed to parse SMDH from CIA's CXI");
+            })
+    }
 
-        Self::from_cia_tmd(f, offset_content).inspect_err(|_| {
-            eprintln!("Failed to parse SMDH from CIA's CXI");
+    /// Parses the main content located at `content_offset`, decrypting it if needed
+    /// with the ticket given by `load_ticket`
+    pub fn from_content<T: Read + Seek>(
+        f: &mut T,
+        content: &CIAContentChunkRecord,
+        content_offset: u64,
+        load_ticket: impl FnOnce(&mut T) -> Result<CIATicket, N3DSParsingError>,
+        checksum_mode: NDSBannerChecksumMode,
+    ) -> Result<Self, N3DSParsingError> {
+        parse_content(f, content, content_offset, load_ticket, |mut content| {
+            Self::from_main_content(&mut content, checksum_mode)
         })
     }
 
+    fn from_main_content<T: Read + Seek>(
+        f: &mut T,
+        checksum_mode: NDSBannerChecksumMode,
+    ) -> Result<Self, N3DSParsingError> {
+        // DSiWare CIAs have a TWL SRL as their main content instead of a CXI
+        if is_twl_srl(f)? {
+            eprintln!("CIA main content is a TWL SRL, parsing it as DSiWare..");
+            return Ok(Self::Twl(extract_nds_banner(f, checksum_mode)?));
+        }
+        Ok(Self::Ctr(Smdh::from_cxi(f)?))
+    }
+}
+
+impl Cbmd {
+    /// The Meta section only holds the icon, so the banner always comes from the main content
+    pub fn from_cia<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        let container = CIAContainer::from_file(f)?;
+        let title_metadata = container.title_metadata(f)?;
+        let main_content = title_metadata.main_content()?;
+        parse_content(
+            f,
+            main_content,
+            container.content_offset,
+            |f| container.ticket(f),
+            |mut content| Self::from_cxi(&mut content),
+        )
+    }
+}
+
+/// Anything both readable and seekable, so plain and decrypted contents are parsed alike
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Runs `parse` on the content located at `content_offset`, decrypting it if needed,
+/// the ticket is only loaded for encrypted contents
+fn parse_content<T: Read + Seek, R>(
+    f: &mut T,
+    content: &CIAContentChunkRecord,
+    content_offset: u64,
+    load_ticket: impl FnOnce(&mut T) -> Result<CIATicket, N3DSParsingError>,
+    parse: impl FnOnce(&mut dyn ReadSeek) -> Result<R, N3DSParsingError>,
+) -> Result<R, N3DSParsingError> {
+    if !content.content_type.is_encrypted() {
+        f.seek(SeekFrom::Start(content_offset))?;
+        return parse(f);
+    }
+
+    // Encrypted contents use the title key with the content index as IV
+    let ticket = load_ticket(f)?;
+    let title_key = ticket.decrypt_title_key(&KeyStore::from_default_path()?)?;
+    let mut iv = [0u8; 16];
+    iv[..2].copy_from_slice(&u16::from(content.content_index).to_be_bytes());
+
+    let mut content = AesCbcReader::new(f, title_key, iv, content_offset, content.content_size);
+    parse(&mut content)
+}
+
+impl Smdh {
     pub fn from_cia_meta<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
         const CIA_META_SMDH_OFFSET: u64 = 0x400;
         let meta_start_pos = f.stream_position()?;
@@ -269,25 +558,253 @@ impl SMDHIcon {
         f.seek(SeekFrom::Start(meta_start_pos + CIA_META_SMDH_OFFSET))?;
         Self::from_smdh(f)
     }
+}
 
-    pub fn from_cia_tmd<T: Read + Seek>(
-        f: &mut T,
-        content_offset: u64,
-    ) -> Result<Self, N3DSParsingError> {
-        let title_metadata = CIATitleMetadata::from_file(f)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
 
-        f.seek(SeekFrom::Start(content_offset))?;
-        let Some(cxi_content) = title_metadata
-            .content_chunk_records()
-            .iter()
-            .find(|item| item.content_index == CIAContentIndex::MainContent)
-        else {
-            return Err(CIAParsingError::NoIconAvailable(CXIParsingError::NoCXIContent).into());
-        };
+    const TITLE_ID: u64 = 0x0004_0000_0012_3400;
 
-        if cxi_content.content_type.is_encrypted() {
-            return Err(CIAParsingError::NoIconAvailable(CXIParsingError::FileEncrypted).into());
+    /// Builds a signature block of `signature_type`, filled with `fill`, plus its padding
+    pub(super) fn synthetic_signature(signature_type: u32, fill: u8) -> Vec<u8> {
+        let signature_type_value = CIASignatureType::try_from(signature_type).unwrap();
+        let mut signature = signature_type.to_be_bytes().to_vec();
+        signature.resize(4 + signature_type_value.size(), fill);
+        signature.resize(signature.len() + signature_type_value.padding_size(), 0);
+        signature
+    }
+
+    fn content_chunk_record(content_id: u32, index: u16, content_type: u16, size: u64) -> Vec<u8> {
+        let mut record = vec![0u8; 0x30];
+        record[..4].copy_from_slice(&content_id.to_be_bytes());
+        record[0x4..0x6].copy_from_slice(&index.to_be_bytes());
+        record[0x6..0x8].copy_from_slice(&content_type.to_be_bytes());
+        record[0x8..0x10].copy_from_slice(&size.to_be_bytes());
+        record[0x10..].fill(content_id as u8 + 0xC0);
+        record
+    }
+
+    /// Builds a TMD with a main content and an encrypted optional manual,
+    /// only the first content info record being in use
+    fn synthetic_tmd() -> Vec<u8> {
+        let mut tmd = synthetic_signature(0x0001_0004, 0x5A);
+
+        let mut header = [0u8; 0xC4];
+        header[..0x1A].copy_from_slice(b"Root-CA00000003-CP0000000b");
+        header[0x40] = 1;
+        header[0x44..0x4C].copy_from_slice(&0x0004_0138_0000_2C00u64.to_be_bytes());
+        header[0x4C..0x54].copy_from_slice(&TITLE_ID.to_be_bytes());
+        header[0x54..0x58].copy_from_slice(&0x40u32.to_be_bytes());
+        header[0x58..0x5A].copy_from_slice(&0x1234u16.to_be_bytes());
+        header[0x5A..0x5E].copy_from_slice(&0x0008_0000u32.to_le_bytes());
+        header[0x98..0x9C].copy_from_slice(&0x0000_0001u32.to_be_bytes());
+        header[0x9C..0x9E].copy_from_slice(&0x0410u16.to_be_bytes());
+        header[0x9E..0xA0].copy_from_slice(&2u16.to_be_bytes());
+        tmd.extend_from_slice(&header);
+
+        let mut content_info_records = [0u8; 64 * 0x24];
+        content_info_records[0x2..0x4].copy_from_slice(&2u16.to_be_bytes());
+        content_info_records[0x4..0x24].fill(0xAA);
+        tmd.extend_from_slice(&content_info_records);
+
+        tmd.extend(content_chunk_record(0x0000_0000, 0, 0x0000, 0x4000));
+        tmd.extend(content_chunk_record(0x0000_0001, 1, 0x4001, 0x200));
+        tmd
+    }
+
+    #[test]
+    fn title_metadata_parsing() {
+        let tmd = synthetic_tmd();
+        let mut f = Cursor::new(&tmd);
+        let title_metadata = CIATitleMetadata::from_file(&mut f).unwrap();
+        assert_eq!(f.position(), tmd.len() as u64);
+
+        assert!(matches!(
+            title_metadata.signature.signature_type,
+            CIASignatureType::Rsa2048Sha256
+        ));
+        assert_eq!(title_metadata.signature.data, vec![0x5A; 0x100]);
+        assert_eq!(title_metadata.issuer, "Root-CA00000003-CP0000000b");
+        assert_eq!(title_metadata.version, 1);
+        assert_eq!(title_metadata.system_version, 0x0004_0138_0000_2C00);
+        assert_eq!(title_metadata.title_id, TitleId(TITLE_ID));
+        assert_eq!(title_metadata.title_type, 0x40);
+        assert_eq!(title_metadata.group_id, 0x1234);
+        assert_eq!(title_metadata.save_data_size, 0x0008_0000);
+        assert_eq!(title_metadata.access_rights, 1);
+        assert_eq!(title_metadata.title_version, 0x0410);
+        assert_eq!(title_metadata.boot_content, 0);
+
+        assert_eq!(title_metadata.content_info_records.len(), 1);
+        let content_info_record = &title_metadata.content_info_records[0];
+        assert_eq!(content_info_record.content_index_offset, 0);
+        assert_eq!(content_info_record.content_command_count, 2);
+        assert_eq!(content_info_record.sha256_hash, [0xAA; 0x20]);
+
+        let [main_content, manual] = title_metadata.content_chunk_records.as_slice() else {
+            panic!("expected two content chunk records");
         };
-        Self::from_cxi(f)
+        assert_eq!(main_content.content_index, CIAContentIndex::MainContent);
+        assert!(!main_content.content_type.is_encrypted());
+        assert_eq!(main_content.content_size, 0x4000);
+        assert_eq!(main_content.sha256_hash, [0xC0; 0x20]);
+        assert_eq!(manual.content_id, 1);
+        assert_eq!(manual.content_index, CIAContentIndex::HomeMenuManual);
+        assert_eq!(
+            manual.content_type,
+            CIAContentType::ENCRYPTED | CIAContentType::OPTIONAL
+        );
+        assert!(manual.content_type.is_encrypted());
+        assert_eq!(title_metadata.main_content().unwrap().content_id, 0);
+    }
+
+    #[test]
+    fn title_metadata_rejects_truncated_records() {
+        let mut tmd = synthetic_tmd();
+        tmd.truncate(tmd.len() - 1);
+        assert!(matches!(
+            CIATitleMetadata::from_file(&mut Cursor::new(&tmd)),
+            Err(N3DSParsingError::IoError(_))
+        ));
+    }
+
+    /// Builds a DLC CIA without Meta section, whose main content is a CFA without ExeFS
+    fn synthetic_dlc_cia() -> Vec<u8> {
+        const DLC_ID: u64 = 0x0004_008C_0012_3400;
+        const CONTENT_CHUNK_RECORDS_OFFSET: usize = 0x140 + 0xC4 + 64 * 0x24;
+
+        let mut tmd = synthetic_tmd();
+        tmd[0x140 + 0x4C..0x140 + 0x54].copy_from_slice(&DLC_ID.to_be_bytes());
+        tmd[0x140 + 0x9E..0x140 + 0xA0].copy_from_slice(&4u16.to_be_bytes());
+        tmd.truncate(CONTENT_CHUNK_RECORDS_OFFSET);
+        for (content_id, index) in (0..).zip([0, 1, 5, 0x40]) {
+            tmd.extend(content_chunk_record(content_id, index, 0x0000, 0x200));
+        }
+
+        let mut cfa = vec![0u8; 0x200];
+        cfa[0x100..0x104].copy_from_slice(b"NCCH");
+        cfa[0x108..0x110].copy_from_slice(&DLC_ID.to_le_bytes());
+        cfa[0x118..0x120].copy_from_slice(&DLC_ID.to_le_bytes());
+        cfa[0x188 + 7] = 0x04;
+
+        let mut cia = vec![0u8; CIAHeader::SIZE as usize];
+        cia[0x10..0x14].copy_from_slice(&(tmd.len() as u32).to_le_bytes());
+        cia[0x18..0x20].copy_from_slice(&(cfa.len() as u64).to_le_bytes());
+        cia.extend_from_slice(&tmd);
+        cia.resize(cia.len().next_multiple_of(0x40), 0);
+        cia.extend_from_slice(&cfa);
+        cia
+    }
+
+    #[test]
+    fn dlc_without_icon() {
+        let cia = synthetic_dlc_cia();
+        let mut f = Cursor::new(&cia);
+        let container = CIAContainer::from_file(&mut f).unwrap();
+        let title_metadata = container.title_metadata(&mut f).unwrap();
+        let content_indexes = title_metadata
+            .content_chunk_records
+            .iter()
+            .map(|record| record.content_index)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            content_indexes,
+            [
+                CIAContentIndex::MainContent,
+                CIAContentIndex::HomeMenuManual,
+                CIAContentIndex::Other(5),
+                CIAContentIndex::Other(0x40)
+            ]
+        );
+
+        assert!(matches!(
+            CIAIcon::from_cia(&mut Cursor::new(&cia), NDSBannerChecksumMode::Lenient),
+            Err(N3DSParsingError::TitleHasNoIcon(title_id)) if title_id == title_metadata.title_id
+        ));
+    }
+
+    #[test]
+    fn signature_sizes() {
+        for (signature_type, size) in [
+            (0x0001_0000, 0x240),
+            (0x0001_0001, 0x140),
+            (0x0001_0002, 0x80),
+            (0x0001_0003, 0x240),
+            (0x0001_0004, 0x140),
+            (0x0001_0005, 0x80),
+        ] {
+            let mut signature = synthetic_signature(signature_type, 0x11);
+            assert_eq!(signature.len(), size);
+            signature.extend_from_slice(b"next");
+
+            let mut f = Cursor::new(&signature);
+            let parsed = CIASignature::from_file(&mut f).unwrap();
+            assert_eq!(
+                parsed.data.len(),
+                size - 4 - parsed.signature_type.padding_size()
+            );
+            assert!(parsed.data.iter().all(|byte| *byte == 0x11));
+            assert_eq!(f.position(), size as u64);
+        }
+
+        let unknown_signature = 0x0002_0000u32.to_be_bytes();
+        assert!(matches!(
+            CIASignature::from_file(&mut Cursor::new(&unknown_signature)),
+            Err(N3DSParsingError::CIAParsingError(
+                CIAParsingError::SignatureTypeInvalidValue(0x0002_0000)
+            ))
+        ));
+    }
+
+    #[test]
+    fn content_info_record_parsing() {
+        let mut record = [0u8; 0x24];
+        record[..2].copy_from_slice(&0x0001u16.to_be_bytes());
+        record[0x2..0x4].copy_from_slice(&0x0102u16.to_be_bytes());
+        for (i, byte) in record[0x4..].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        let record = CIAContentInfoRecord::from_bytes(&record);
+        assert_eq!(record.content_index_offset, 1);
+        assert_eq!(record.content_command_count, 0x0102);
+        assert_eq!(record.sha256_hash[0], 0);
+        assert_eq!(record.sha256_hash[0x1F], 0x1F);
+    }
+
+    #[test]
+    fn only_missing_icons_are_explained() {
+        let mut tmd = synthetic_tmd();
+        let title_id_offset = 0x140 + 0x4C;
+        tmd[title_id_offset..title_id_offset + 8]
+            .copy_from_slice(&0x0004_008C_0012_3400u64.to_be_bytes());
+        let title_metadata = CIATitleMetadata::from_file(&mut Cursor::new(&tmd)).unwrap();
+
+        for err in [
+            N3DSParsingError::from(CXIParsingError::ExeFSFileNotFound("icon")),
+            N3DSParsingError::from(CXIParsingError::NoExeFS),
+            N3DSParsingError::from(CIAParsingError::NoIconAvailable(
+                CXIParsingError::NoCXIContent,
+            )),
+        ] {
+            assert!(matches!(
+                title_metadata.explain_missing_icon(err),
+                N3DSParsingError::TitleHasNoIcon(_)
+            ));
+        }
+        assert!(matches!(
+            title_metadata.explain_missing_icon(CIAParsingError::TicketNotAvailable.into()),
+            N3DSParsingError::CIAParsingError(CIAParsingError::TicketNotAvailable)
+        ));
+
+        // Base titles always keep their errors
+        let title_metadata =
+            CIATitleMetadata::from_file(&mut Cursor::new(synthetic_tmd())).unwrap();
+        assert!(matches!(
+            title_metadata.explain_missing_icon(CXIParsingError::NoExeFS.into()),
+            N3DSParsingError::CXIParsingError(CXIParsingError::NoExeFS)
+        ));
     }
 }
@@ -0,0 +1,186 @@
+use std::io::{Read, Seek};
+
+use crate::n3ds::errors::{CIAParsingError, N3DSParsingError};
+
+use super::{string_from_padded_bytes, CIASignature};
+
+/*
+ * The certificate chain of a CIA usually holds the CA, the ticket signer (XS)
+ * and the TMD signer (CP) certificates, one right after the other.
+ * Each one is made of a signature, the issuer, the key type, the name
+ * and a public key whose size depends on the key type.
+ *
+ * See https://www.3dbrew.org/wiki/Certificates for more info
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CIACertificateKeyType {
+    Rsa4096,
+    Rsa2048,
+    EllipticCurve,
+}
+
+impl TryFrom<u32> for CIACertificateKeyType {
+    type Error = CIAParsingError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(CIACertificateKeyType::Rsa4096),
+            1 => Ok(CIACertificateKeyType::Rsa2048),
+            2 => Ok(CIACertificateKeyType::EllipticCurve),
+            _ => Err(Self::Error::CertificateKeyTypeInvalidValue(value)),
+        }
+    }
+}
+
+impl CIACertificateKeyType {
+    /// Size of the public key, including the RSA exponent
+    pub fn size(&self) -> usize {
+        match self {
+            CIACertificateKeyType::Rsa4096 => 0x200 + 4,
+            CIACertificateKeyType::Rsa2048 => 0x100 + 4,
+            CIACertificateKeyType::EllipticCurve => 0x3C,
+        }
+    }
+
+    pub fn padding_size(&self) -> usize {
+        match self {
+            CIACertificateKeyType::Rsa4096 | CIACertificateKeyType::Rsa2048 => 0x34,
+            CIACertificateKeyType::EllipticCurve => 0x3C,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CIACertificate {
+    pub signature: CIASignature,
+    pub issuer: String,
+    pub key_type: CIACertificateKeyType,
+    pub name: String,
+    pub expiration_time: u32,
+    pub public_key: Vec<u8>,
+}
+
+impl CIACertificate {
+    pub fn from_file<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        const CERTIFICATE_HEADER_SIZE: usize = 0x88;
+
+        let signature = CIASignature::from_file(f)?;
+
+        let mut header = [0u8; CERTIFICATE_HEADER_SIZE];
+        f.read_exact(&mut header)?;
+        let key_type = u32::from_be_bytes(header[0x40..0x40 + 4].try_into().unwrap());
+        let key_type = CIACertificateKeyType::try_from(key_type)?;
+
+        let mut public_key = vec![0u8; key_type.size() + key_type.padding_size()];
+        f.read_exact(&mut public_key)?;
+        public_key.truncate(key_type.size());
+
+        Ok(CIACertificate {
+            signature,
+            issuer: string_from_padded_bytes(&header[..0x40]),
+            key_type,
+            name: string_from_padded_bytes(&header[0x44..0x44 + 0x40]),
+            expiration_time: u32::from_be_bytes(header[0x84..0x84 + 4].try_into().unwrap()),
+            public_key,
+        })
+    }
+
+    /// Parses every certificate of a chain of `size` bytes starting at the current position
+    pub fn chain_from_file<T: Read + Seek>(
+        f: &mut T,
+        size: u64,
+    ) -> Result<Vec<Self>, N3DSParsingError> {
+        let chain_end = f.stream_position()? + size;
+
+        let mut certificates = Vec::new();
+        while f.stream_position()? < chain_end {
+            certificates.push(Self::from_file(f)?);
+        }
+        Ok(certificates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::n3ds::structures::cia::tests::synthetic_signature;
+    use std::io::Cursor;
+
+    fn synthetic_certificate(issuer: &str, key_type: u32, name: &str) -> Vec<u8> {
+        let mut certificate = synthetic_signature(0x0001_0004, 0x22);
+
+        let mut header = [0u8; 0x88];
+        header[..issuer.len()].copy_from_slice(issuer.as_bytes());
+        header[0x40..0x44].copy_from_slice(&key_type.to_be_bytes());
+        header[0x44..0x44 + name.len()].copy_from_slice(name.as_bytes());
+        header[0x84..0x88].copy_from_slice(&0x5EAD_BEEFu32.to_be_bytes());
+        certificate.extend_from_slice(&header);
+
+        let key_type = CIACertificateKeyType::try_from(key_type).unwrap();
+        certificate.resize(certificate.len() + key_type.size(), 0x33);
+        certificate.resize(certificate.len() + key_type.padding_size(), 0);
+        certificate
+    }
+
+    #[test]
+    fn public_key_sizes() {
+        for (key_type, size) in [(0, 0x204), (1, 0x104), (2, 0x3C)] {
+            let certificate = synthetic_certificate("Root", key_type, "CA00000003");
+            let padding_size = if key_type == 2 { 0x3C } else { 0x34 };
+            assert_eq!(certificate.len(), 0x140 + 0x88 + size + padding_size);
+
+            let mut f = Cursor::new(&certificate);
+            let parsed = CIACertificate::from_file(&mut f).unwrap();
+            assert_eq!(parsed.public_key, vec![0x33; size]);
+            assert_eq!(f.position(), certificate.len() as u64);
+        }
+    }
+
+    #[test]
+    fn chain_parsing() {
+        let mut chain = synthetic_certificate("Root", 0, "CA00000003");
+        chain.extend(synthetic_certificate("Root-CA00000003", 1, "XS0000000c"));
+        chain.extend(synthetic_certificate("Root-CA00000003", 1, "CP0000000b"));
+        // Whatever follows the chain, such as the section padding, isn't parsed
+        chain.extend_from_slice(&[0xFF; 0x40]);
+
+        let certificates =
+            CIACertificate::chain_from_file(&mut Cursor::new(&chain), chain.len() as u64 - 0x40)
+                .unwrap();
+        let names = certificates
+            .iter()
+            .map(|certificate| format!("{}-{}", certificate.issuer, certificate.name))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "Root-CA00000003",
+                "Root-CA00000003-XS0000000c",
+                "Root-CA00000003-CP0000000b"
+            ]
+        );
+        assert_eq!(certificates[0].key_type, CIACertificateKeyType::Rsa4096);
+        assert_eq!(certificates[1].key_type, CIACertificateKeyType::Rsa2048);
+        assert_eq!(certificates[2].expiration_time, 0x5EAD_BEEF);
+    }
+
+    #[test]
+    fn chain_rejects_bad_certificates() {
+        let mut chain = synthetic_certificate("Root", 0, "CA00000003");
+        chain.truncate(chain.len() - 1);
+        assert!(matches!(
+            CIACertificate::chain_from_file(&mut Cursor::new(&chain), chain.len() as u64 + 1),
+            Err(N3DSParsingError::IoError(_))
+        ));
+
+        let mut chain = synthetic_certificate("Root", 1, "CA00000003");
+        chain[0x140 + 0x40..0x140 + 0x44].copy_from_slice(&3u32.to_be_bytes());
+        assert!(matches!(
+            CIACertificate::chain_from_file(&mut Cursor::new(&chain), chain.len() as u64),
+            Err(N3DSParsingError::CIAParsingError(
+                CIAParsingError::CertificateKeyTypeInvalidValue(3)
+            ))
+        ));
+    }
+}
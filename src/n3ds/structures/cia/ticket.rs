@@ -0,0 +1,139 @@
+use std::io::{Read, Seek};
+
+use crate::n3ds::{
+    crypto::{aes_cbc_decrypt, AesKey, KeyStore},
+    errors::{N3DSCryptoError, N3DSParsingError},
+};
+
+use super::{string_from_padded_bytes, CIASignature};
+use crate::n3ds::structures::TitleId;
+
+/*
+ * The ticket holds the title key needed to decrypt the CIA contents, itself encrypted
+ * with one of the common keys (keyslot 0x3D with the KeyY selected by the common key index)
+ * using AES-128-CBC with the title ID as IV.
+ *
+ * See https://www.3dbrew.org/wiki/Ticket for more info
+*/
+
+const TICKET_DATA_SIZE: usize = 0xB2;
+const TICKET_COMMON_KEY_X_SLOT: u8 = 0x3D;
+
+#[derive(Debug)]
+pub struct CIATicket {
+    pub signature: CIASignature,
+    pub issuer: String,
+    pub version: u8,
+    pub encrypted_title_key: AesKey,
+    pub ticket_id: u64,
+    pub console_id: u32,
+    pub title_id: TitleId,
+    pub title_version: u16,
+    pub common_key_index: u8,
+}
+
+impl CIATicket {
+    pub fn from_file<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        let signature = CIASignature::from_file(f)?;
+
+        let mut ticket_data = [0u8; TICKET_DATA_SIZE];
+        f.read_exact(&mut ticket_data)?;
+
+        Ok(CIATicket {
+            signature,
+            issuer: string_from_padded_bytes(&ticket_data[..0x40]),
+            version: ticket_data[0x7C],
+            encrypted_title_key: ticket_data[0x7F..0x7F + 0x10].try_into().unwrap(),
+            ticket_id: u64::from_be_bytes(ticket_data[0x90..0x90 + 8].try_into().unwrap()),
+            console_id: u32::from_be_bytes(ticket_data[0x98..0x98 + 4].try_into().unwrap()),
+            title_id: TitleId(u64::from_be_bytes(
+                ticket_data[0x9C..0x9C + 8].try_into().unwrap(),
+            )),
+            title_version: u16::from_be_bytes(ticket_data[0xA6..0xA6 + 2].try_into().unwrap()),
+            common_key_index: ticket_data[0xB1],
+        })
+    }
+
+    pub fn decrypt_title_key(&self, key_store: &KeyStore) -> Result<AesKey, N3DSCryptoError> {
+        let key_y = key_store.key(&format!("common{}", self.common_key_index))?;
+        let common_key = key_store.scrambled_key(TICKET_COMMON_KEY_X_SLOT, &key_y)?;
+
+        let mut iv = [0u8; 16];
+        iv[..8].copy_from_slice(&self.title_id.0.to_be_bytes());
+
+        let mut title_key = self.encrypted_title_key;
+        aes_cbc_decrypt(&common_key, &iv, &mut title_key);
+        Ok(title_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::n3ds::crypto::scramble_key;
+    use crate::n3ds::structures::cia::tests::synthetic_signature;
+    use aes::{
+        cipher::{BlockEncrypt, KeyInit},
+        Aes128,
+    };
+    use std::io::Cursor;
+
+    const TITLE_ID: u64 = 0x0004_0000_0012_3400;
+    const TITLE_KEY: AesKey = [
+        0x3A, 0x91, 0x0C, 0x5E, 0x77, 0xD2, 0x18, 0xB4, 0x60, 0x2F, 0xE9, 0x43, 0x85, 0x1B, 0xC6,
+        0x0D,
+    ];
+    const KEY_FILE: &str = "slot0x3DKeyX=2B7E151628AED2A6ABF7158809CF4F3C\n\
+                            common1=6BC1BEE22E409F96E93D7E117393172A\n\
+                            generator=0123456789ABCDEFFEDCBA9876543210\n";
+
+    fn synthetic_ticket(common_key_index: u8, encrypted_title_key: &AesKey) -> Vec<u8> {
+        let mut ticket = synthetic_signature(0x0001_0004, 0x44);
+
+        let mut ticket_data = [0u8; TICKET_DATA_SIZE];
+        ticket_data[..0x1A].copy_from_slice(b"Root-CA00000003-XS0000000c");
+        ticket_data[0x7C] = 1;
+        ticket_data[0x7F..0x7F + 0x10].copy_from_slice(encrypted_title_key);
+        ticket_data[0x90..0x98].copy_from_slice(&0x0004_1234_5678_9ABCu64.to_be_bytes());
+        ticket_data[0x9C..0xA4].copy_from_slice(&TITLE_ID.to_be_bytes());
+        ticket_data[0xA6..0xA8].copy_from_slice(&0x0410u16.to_be_bytes());
+        ticket_data[0xB1] = common_key_index;
+        ticket.extend_from_slice(&ticket_data);
+        ticket
+    }
+
+    #[test]
+    fn title_key_decryption() {
+        let key_store = KeyStore::from_contents(KEY_FILE);
+        let common_key = scramble_key(
+            &key_store.key_x(TICKET_COMMON_KEY_X_SLOT).unwrap(),
+            &key_store.key("common1").unwrap(),
+            &key_store.key("generator").unwrap(),
+        );
+
+        // A single AES-128-CBC block, with the title ID as IV
+        let mut encrypted_title_key = TITLE_KEY;
+        for (byte, iv_byte) in encrypted_title_key.iter_mut().zip(TITLE_ID.to_be_bytes()) {
+            *byte ^= iv_byte;
+        }
+        Aes128::new(&common_key.into()).encrypt_block((&mut encrypted_title_key).into());
+
+        let ticket =
+            CIATicket::from_file(&mut Cursor::new(synthetic_ticket(1, &encrypted_title_key)))
+                .unwrap();
+        assert_eq!(ticket.issuer, "Root-CA00000003-XS0000000c");
+        assert_eq!(ticket.ticket_id, 0x0004_1234_5678_9ABC);
+        assert_eq!(ticket.title_id, TitleId(TITLE_ID));
+        assert_eq!(ticket.title_version, 0x0410);
+        assert_eq!(ticket.decrypt_title_key(&key_store).unwrap(), TITLE_KEY);
+
+        // Another common key index needs another KeyY
+        let ticket =
+            CIATicket::from_file(&mut Cursor::new(synthetic_ticket(0, &encrypted_title_key)))
+                .unwrap();
+        assert!(matches!(
+            ticket.decrypt_title_key(&key_store),
+            Err(N3DSCryptoError::MissingKey(name)) if name == "common0"
+        ));
+    }
+}
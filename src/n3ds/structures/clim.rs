@@ -0,0 +1,204 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use image::{imageops, RgbaImage};
+
+use crate::n3ds::errors::{N3DSParsingError, TextureParsingError};
+use crate::utils::pica::{decode_texture, PicaTextureFormat};
+
+/*
+ * BCLIM and BFLIM are the layout images used by the HOME Menu, applets and many games UI.
+ * Unlike most formats, the image data comes first and the headers are at the end of the file:
+ * a CLIM (or FLIM) header, followed by an imag block and the size of the image data.
+ *
+ * The imag block holds the visible width and height, the texture format and a swizzle mode
+ * telling how the texture is transformed, while the stored texture is padded
+ * to a power of two of at least 8 pixels, as required by the GPU.
+ * BCLIM and 3DS BFLIM files share the same format numbering, which differs from the GPU one,
+ * Wii U BFLIM files are big endian and use GX2 textures instead, so they aren't supported.
+ *
+ * See https://www.3dbrew.org/wiki/BCLIM for more info
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutImageKind {
+    Bclim,
+    Bflim,
+}
+
+impl LayoutImageKind {
+    fn magic(self) -> &'static str {
+        match self {
+            LayoutImageKind::Bclim => "CLIM",
+            LayoutImageKind::Bflim => "FLIM",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureSwizzle {
+    None,
+    FlipVertical,
+    Transpose,
+    Rotate,
+}
+
+impl TryFrom<u8> for TextureSwizzle {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(TextureSwizzle::None),
+            2 => Ok(TextureSwizzle::FlipVertical),
+            4 => Ok(TextureSwizzle::Transpose),
+            8 => Ok(TextureSwizzle::Rotate),
+            _ => Err(value),
+        }
+    }
+}
+
+impl TextureSwizzle {
+    /// Transposed and rotated textures are stored with their dimensions swapped
+    fn swaps_dimensions(self) -> bool {
+        matches!(self, TextureSwizzle::Transpose | TextureSwizzle::Rotate)
+    }
+
+    fn apply(self, img: RgbaImage) -> RgbaImage {
+        match self {
+            TextureSwizzle::None => img,
+            TextureSwizzle::FlipVertical => imageops::flip_vertical(&img),
+            TextureSwizzle::Transpose => imageops::flip_horizontal(&imageops::rotate90(&img)),
+            TextureSwizzle::Rotate => imageops::rotate90(&img),
+        }
+    }
+}
+
+fn layout_texture_format(value: u8) -> Result<PicaTextureFormat, u8> {
+    match value {
+        0x0 => Ok(PicaTextureFormat::L8),
+        0x1 => Ok(PicaTextureFormat::A8),
+        0x2 => Ok(PicaTextureFormat::La4),
+        0x3 => Ok(PicaTextureFormat::La8),
+        0x4 => Ok(PicaTextureFormat::Hilo8),
+        0x5 => Ok(PicaTextureFormat::Rgb565),
+        0x6 => Ok(PicaTextureFormat::Rgb8),
+        0x7 => Ok(PicaTextureFormat::Rgba5551),
+        0x8 => Ok(PicaTextureFormat::Rgba4444),
+        0x9 => Ok(PicaTextureFormat::Rgba8),
+        0xA => Ok(PicaTextureFormat::Etc1),
+        0xB => Ok(PicaTextureFormat::Etc1A4),
+        0xC => Ok(PicaTextureFormat::L4),
+        0xD => Ok(PicaTextureFormat::A4),
+        _ => Err(value),
+    }
+}
+
+#[derive(Debug)]
+pub struct LayoutImage {
+    pub kind: LayoutImageKind,
+    pub width: usize,
+    pub height: usize,
+    pub format: PicaTextureFormat,
+    pub swizzle: TextureSwizzle,
+    pub data: Vec<u8>,
+}
+
+impl LayoutImage {
+    /// CLIM header, followed by the imag block and the image data size
+    const FOOTER_SIZE: u64 = 0x28;
+    const IMAG_BLOCK_SIZE: usize = 0x10;
+
+    pub fn from_layout_image<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        let file_size = f.seek(SeekFrom::End(0))?;
+        let footer_offset = file_size
+            .checked_sub(Self::FOOTER_SIZE)
+            .ok_or(TextureParsingError::TruncatedBlock("CLIM", 0))?;
+        f.seek(SeekFrom::Start(footer_offset))?;
+        let mut footer = [0u8; Self::FOOTER_SIZE as usize];
+        f.read_exact(&mut footer)?;
+
+        let magic: [u8; 4] = footer[..0x4].try_into().unwrap();
+        let kind = match &magic {
+            b"CLIM" => LayoutImageKind::Bclim,
+            b"FLIM" => LayoutImageKind::Bflim,
+            _ => return Err(N3DSParsingError::FileMagicNotFound("CLIM", magic)),
+        };
+
+        let u16_at = |offset: usize| u16::from_le_bytes([footer[offset], footer[offset + 1]]);
+        let byte_order_mark = u16_at(0x4);
+        if byte_order_mark != 0xFEFF {
+            return Err(TextureParsingError::ByteOrderMarkInvalidValue(
+                kind.magic(),
+                byte_order_mark,
+            ))?;
+        }
+
+        // The imag block follows the header, whose size is usually 0x14
+        let imag_offset = usize::from(u16_at(0x6));
+        let imag = footer
+            .get(imag_offset..imag_offset + Self::IMAG_BLOCK_SIZE + 0x4)
+            .ok_or(TextureParsingError::TruncatedBlock("imag", imag_offset))?;
+        if &imag[..0x4] != b"imag" {
+            return Err(N3DSParsingError::FileMagicNotFound(
+                "imag",
+                imag[..0x4].try_into().unwrap(),
+            ));
+        }
+
+        let width = usize::from(u16::from_le_bytes([imag[0x8], imag[0x9]]));
+        let height = usize::from(u16::from_le_bytes([imag[0xA], imag[0xB]]));
+        // BFLIM has an alignment halfword between the dimensions and the format and swizzle bytes
+        let (format, swizzle) = match kind {
+            LayoutImageKind::Bclim => (imag[0xC], imag[0xD]),
+            LayoutImageKind::Bflim => (imag[0xE], imag[0xF]),
+        };
+        let format = layout_texture_format(format)
+            .map_err(|format| TextureParsingError::UnsupportedTextureFormat(format.into()))?;
+        let swizzle =
+            TextureSwizzle::try_from(swizzle).map_err(TextureParsingError::SwizzleInvalidValue)?;
+
+        let data_size = u32::from_le_bytes(imag[Self::IMAG_BLOCK_SIZE..].try_into().unwrap());
+        if u64::from(data_size) > footer_offset {
+            return Err(TextureParsingError::TruncatedBlock(
+                "Image data",
+                data_size as usize,
+            ))?;
+        }
+        f.seek(SeekFrom::Start(0))?;
+        let mut data = vec![0u8; data_size as usize];
+        f.read_exact(&mut data)?;
+
+        Ok(LayoutImage {
+            kind,
+            width,
+            height,
+            format,
+            swizzle,
+            data,
+        })
+    }
+
+    /// Dimensions of the texture as stored, before applying the swizzle mode
+    pub fn texture_dimensions(&self) -> (usize, usize) {
+        let pad = |size: usize| size.next_power_of_two().max(8);
+        if self.swizzle.swaps_dimensions() {
+            (pad(self.height), pad(self.width))
+        } else {
+            (pad(self.width), pad(self.height))
+        }
+    }
+
+    /// Decodes the texture, cropped to its visible size
+    pub fn decode(&self) -> Result<RgbaImage, TextureParsingError> {
+        let (texture_width, texture_height) = self.texture_dimensions();
+        let texture = decode_texture(&self.data, texture_width, texture_height, self.format)
+            .ok_or(TextureParsingError::TextureTooLarge(
+                texture_width,
+                texture_height,
+                self.data.len(),
+            ))?;
+
+        let img = self.swizzle.apply(texture);
+        #[allow(clippy::cast_possible_truncation)]
+        Ok(imageops::crop_imm(&img, 0, 0, self.width as u32, self.height as u32).to_image())
+    }
+}
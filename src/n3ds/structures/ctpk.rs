@@ -0,0 +1,188 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use image::RgbaImage;
+
+use crate::n3ds::errors::{N3DSParsingError, TextureParsingError};
+use crate::utils::pica::{decode_texture, PicaTextureFormat};
+
+/*
+ * CTPK is a texture package, made of a header, a table of entries describing each texture,
+ * a table of file names and a hash table, followed by the texture data section.
+ *
+ * Entries hold the file name offset (relative to the start of the file),
+ * the data size and offset (relative to the start of the texture data section),
+ * the texture format (numbered like the GPU does), the dimensions and the mipmap levels.
+ * The data of each texture starts with its largest mipmap level.
+ *
+ * See https://www.3dbrew.org/wiki/CTPK for more info
+*/
+
+#[derive(Debug)]
+pub struct CTPKTexture {
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    pub format: PicaTextureFormat,
+    pub mipmap_levels: u8,
+    pub data: Vec<u8>,
+}
+
+impl CTPKTexture {
+    pub fn decode(&self) -> Option<RgbaImage> {
+        decode_texture(&self.data, self.width, self.height, self.format)
+    }
+}
+
+#[derive(Debug)]
+pub struct Ctpk {
+    pub version: u16,
+    pub textures: Vec<CTPKTexture>,
+}
+
+impl Ctpk {
+    const HEADER_SIZE: u64 = 0x20;
+    const ENTRY_SIZE: usize = 0x20;
+    const MAX_NAME_LENGTH: u64 = 0x100;
+
+    pub fn from_ctpk<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        let ctpk_start_pos = f.stream_position()?;
+
+        let mut header = [0u8; Self::HEADER_SIZE as usize];
+        f.read_exact(&mut header)?;
+        let magic: [u8; 4] = header[..0x4].try_into().unwrap();
+        if b"CTPK" != &magic {
+            return Err(N3DSParsingError::FileMagicNotFound("CTPK", magic));
+        }
+
+        let version = u16::from_le_bytes([header[0x4], header[0x5]]);
+        let texture_count = usize::from(u16::from_le_bytes([header[0x6], header[0x7]]));
+        let texture_section_offset = u32::from_le_bytes(header[0x8..0xC].try_into().unwrap());
+        let texture_section_size = u32::from_le_bytes(header[0xC..0x10].try_into().unwrap());
+
+        let mut entries = vec![0u8; texture_count * Self::ENTRY_SIZE];
+        f.read_exact(&mut entries)?;
+        let ctpk_size = f.seek(SeekFrom::End(0))?.saturating_sub(ctpk_start_pos);
+
+        let mut textures = Vec::with_capacity(texture_count);
+        for entry in entries.chunks_exact(Self::ENTRY_SIZE) {
+            let u32_at =
+                |offset: usize| u32::from_le_bytes(entry[offset..offset + 4].try_into().unwrap());
+            let u16_at = |offset: usize| u16::from_le_bytes([entry[offset], entry[offset + 1]]);
+
+            let name_offset = u32_at(0x0);
+            let data_size = u32_at(0x4);
+            let data_offset = u32_at(0x8);
+            let format = PicaTextureFormat::try_from(u32_at(0xC))
+                .map_err(TextureParsingError::UnsupportedTextureFormat)?;
+            let width = usize::from(u16_at(0x10));
+            let height = usize::from(u16_at(0x12));
+            let mipmap_levels = entry[0x14];
+
+            // The sizes of the header aren't trusted for the allocation, the data must be there
+            let data_end = u64::from(data_offset) + u64::from(data_size);
+            if data_end > u64::from(texture_section_size)
+                || u64::from(texture_section_offset) + data_end > ctpk_size
+            {
+                return Err(TextureParsingError::TruncatedBlock(
+                    "Texture data",
+                    data_offset as usize,
+                ))?;
+            }
+
+            f.seek(SeekFrom::Start(ctpk_start_pos + u64::from(name_offset)))?;
+            let mut name = Vec::new();
+            f.by_ref()
+                .take(Self::MAX_NAME_LENGTH)
+                .read_to_end(&mut name)?;
+            let name_length = name.iter().position(|c| *c == b'\0').unwrap_or(name.len());
+            let name = String::from_utf8_lossy(&name[..name_length]).to_string();
+
+            f.seek(SeekFrom::Start(
+                ctpk_start_pos + u64::from(texture_section_offset) + u64::from(data_offset),
+            ))?;
+            let mut data = vec![0u8; data_size as usize];
+            f.read_exact(&mut data)?;
+
+            textures.push(CTPKTexture {
+                name,
+                width,
+                height,
+                format,
+                mipmap_levels,
+                data,
+            });
+        }
+
+        Ok(Ctpk { version, textures })
+    }
+
+    /// Decodes the largest texture of the package
+    pub fn largest_texture(&self) -> Result<RgbaImage, TextureParsingError> {
+        let mut textures = self.textures.iter().collect::<Vec<_>>();
+        textures.sort_by_key(|texture| std::cmp::Reverse(texture.width * texture.height));
+
+        textures
+            .into_iter()
+            .find_map(CTPKTexture::decode)
+            .ok_or(TextureParsingError::NoTexturesFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const TEXTURE_SECTION_OFFSET: usize = 0x80;
+
+    /// Builds a CTPK holding a single 8x8 RGBA8 texture
+    fn synthetic_ctpk() -> Vec<u8> {
+        let mut ctpk = vec![0u8; TEXTURE_SECTION_OFFSET];
+        ctpk[..0x4].copy_from_slice(b"CTPK");
+        ctpk[0x4..0x6].copy_from_slice(&1u16.to_le_bytes());
+        ctpk[0x6..0x8].copy_from_slice(&1u16.to_le_bytes());
+        ctpk[0x8..0xC].copy_from_slice(&(TEXTURE_SECTION_OFFSET as u32).to_le_bytes());
+        ctpk[0xC..0x10].copy_from_slice(&0x100u32.to_le_bytes());
+
+        let entry = &mut ctpk[0x20..0x40];
+        entry[0x0..0x4].copy_from_slice(&0x40u32.to_le_bytes());
+        entry[0x4..0x8].copy_from_slice(&0x100u32.to_le_bytes());
+        entry[0x10..0x12].copy_from_slice(&8u16.to_le_bytes());
+        entry[0x12..0x14].copy_from_slice(&8u16.to_le_bytes());
+        entry[0x14] = 1;
+        ctpk[0x40..0x47].copy_from_slice(b"tex.tga");
+
+        ctpk.extend_from_slice(&[0xFF; 0x100]);
+        ctpk
+    }
+
+    #[test]
+    fn single_texture() {
+        let ctpk = Ctpk::from_ctpk(&mut Cursor::new(synthetic_ctpk())).unwrap();
+        assert_eq!(ctpk.version, 1);
+        let [texture] = ctpk.textures.as_slice() else {
+            panic!("expected a single texture");
+        };
+        assert_eq!(texture.name, "tex.tga");
+        assert_eq!(texture.format, PicaTextureFormat::Rgba8);
+        assert_eq!(texture.decode().unwrap().dimensions(), (8, 8));
+    }
+
+    #[test]
+    fn data_past_the_end_of_the_file() {
+        // Both sizes are consistent with each other, but not with the file
+        let mut ctpk = synthetic_ctpk();
+        ctpk[0xC..0x10].copy_from_slice(&u32::MAX.to_le_bytes());
+        ctpk[0x24..0x28].copy_from_slice(&0xFFFF_0000u32.to_le_bytes());
+        assert!(matches!(
+            Ctpk::from_ctpk(&mut Cursor::new(&ctpk)),
+            Err(N3DSParsingError::TextureParsingError(
+                TextureParsingError::TruncatedBlock("Texture data", 0)
+            ))
+        ));
+
+        let mut ctpk = synthetic_ctpk();
+        ctpk.pop();
+        assert!(Ctpk::from_ctpk(&mut Cursor::new(&ctpk)).is_err());
+    }
+}
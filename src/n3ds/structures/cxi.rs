@@ -1,10 +1,18 @@
+mod ncch_crypto;
 mod ncch_flags;
 
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom};
 
 use crate::n3ds::{
-    errors::{CXIParsingError, N3DSParsingError},
-    structures::{cxi::ncch_flags::NCCHFlags, SMDHIcon},
+    crypto::{KeyStore, SeedDatabase},
+    errors::{CXIParsingError, N3DSCryptoError, N3DSParsingError},
+    structures::{
+        cxi::{
+            ncch_crypto::NCCHCrypto,
+            ncch_flags::{NCCHFlags, NCCHSecurityFlags},
+        },
+        Cbmd, Smdh, TitleId, TitleKind,
+    },
 };
 
 #[derive(Debug)]
@@ -39,19 +47,29 @@ impl ExeFSFileHeader {
     }
 }
 
-impl SMDHIcon {
+/// The NCCH header fields identifying the title
+#[derive(Debug)]
+pub struct NCCHHeader {
+    pub partition_id: TitleId,
+    pub program_id: TitleId,
+    pub product_code: String,
+}
+
+impl NCCHHeader {
     pub fn from_cxi<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
-        const CXI_HEADER_MAGIC_OFFSET: u64 = 0x100;
-        const CXI_HEADER_FLAGS_OFFSET: u64 = 0x188;
-        const CXI_HEADER_EXEFS_OFFSET_VALUE: u64 = 0x1A0;
-        const CXI_MEDIA_UNIT_SIZE: u64 = 0x200;
+        const CXI_HEADER_SIZE: usize = 0x200;
+        const CXI_HEADER_MAGIC_OFFSET: usize = 0x100;
+        const CXI_HEADER_PARTITION_ID_OFFSET: usize = 0x108;
+        const CXI_HEADER_PROGRAM_ID_OFFSET: usize = 0x118;
+        const CXI_HEADER_PRODUCT_CODE_OFFSET: usize = 0x150;
         const CXI_MAGIC_STR: &str = "NCCH";
 
-        let cxi_start_pos = f.stream_position()?;
+        let mut header = [0u8; CXI_HEADER_SIZE];
+        f.read_exact(&mut header)?;
 
-        f.seek(SeekFrom::Start(cxi_start_pos + CXI_HEADER_MAGIC_OFFSET))?;
-        let mut cxi_magic = [0u8; 4];
-        f.read_exact(&mut cxi_magic)?;
+        let cxi_magic: [u8; 4] = header[CXI_HEADER_MAGIC_OFFSET..CXI_HEADER_MAGIC_OFFSET + 4]
+            .try_into()
+            .unwrap();
         if CXI_MAGIC_STR.as_bytes() != cxi_magic {
             return Err(N3DSParsingError::FileMagicNotFound(
                 CXI_MAGIC_STR,
@@ -59,50 +77,380 @@ impl SMDHIcon {
             ));
         }
 
-        f.seek(SeekFrom::Start(cxi_start_pos + CXI_HEADER_FLAGS_OFFSET))?;
-        let mut flags = [0u8; 8];
-        f.read_exact(&mut flags)?;
-        let flags = NCCHFlags::try_from(flags)?;
-        let security_flags = flags.security;
-        if !security_flags.is_not_encrypted() {
-            return Err(CXIParsingError::FileEncrypted.into());
-        }
+        let read_title_id = |offset: usize| {
+            TitleId(u64::from_le_bytes(
+                header[offset..offset + 8].try_into().unwrap(),
+            ))
+        };
+
+        let product_code =
+            &header[CXI_HEADER_PRODUCT_CODE_OFFSET..CXI_HEADER_PRODUCT_CODE_OFFSET + 0x10];
+        let product_code_len = product_code
+            .iter()
+            .position(|c| *c == b'\0')
+            .unwrap_or(0x10);
+        let product_code = String::from_utf8_lossy(&product_code[..product_code_len]).to_string();
 
-        f.seek(SeekFrom::Start(
-            cxi_start_pos + CXI_HEADER_EXEFS_OFFSET_VALUE,
-        ))?;
+        Ok(NCCHHeader {
+            partition_id: read_title_id(CXI_HEADER_PARTITION_ID_OFFSET),
+            program_id: read_title_id(CXI_HEADER_PROGRAM_ID_OFFSET),
+            product_code,
+        })
+    }
 
-        let mut exefs_offset = [0u8; 4];
-        f.read_exact(&mut exefs_offset)?;
-        let exefs_offset: u64 = u32::from_le_bytes(exefs_offset).into(); // in media units
-        let exefs_offset = exefs_offset * CXI_MEDIA_UNIT_SIZE;
+    pub fn title_kind(&self) -> TitleKind {
+        self.program_id.kind_with_product_code(&self.product_code)
+    }
+}
 
-        let mut _exefs_size = [0u8; 4];
-        f.read_exact(&mut _exefs_size)?;
+/// Reads a file from the ExeFS of the CXI at the current position, decrypting it if needed
+pub(super) fn read_exefs_file<T: Read + Seek>(
+    f: &mut T,
+    file_name: &'static str,
+) -> Result<Vec<u8>, N3DSParsingError> {
+    read_exefs_file_with_keys(f, file_name, KeyStore::from_default_path)
+}
+
+/// The key store is only loaded when the NCCH is encrypted with keys that need it
+fn read_exefs_file_with_keys<T: Read + Seek>(
+    f: &mut T,
+    file_name: &'static str,
+    load_key_store: impl FnOnce() -> Result<KeyStore, N3DSCryptoError>,
+) -> Result<Vec<u8>, N3DSParsingError> {
+    const CXI_HEADER_SIZE: usize = 0x200;
+    const CXI_HEADER_MAGIC_OFFSET: u64 = 0x100;
+    const CXI_HEADER_FLAGS_OFFSET: u64 = 0x188;
+    const CXI_HEADER_EXEFS_OFFSET_VALUE: u64 = 0x1A0;
+    const CXI_MEDIA_UNIT_SIZE: u64 = 0x200;
+    const CXI_MAGIC_STR: &str = "NCCH";
+
+    let cxi_start_pos = f.stream_position()?;
+
+    f.seek(SeekFrom::Start(cxi_start_pos + CXI_HEADER_MAGIC_OFFSET))?;
+    let mut cxi_magic = [0u8; 4];
+    f.read_exact(&mut cxi_magic)?;
+    if CXI_MAGIC_STR.as_bytes() != cxi_magic {
+        return Err(N3DSParsingError::FileMagicNotFound(
+            CXI_MAGIC_STR,
+            cxi_magic,
+        ));
+    }
+
+    f.seek(SeekFrom::Start(cxi_start_pos + CXI_HEADER_FLAGS_OFFSET))?;
+    let mut flags = [0u8; 8];
+    f.read_exact(&mut flags)?;
+    let flags = NCCHFlags::try_from(flags)?;
+
+    f.seek(SeekFrom::Start(
+        cxi_start_pos + CXI_HEADER_EXEFS_OFFSET_VALUE,
+    ))?;
+
+    let mut exefs_offset = [0u8; 4];
+    f.read_exact(&mut exefs_offset)?;
+    let exefs_offset: u64 = u32::from_le_bytes(exefs_offset).into(); // in media units
+    let exefs_offset = exefs_offset * CXI_MEDIA_UNIT_SIZE;
+    // CFAs, such as DLCs and manuals, only have a RomFS
+    if exefs_offset == 0 {
+        return Err(CXIParsingError::NoExeFS.into());
+    }
 
-        f.seek(SeekFrom::Start(cxi_start_pos + exefs_offset))?;
-        Self::from_exefs(f)
+    let mut exefs_size = [0u8; 4];
+    f.read_exact(&mut exefs_size)?;
+    let exefs_size: u64 = u32::from_le_bytes(exefs_size).into(); // in media units
+    // The header isn't trusted either, the ExeFS can't go past the end of the stream
+    let stream_end = f.seek(SeekFrom::End(0))?;
+    let exefs_size = (exefs_size * CXI_MEDIA_UNIT_SIZE)
+        .min(stream_end.saturating_sub(cxi_start_pos + exefs_offset));
+
+    let crypto = if flags.security.is_not_encrypted() {
+        None
+    } else {
+        let seed_database = if flags
+            .security
+            .contains(NCCHSecurityFlags::NEW_KEY_Y_GENERATOR)
+        {
+            // Only the secondary key needs the seed, so the icon can do without it
+            SeedDatabase::from_default_paths()
+                .inspect_err(|err| eprintln!("Failed to load seeds: {err}"))
+                .ok()
+        } else {
+            None
+        };
+
+        f.seek(SeekFrom::Start(cxi_start_pos))?;
+        let mut header = [0u8; CXI_HEADER_SIZE];
+        f.read_exact(&mut header)?;
+        Some(NCCHCrypto::new(
+            &header,
+            &flags,
+            exefs_offset,
+            load_key_store,
+            seed_database.as_ref(),
+        )?)
+    };
+
+    f.seek(SeekFrom::Start(cxi_start_pos + exefs_offset))?;
+    read_file_from_exefs(f, exefs_size, crypto.as_ref(), file_name)
+}
+
+fn read_file_from_exefs<T: Read + Seek>(
+    f: &mut T,
+    exefs_size: u64,
+    crypto: Option<&NCCHCrypto>,
+    file_name: &'static str,
+) -> Result<Vec<u8>, N3DSParsingError> {
+    const EXEFS_FILE_HEADERS_BLOCK_SIZE: usize = 0xA0;
+    const EXEFS_HEADER_TOTAL_SIZE: u64 = 0x200;
+
+    let exefs_start_pos = f.stream_position()?;
+
+    let mut file_headers = [0u8; EXEFS_FILE_HEADERS_BLOCK_SIZE];
+    f.read_exact(&mut file_headers)?;
+    if let Some(crypto) = crypto {
+        crypto
+            .exefs_cipher(None)?
+            .apply_keystream(0, &mut file_headers);
+    }
+
+    let file = file_headers
+        .chunks_exact(16)
+        .filter_map(|chunk| ExeFSFileHeader::from_bytes(chunk.try_into().unwrap()))
+        .find(|item| item.file_name() == file_name.as_bytes())
+        .ok_or(CXIParsingError::ExeFSFileNotFound(file_name))?;
+
+    let file_offset = EXEFS_HEADER_TOTAL_SIZE + u64::from(file.file_offset);
+    if file_offset + u64::from(file.file_size) > exefs_size {
+        return Err(CXIParsingError::ExeFSFileOutOfBounds(file_name).into());
+    }
+
+    f.seek(SeekFrom::Start(exefs_start_pos + file_offset))?;
+    let mut file_bytes = vec![0u8; file.file_size as usize];
+    f.read_exact(&mut file_bytes)?;
+
+    // Only the requested file gets decrypted, the rest of the ExeFS is left alone
+    if let Some(crypto) = crypto {
+        crypto
+            .exefs_cipher(Some(file.file_name()))?
+            .apply_keystream(file_offset, &mut file_bytes);
+    }
+    Ok(file_bytes)
+}
+
+impl Smdh {
+    pub fn from_cxi<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        Self::from_cxi_with_keys(f, KeyStore::from_default_path)
     }
 
-    pub fn from_exefs<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
-        const EXEFS_FILE_HEADERS_BLOCK_SIZE: usize = 0xA0;
-        const EXEFS_HEADER_TOTAL_SIZE: u64 = 0x200;
+    fn from_cxi_with_keys<T: Read + Seek>(
+        f: &mut T,
+        load_key_store: impl FnOnce() -> Result<KeyStore, N3DSCryptoError>,
+    ) -> Result<Self, N3DSParsingError> {
         const ICON_FILENAME_STR: &str = "icon";
 
-        let exefs_start_pos = f.stream_position()?;
+        let cxi_start_pos = f.stream_position()?;
+        let header = NCCHHeader::from_cxi(f)?;
+        f.seek(SeekFrom::Start(cxi_start_pos))?;
+
+        // DLCs are CFAs without an ExeFS, while updates don't ship an icon in theirs
+        let icon =
+            read_exefs_file_with_keys(f, ICON_FILENAME_STR, load_key_store).map_err(|err| {
+                if err.is_missing_icon() && header.program_id.kind().is_base_title_dependent() {
+                    N3DSParsingError::TitleHasNoIcon(header.program_id)
+                } else {
+                    err
+                }
+            })?;
+        Self::from_smdh(&mut Cursor::new(icon))
+    }
+}
+
+impl Cbmd {
+    pub fn from_cxi<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        const BANNER_FILENAME_STR: &str = "banner";
+
+        let banner = read_exefs_file(f, BANNER_FILENAME_STR)?;
+        Self::from_cbmd(&mut Cursor::new(banner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::n3ds::crypto::{scramble_key, AesCtrCipher, AesKey};
+
+    const PROGRAM_ID: u64 = 0x0004_0000_0012_3400;
+    const KEY_Y: AesKey = [
+        0xF0, 0xE1, 0xD2, 0xC3, 0xB4, 0xA5, 0x96, 0x87, 0x78, 0x69, 0x5A, 0x4B, 0x3C, 0x2D, 0x1E,
+        0x0F,
+    ];
+    const KEY_FILE: &str = "slot0x2CKeyX=000102030405060708090A0B0C0D0E0F\n\
+                            generator=0123456789ABCDEFFEDCBA9876543210\n";
+
+    fn synthetic_smdh() -> Vec<u8> {
+        let mut smdh = vec![0u8; Smdh::SIZE];
+        smdh[..4].copy_from_slice(b"SMDH");
+        smdh[0x201C..0x2020].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+        // The first large icon pixel is pure red in RGB565
+        smdh[0x24C0..0x24C2].copy_from_slice(&0xF800u16.to_le_bytes());
+        smdh
+    }
+
+    /// Builds a version 2 NCCH with an ExeFS holding only an icon, the ExeFS header and icon
+    /// being encrypted with `key` when the NCCH isn't flagged as unencrypted
+    fn synthetic_ncch(security_flags: u8, key: AesKey) -> Vec<u8> {
+        const EXEFS_OFFSET: usize = 0x200;
+
+        let smdh = synthetic_smdh();
+        let mut ncch = vec![0u8; EXEFS_OFFSET + 0x200 + smdh.len()];
+
+        ncch[..0x10].copy_from_slice(&KEY_Y);
+        ncch[0x100..0x104].copy_from_slice(b"NCCH");
+        ncch[0x108..0x110].copy_from_slice(&PROGRAM_ID.to_le_bytes());
+        ncch[0x112..0x114].copy_from_slice(&2u16.to_le_bytes());
+        ncch[0x118..0x120].copy_from_slice(&PROGRAM_ID.to_le_bytes());
+        ncch[0x188 + 5] = 0x03;
+        ncch[0x188 + 7] = security_flags;
+        ncch[0x1A0..0x1A4].copy_from_slice(&1u32.to_le_bytes());
+        let exefs_size = (0x200 + smdh.len()).div_ceil(0x200) as u32;
+        ncch[0x1A4..0x1A8].copy_from_slice(&exefs_size.to_le_bytes());
+
+        let exefs = &mut ncch[EXEFS_OFFSET..];
+        exefs[..4].copy_from_slice(b"icon");
+        exefs[0xC..0x10].copy_from_slice(&(smdh.len() as u32).to_le_bytes());
+        exefs[0x200..].copy_from_slice(&smdh);
+
+        if security_flags & 0x04 == 0 {
+            let mut counter = [0u8; 16];
+            for (counter_byte, partition_id_byte) in
+                counter[..8].iter_mut().zip(PROGRAM_ID.to_be_bytes())
+            {
+                *counter_byte = partition_id_byte;
+            }
+            counter[8] = 0x02;
+            AesCtrCipher::new(key, counter).apply_keystream(0, exefs);
+        }
 
-        let mut file_headers = [0u8; EXEFS_FILE_HEADERS_BLOCK_SIZE];
-        f.read_exact(&mut file_headers)?;
+        ncch
+    }
+
+    fn assert_synthetic_smdh(smdh: &Smdh) {
+        assert_eq!(smdh.match_maker_id, 0x1234_5678);
+        assert_eq!(
+            smdh.icon.large_icon.get_pixel(0, 0).0,
+            [0xFF, 0x00, 0x00, 0xFF]
+        );
+    }
+
+    #[test]
+    fn unencrypted_icon() {
+        let ncch = synthetic_ncch(0x04, [0u8; 16]);
+        let smdh = Smdh::from_cxi_with_keys(&mut Cursor::new(ncch), || {
+            panic!("an unencrypted NCCH doesn't need keys")
+        })
+        .unwrap();
+        assert_synthetic_smdh(&smdh);
+    }
+
+    #[test]
+    fn fixed_zero_key_icon() {
+        let ncch = synthetic_ncch(0x01, [0u8; 16]);
+        // Non-system titles using a fixed key don't need the key file
+        let smdh = Smdh::from_cxi(&mut Cursor::new(ncch)).unwrap();
+        assert_synthetic_smdh(&smdh);
+    }
+
+    #[test]
+    fn scrambled_key_icon() {
+        let key_store = KeyStore::from_contents(KEY_FILE);
+        let primary_key = scramble_key(
+            &key_store.key_x(0x2C).unwrap(),
+            &KEY_Y,
+            &key_store.key("generator").unwrap(),
+        );
+        let ncch = synthetic_ncch(0x00, primary_key);
+
+        let smdh = Smdh::from_cxi_with_keys(&mut Cursor::new(&ncch), || {
+            Ok(KeyStore::from_contents(KEY_FILE))
+        })
+        .unwrap();
+        assert_synthetic_smdh(&smdh);
+
+        // A different KeyX doesn't decrypt the ExeFS header
+        let result = Smdh::from_cxi_with_keys(&mut Cursor::new(&ncch), || {
+            Ok(KeyStore::from_contents(
+                &KEY_FILE.replace("0405060708", "0405060709"),
+            ))
+        });
+        assert!(result.is_err());
+
+        let result = Smdh::from_cxi_with_keys(&mut Cursor::new(&ncch), || {
+            Err(N3DSCryptoError::KeyFileNotFound("aes_keys.txt".into()))
+        });
+        assert!(matches!(
+            result,
+            Err(N3DSParsingError::CryptoError(
+                N3DSCryptoError::KeyFileNotFound(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn update_without_icon() {
+        const UPDATE_ID: u64 = 0x0004_000E_0012_3400;
+        let update_ncch = |security_flags: u8| {
+            let mut ncch = synthetic_ncch(security_flags, [0u8; 16]);
+            ncch[0x118..0x120].copy_from_slice(&UPDATE_ID.to_le_bytes());
+            ncch
+        };
+        let no_keys = || Err(N3DSCryptoError::KeyFileNotFound("aes_keys.txt".into()));
+
+        let mut ncch = update_ncch(0x04);
+        ncch[0x200..0x204].copy_from_slice(b"logo");
+        assert!(matches!(
+            Smdh::from_cxi_with_keys(&mut Cursor::new(&ncch), no_keys),
+            Err(N3DSParsingError::TitleHasNoIcon(TitleId(UPDATE_ID)))
+        ));
+
+        let mut ncch = update_ncch(0x04);
+        ncch[0x1A0..0x1A4].fill(0);
+        assert!(matches!(
+            Smdh::from_cxi_with_keys(&mut Cursor::new(&ncch), no_keys),
+            Err(N3DSParsingError::TitleHasNoIcon(TitleId(UPDATE_ID)))
+        ));
+
+        // Failing to decrypt the ExeFS doesn't mean there's no icon
+        let ncch = update_ncch(0x00);
+        assert!(matches!(
+            Smdh::from_cxi_with_keys(&mut Cursor::new(&ncch), no_keys),
+            Err(N3DSParsingError::CryptoError(
+                N3DSCryptoError::KeyFileNotFound(_)
+            ))
+        ));
+    }
 
-        let icon_file = file_headers
-            .chunks_exact(16)
-            .filter_map(|chunk| ExeFSFileHeader::from_bytes(chunk.try_into().unwrap()))
-            .find(|item| item.file_name() == ICON_FILENAME_STR.as_bytes())
-            .ok_or(CXIParsingError::ExeFSIconFileNotFound)?;
+    #[test]
+    fn oversized_exefs_file() {
+        let no_keys = || panic!("an unencrypted NCCH doesn't need keys");
 
-        let icon_pos = exefs_start_pos + EXEFS_HEADER_TOTAL_SIZE + u64::from(icon_file.file_offset);
+        // Bigger than the ExeFS, which is cut short by the end of the file
+        let mut ncch = synthetic_ncch(0x04, [0u8; 16]);
+        ncch[0x20C..0x210].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Smdh::from_cxi_with_keys(&mut Cursor::new(&ncch), no_keys),
+            Err(N3DSParsingError::CXIParsingError(
+                CXIParsingError::ExeFSFileOutOfBounds("icon")
+            ))
+        ));
 
-        f.seek(SeekFrom::Start(icon_pos))?;
-        Self::from_smdh(f)
+        // Within the file, but past the ExeFS size given by the header
+        let mut ncch = synthetic_ncch(0x04, [0u8; 16]);
+        ncch[0x1A4..0x1A8].copy_from_slice(&1u32.to_le_bytes());
+        ncch.extend_from_slice(&[0u8; 0x4000]);
+        assert!(matches!(
+            Smdh::from_cxi_with_keys(&mut Cursor::new(&ncch), no_keys),
+            Err(N3DSParsingError::CXIParsingError(
+                CXIParsingError::ExeFSFileOutOfBounds("icon")
+            ))
+        ));
     }
 }
@@ -0,0 +1,283 @@
+use sha2::{Digest, Sha256};
+
+use crate::n3ds::{
+    crypto::{AesCtrCipher, AesKey, KeyStore, Seed, SeedDatabase},
+    errors::N3DSCryptoError,
+    structures::{TitleCategory, TitleId},
+};
+
+use super::ncch_flags::{NCCHCryptoMethodFlags, NCCHFlags, NCCHSecurityFlags};
+
+/*
+ * NCCH contents are encrypted with AES-128-CTR using two keys:
+ * the primary key protects the ExHeader, the ExeFS header and the ExeFS icon and banner,
+ * while the secondary key, selected by the crypto method, protects the rest of the ExeFS
+ * and the RomFS.
+ * Both keys are scrambled from a keyslot's KeyX and a KeyY taken from the NCCH signature,
+ * unless the NCCH uses a fixed key instead.
+ * With seed crypto, the secondary KeyY is the first half of SHA256(KeyY || seed),
+ * which means the icon can still be decrypted without the seed.
+ *
+ * See https://www.3dbrew.org/wiki/NCCH#Encryption for more info
+*/
+
+const NCCH_HEADER_KEY_Y_OFFSET: usize = 0x000;
+const NCCH_HEADER_PARTITION_ID_OFFSET: usize = 0x108;
+const NCCH_HEADER_VERSION_OFFSET: usize = 0x112;
+const NCCH_HEADER_SEED_CHECK_OFFSET: usize = 0x114;
+const NCCH_HEADER_PROGRAM_ID_OFFSET: usize = 0x118;
+
+const NCCH_PRIMARY_KEY_X_SLOT: u8 = 0x2C;
+const NCCH_EXEFS_COUNTER_TYPE: u8 = 0x02;
+
+#[derive(Debug)]
+pub struct NCCHCrypto {
+    partition_id: [u8; 8],
+    version: u16,
+    exefs_offset: u64,
+    primary_key: AesKey,
+    secondary_key: Result<AesKey, N3DSCryptoError>,
+}
+
+impl NCCHCryptoMethodFlags {
+    pub fn key_x_slot(&self) -> u8 {
+        match self {
+            NCCHCryptoMethodFlags::Initial => 0x2C,
+            NCCHCryptoMethodFlags::KeyY => 0x25,
+            NCCHCryptoMethodFlags::New3DSArm9Loader => 0x18,
+            NCCHCryptoMethodFlags::New3DSArmLoaderChanged => 0x1B,
+        }
+    }
+}
+
+impl NCCHCrypto {
+    pub fn new(
+        header: &[u8; 0x200],
+        flags: &NCCHFlags,
+        exefs_offset: u64,
+        load_key_store: impl FnOnce() -> Result<KeyStore, N3DSCryptoError>,
+        seed_database: Option<&SeedDatabase>,
+    ) -> Result<Self, N3DSCryptoError> {
+        let key_y: AesKey = header[NCCH_HEADER_KEY_Y_OFFSET..NCCH_HEADER_KEY_Y_OFFSET + 0x10]
+            .try_into()
+            .unwrap();
+        let partition_id: [u8; 8] = header
+            [NCCH_HEADER_PARTITION_ID_OFFSET..NCCH_HEADER_PARTITION_ID_OFFSET + 8]
+            .try_into()
+            .unwrap();
+        let version = u16::from_le_bytes(
+            header[NCCH_HEADER_VERSION_OFFSET..NCCH_HEADER_VERSION_OFFSET + 2]
+                .try_into()
+                .unwrap(),
+        );
+        let program_id = TitleId(u64::from_le_bytes(
+            header[NCCH_HEADER_PROGRAM_ID_OFFSET..NCCH_HEADER_PROGRAM_ID_OFFSET + 8]
+                .try_into()
+                .unwrap(),
+        ));
+
+        let (primary_key, secondary_key) =
+            if flags.security.contains(NCCHSecurityFlags::FIXED_CRYPTO_KEY) {
+                // System titles use the fixed system key, everything else uses a zero key
+                // and doesn't need a key file at all
+                let fixed_key = if program_id.category().contains(TitleCategory::SYSTEM) {
+                    load_key_store()?.key("fixedSystemKey")?
+                } else {
+                    [0u8; 16]
+                };
+                (fixed_key, Ok(fixed_key))
+            } else {
+                let key_store = load_key_store()?;
+                let primary_key = key_store.scrambled_key(NCCH_PRIMARY_KEY_X_SLOT, &key_y)?;
+                // The secondary key is only needed for the code and RomFS,
+                // so a missing KeyX or seed must not prevent extracting the icon
+                let secondary_key_y = if flags
+                    .security
+                    .contains(NCCHSecurityFlags::NEW_KEY_Y_GENERATOR)
+                {
+                    let seed_check: [u8; 4] = header
+                        [NCCH_HEADER_SEED_CHECK_OFFSET..NCCH_HEADER_SEED_CHECK_OFFSET + 4]
+                        .try_into()
+                        .unwrap();
+                    seed_database
+                        .and_then(|seed_database| seed_database.seed(program_id))
+                        .ok_or(N3DSCryptoError::MissingSeed(program_id))
+                        .and_then(|seed| seeded_key_y(&key_y, &seed, program_id, seed_check))
+                } else {
+                    Ok(key_y)
+                };
+                let secondary_key = secondary_key_y.and_then(|secondary_key_y| {
+                    key_store.scrambled_key(flags.crypto_method.key_x_slot(), &secondary_key_y)
+                });
+                (primary_key, secondary_key)
+            };
+
+        if let Err(err) = &secondary_key {
+            eprintln!("Only the NCCH icon can be decrypted: {err}");
+        }
+
+        Ok(NCCHCrypto {
+            partition_id,
+            version,
+            exefs_offset,
+            primary_key,
+            secondary_key,
+        })
+    }
+
+    /// The ExeFS header, icon and banner use the primary key, other ExeFS files use the
+    /// secondary key. The returned cipher expects offsets relative to the ExeFS start
+    pub fn exefs_cipher(&self, file_name: Option<&[u8]>) -> Result<AesCtrCipher, N3DSCryptoError> {
+        let key = match file_name {
+            None | Some(b"icon" | b"banner") => self.primary_key,
+            Some(_) => self.secondary_key.clone()?,
+        };
+
+        Ok(AesCtrCipher::new(
+            key,
+            self.counter(NCCH_EXEFS_COUNTER_TYPE, self.exefs_offset),
+        ))
+    }
+
+    fn counter(&self, section_type: u8, section_offset: u64) -> [u8; 16] {
+        let mut counter = [0u8; 16];
+
+        if self.version == 1 {
+            counter[..8].copy_from_slice(&self.partition_id);
+            // Only the lower 32 bits of the section offset are part of the counter
+            #[allow(clippy::cast_possible_truncation)]
+            let section_offset = section_offset as u32;
+            counter[12..].copy_from_slice(&section_offset.to_be_bytes());
+        } else {
+            // NCCH versions 0 and 2
+            for (counter_byte, partition_id_byte) in
+                counter[..8].iter_mut().zip(self.partition_id.iter().rev())
+            {
+                *counter_byte = *partition_id_byte;
+            }
+            counter[8] = section_type;
+        }
+
+        counter
+    }
+}
+
+/// Checks the seed against the NCCH seed check hash (the first 4 bytes of
+/// SHA256(seed || program ID)) and derives the seeded KeyY
+fn seeded_key_y(
+    key_y: &AesKey,
+    seed: &Seed,
+    program_id: TitleId,
+    seed_check: [u8; 4],
+) -> Result<AesKey, N3DSCryptoError> {
+    let seed_hash = Sha256::new()
+        .chain_update(seed)
+        .chain_update(program_id.0.to_le_bytes())
+        .finalize();
+    if seed_hash[..4] != seed_check {
+        return Err(N3DSCryptoError::SeedMismatch(program_id));
+    }
+
+    let key_y_hash = Sha256::new()
+        .chain_update(key_y)
+        .chain_update(seed)
+        .finalize();
+    Ok(key_y_hash[..16].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARTITION_ID: [u8; 8] = [0x00, 0x34, 0x12, 0x00, 0x00, 0x00, 0x04, 0x00];
+
+    fn ncch_crypto(version: u16, secondary_key: Result<AesKey, N3DSCryptoError>) -> NCCHCrypto {
+        NCCHCrypto {
+            partition_id: PARTITION_ID,
+            version,
+            exefs_offset: 0x1234_5600,
+            primary_key: [0x11; 16],
+            secondary_key,
+        }
+    }
+
+    #[test]
+    fn counter_for_versions_0_and_2() {
+        let expected = [
+            0x00, 0x04, 0x00, 0x00, 0x00, 0x12, 0x34, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        for version in [0, 2] {
+            let crypto = ncch_crypto(version, Ok([0x22; 16]));
+            assert_eq!(
+                crypto.counter(NCCH_EXEFS_COUNTER_TYPE, 0x1234_5600),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn counter_for_version_1() {
+        let crypto = ncch_crypto(1, Ok([0x22; 16]));
+        assert_eq!(
+            crypto.counter(NCCH_EXEFS_COUNTER_TYPE, 0x1_1234_5600),
+            [
+                0x00, 0x34, 0x12, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x12, 0x34,
+                0x56, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn exefs_cipher_key_selection() {
+        let crypto = ncch_crypto(2, Err(N3DSCryptoError::MissingKey("slot0x25KeyX".into())));
+        let counter = crypto.counter(NCCH_EXEFS_COUNTER_TYPE, crypto.exefs_offset);
+        let keystream = |cipher: AesCtrCipher| {
+            let mut data = [0u8; 0x10];
+            cipher.apply_keystream(0, &mut data);
+            data
+        };
+
+        // The header, icon and banner only need the primary key
+        for file_name in [None, Some(&b"icon"[..]), Some(&b"banner"[..])] {
+            let cipher = crypto.exefs_cipher(file_name).unwrap();
+            assert_eq!(
+                keystream(cipher),
+                keystream(AesCtrCipher::new([0x11; 16], counter))
+            );
+        }
+        assert!(matches!(
+            crypto.exefs_cipher(Some(b".code")),
+            Err(N3DSCryptoError::MissingKey(_))
+        ));
+
+        let crypto = ncch_crypto(2, Ok([0x22; 16]));
+        let cipher = crypto.exefs_cipher(Some(b".code")).unwrap();
+        assert_eq!(
+            keystream(cipher),
+            keystream(AesCtrCipher::new([0x22; 16], counter))
+        );
+    }
+
+    #[test]
+    fn seeded_key_y_checks_the_seed() {
+        let key_y = [
+            0xF0, 0xE1, 0xD2, 0xC3, 0xB4, 0xA5, 0x96, 0x87, 0x78, 0x69, 0x5A, 0x4B, 0x3C, 0x2D,
+            0x1E, 0x0F,
+        ];
+        let seed: Seed = core::array::from_fn(|i| 0x10 + i as u8);
+        let program_id = TitleId(0x0004_0000_0012_3400);
+
+        assert_eq!(
+            seeded_key_y(&key_y, &seed, program_id, [0x55, 0xC6, 0x68, 0x42]).unwrap(),
+            [
+                0x5A, 0x91, 0xA9, 0xB9, 0xB2, 0x26, 0x74, 0xDE, 0xB9, 0xF7, 0xFF, 0x45, 0x18, 0x33,
+                0xD5, 0x8F,
+            ]
+        );
+        assert!(matches!(
+            seeded_key_y(&key_y, &seed, program_id, [0x55, 0xC6, 0x68, 0x43]),
+            Err(N3DSCryptoError::SeedMismatch(title_id)) if title_id == program_id
+        ));
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::n3ds::{
+    errors::{CIAParsingError, CXIParsingError, N3DSParsingError},
+    structures::{CIAContainer, CIAIcon, NCCHHeader, Smdh, TitleId, TitleKind},
+};
+use crate::nds::structures::NDSBannerChecksumMode;
+
+/*
+ * Updates and DLCs have no icon of their own, but they share the unique ID of their base title,
+ * so the icon can be borrowed from the base title when it's stored in the same directory.
+ *
+ * Finding it means reading the title ID of every 3DS file in the directory,
+ * therefore title IDs are cached along with the size and modification time of each file,
+ * at ~/.cache/bign-handheld-thumbnailer/title-ids
+*/
+
+const SIBLING_EXTENSIONS: [&str; 5] = ["cia", "3ds", "cci", "cxi", "cfa"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CachedTitleId {
+    size: u64,
+    modified: u64,
+    /// Files that couldn't be parsed are cached too, so they aren't parsed again
+    title_id: Option<TitleId>,
+}
+
+#[derive(Debug, Default)]
+struct TitleIdCache {
+    path: Option<PathBuf>,
+    entries: HashMap<PathBuf, CachedTitleId>,
+    is_dirty: bool,
+}
+
+impl TitleIdCache {
+    fn default_path() -> Option<PathBuf> {
+        let cache_dir = env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|cache_dir| cache_dir.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+        Some(cache_dir.join(env!("CARGO_PKG_NAME")).join("title-ids"))
+    }
+
+    /// Each line holds the title ID (or - if unknown), size, modification time and path
+    /// of a file, separated by tabs
+    fn load() -> Self {
+        let Some(path) = Self::default_path() else {
+            return Self::default();
+        };
+
+        let entries = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(4, '\t');
+                let title_id = match fields.next()? {
+                    "-" => None,
+                    title_id => Some(TitleId(u64::from_str_radix(title_id, 16).ok()?)),
+                };
+                let size = fields.next()?.parse().ok()?;
+                let modified = fields.next()?.parse().ok()?;
+                let file_path = PathBuf::from(fields.next()?);
+
+                let entry = CachedTitleId {
+                    size,
+                    modified,
+                    title_id,
+                };
+                Some((file_path, entry))
+            })
+            .collect();
+
+        TitleIdCache {
+            path: Some(path),
+            entries,
+            is_dirty: false,
+        }
+    }
+
+    fn title_id(&mut self, path: &Path) -> Option<TitleId> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_secs();
+
+        if let Some(entry) = self.entries.get(path)
+            && entry.size == metadata.len()
+            && entry.modified == modified
+        {
+            return entry.title_id;
+        }
+
+        let title_id = read_sibling_title_id(path).ok();
+        let entry = CachedTitleId {
+            size: metadata.len(),
+            modified,
+            title_id,
+        };
+        self.entries.insert(path.to_path_buf(), entry);
+        self.is_dirty = true;
+        title_id
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = self.path.as_deref().filter(|_| self.is_dirty) else {
+            return Ok(());
+        };
+
+        let mut contents = String::new();
+        for (file_path, entry) in &self.entries {
+            // Paths that aren't valid UTF-8 can't be stored and are simply read again next time,
+            // while files that were removed since are dropped from the cache
+            let Some(file_path) = file_path.to_str().filter(|_| file_path.exists()) else {
+                continue;
+            };
+            let title_id = entry
+                .title_id
+                .map_or_else(|| "-".to_string(), |title_id| title_id.to_string());
+            contents.push_str(&format!(
+                "{title_id}\t{}\t{}\t{file_path}\n",
+                entry.size, entry.modified
+            ));
+        }
+
+        if let Some(cache_dir) = path.parent() {
+            fs::create_dir_all(cache_dir)?;
+        }
+        // Written to a temporary file first, so concurrent thumbnailers never read a partial cache
+        let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        File::create(&temp_path)?.write_all(contents.as_bytes())?;
+        fs::rename(temp_path, path)
+    }
+}
+
+fn sibling_extension(path: &Path) -> Option<String> {
+    let extension = path.extension().and_then(OsStr::to_str)?.to_lowercase();
+    SIBLING_EXTENSIONS
+        .contains(&extension.as_str())
+        .then_some(extension)
+}
+
+fn read_sibling_title_id(path: &Path) -> Result<TitleId, N3DSParsingError> {
+    let mut f = File::open(path)?;
+    match sibling_extension(path).as_deref() {
+        Some("cia") => Ok(CIAContainer::from_file(&mut f)?
+            .title_metadata(&mut f)?
+            .title_id),
+        Some("3ds" | "cci") => Ok(NCCHHeader::from_cci(&mut f)?.program_id),
+        _ => Ok(NCCHHeader::from_cxi(&mut f)?.program_id),
+    }
+}
+
+fn read_sibling_icon(path: &Path) -> Result<Smdh, N3DSParsingError> {
+    let mut f = File::open(path)?;
+    match sibling_extension(path).as_deref() {
+        Some("cia") => match CIAIcon::from_cia(&mut f, NDSBannerChecksumMode::Lenient)? {
+            CIAIcon::Ctr(smdh) => Ok(smdh),
+            CIAIcon::Twl(_) => Err(CIAParsingError::NoIconAvailable(
+                CXIParsingError::NoCXIContent,
+            ))?,
+        },
+        Some("3ds" | "cci") => Smdh::from_cci(&mut f),
+        _ => Smdh::from_cxi(&mut f),
+    }
+}
+
+impl Smdh {
+    /// Looks for the base title of an update or DLC among the 3DS files next to it,
+    /// preferring full applications over demos
+    pub fn from_base_title_sibling(
+        path: &Path,
+        title_id: TitleId,
+    ) -> Result<Self, N3DSParsingError> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => fs::canonicalize(dir)?,
+            _ => env::current_dir()?,
+        };
+
+        let mut cache = TitleIdCache::load();
+        let mut candidates = fs::read_dir(&dir)?
+            .filter_map(Result::ok)
+            .filter(|entry| Some(entry.file_name().as_os_str()) != path.file_name())
+            .map(|entry| entry.path())
+            .filter(|sibling_path| sibling_extension(sibling_path).is_some())
+            .filter_map(|sibling_path| Some((cache.title_id(&sibling_path)?, sibling_path)))
+            .filter(|(sibling_title_id, _)| {
+                sibling_title_id.platform() == title_id.platform()
+                    && sibling_title_id.unique_id() == title_id.unique_id()
+                    && !sibling_title_id.kind().is_base_title_dependent()
+            })
+            .collect::<Vec<_>>();
+        if let Err(err) = cache.save() {
+            eprintln!("Failed to save title ID cache: {err}");
+        }
+
+        candidates
+            .sort_by_key(|(sibling_title_id, _)| sibling_title_id.kind() != TitleKind::Application);
+        for (sibling_title_id, sibling_path) in candidates {
+            match read_sibling_icon(&sibling_path) {
+                Ok(smdh) => {
+                    eprintln!(
+                        "Borrowing icon from base title {sibling_title_id} at {}",
+                        sibling_path.display()
+                    );
+                    return Ok(smdh);
+                }
+                Err(err) => eprintln!(
+                    "Failed to extract icon from {}: {err}",
+                    sibling_path.display()
+                ),
+            }
+        }
+
+        Err(N3DSParsingError::BaseTitleNotFound(title_id))
+    }
+}
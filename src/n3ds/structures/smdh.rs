@@ -0,0 +1,315 @@
+use bitflags::bitflags;
+use std::io::{Read, Seek};
+
+use crate::n3ds::errors::N3DSParsingError;
+use crate::utils::locale::{self, Locale, LocalizedTitle, TitleLanguage};
+
+use super::SMDHIcon;
+
+/*
+ * The SMDH is 0x36C0 bytes long and is composed of:
+ * 0x0000 header (magic and version)
+ * 0x0008 16 application titles of 0x200 bytes each
+ * 0x2008 application settings (ratings, region lockout, match maker IDs, flags, etc.)
+ * 0x2040 small icon (24x24)
+ * 0x24C0 large icon (48x48)
+ *
+ * See https://www.3dbrew.org/wiki/SMDH for more info
+*/
+
+#[derive(Debug)]
+pub struct Smdh {
+    pub version: u16,
+    pub titles: Vec<SmdhTitle>,
+    pub age_ratings: Vec<SmdhAgeRating>,
+    pub region_lockout: SmdhRegionLockout,
+    pub match_maker_id: u32,
+    pub match_maker_bit_id: u64,
+    pub flags: SmdhFlags,
+    pub eula_version: (u8, u8),
+    pub optimal_banner_frame: f32,
+    pub cec_id: u32,
+    pub icon: SMDHIcon,
+}
+
+impl Smdh {
+    pub const SIZE: usize = 0x36C0;
+
+    pub fn from_smdh<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        let mut smdh_bytes = vec![0u8; Self::SIZE];
+        f.read_exact(&mut smdh_bytes)?;
+
+        let smdh_magic: [u8; 4] = smdh_bytes[..4].try_into().unwrap();
+        if b"SMDH" != &smdh_magic {
+            return Err(N3DSParsingError::FileMagicNotFound("SMDH", smdh_magic));
+        }
+
+        let read_u16 =
+            |offset: usize| u16::from_le_bytes(smdh_bytes[offset..offset + 2].try_into().unwrap());
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(smdh_bytes[offset..offset + 4].try_into().unwrap());
+
+        let titles = smdh_bytes[0x0008..0x2008]
+            .chunks_exact(0x200)
+            .zip(SmdhTitleLanguage::ALL)
+            .map(|(chunk, language)| SmdhTitle::from_bytes(language, chunk.try_into().unwrap()))
+            .collect();
+
+        let age_ratings = smdh_bytes[0x2008..0x2018]
+            .iter()
+            .enumerate()
+            .filter_map(|(index, rating)| SmdhAgeRating::from_byte(index, *rating))
+            .collect();
+
+        let eula_version = read_u16(0x202C).to_le_bytes();
+        let icon = SMDHIcon::from_bytes(
+            smdh_bytes[0x2040..0x24C0].try_into().unwrap(),
+            smdh_bytes[0x24C0..0x36C0].try_into().unwrap(),
+        );
+
+        Ok(Smdh {
+            version: read_u16(0x0004),
+            titles,
+            age_ratings,
+            region_lockout: SmdhRegionLockout::from_bits_retain(read_u32(0x2018)),
+            match_maker_id: read_u32(0x201C),
+            match_maker_bit_id: u64::from_le_bytes(smdh_bytes[0x2020..0x2028].try_into().unwrap()),
+            flags: SmdhFlags::from_bits_retain(read_u32(0x2028)),
+            eula_version: (eula_version[1], eula_version[0]),
+            optimal_banner_frame: f32::from_le_bytes(
+                smdh_bytes[0x2030..0x2034].try_into().unwrap(),
+            ),
+            cec_id: read_u32(0x2034),
+            icon,
+        })
+    }
+
+    /// Picks the title matching the user locale, see `locale::best_title`
+    pub fn best_title(&self) -> Option<&SmdhTitle> {
+        locale::best_title(&self.titles, locale::user_title_language())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmdhTitleLanguage {
+    Japanese,
+    English,
+    French,
+    German,
+    Italian,
+    Spanish,
+    SimplifiedChinese,
+    Korean,
+    Dutch,
+    Portuguese,
+    Russian,
+    TraditionalChinese,
+}
+
+impl SmdhTitleLanguage {
+    /// The last 4 of the 16 title entries are unused
+    pub const ALL: [SmdhTitleLanguage; 12] = [
+        SmdhTitleLanguage::Japanese,
+        SmdhTitleLanguage::English,
+        SmdhTitleLanguage::French,
+        SmdhTitleLanguage::German,
+        SmdhTitleLanguage::Italian,
+        SmdhTitleLanguage::Spanish,
+        SmdhTitleLanguage::SimplifiedChinese,
+        SmdhTitleLanguage::Korean,
+        SmdhTitleLanguage::Dutch,
+        SmdhTitleLanguage::Portuguese,
+        SmdhTitleLanguage::Russian,
+        SmdhTitleLanguage::TraditionalChinese,
+    ];
+}
+
+impl TitleLanguage for SmdhTitleLanguage {
+    const ENGLISH: Self = SmdhTitleLanguage::English;
+
+    /// Chinese is written with traditional characters in Taiwan, Hong Kong and Macau
+    fn from_locale(locale: &Locale) -> Option<Self> {
+        match &locale.language[..] {
+            "ja" => Some(SmdhTitleLanguage::Japanese),
+            "en" => Some(SmdhTitleLanguage::English),
+            "fr" => Some(SmdhTitleLanguage::French),
+            "de" => Some(SmdhTitleLanguage::German),
+            "it" => Some(SmdhTitleLanguage::Italian),
+            "es" => Some(SmdhTitleLanguage::Spanish),
+            "zh" => match locale.territory.as_deref() {
+                Some("TW" | "HK" | "MO") => Some(SmdhTitleLanguage::TraditionalChinese),
+                _ => Some(SmdhTitleLanguage::SimplifiedChinese),
+            },
+            "ko" => Some(SmdhTitleLanguage::Korean),
+            "nl" => Some(SmdhTitleLanguage::Dutch),
+            "pt" => Some(SmdhTitleLanguage::Portuguese),
+            "ru" => Some(SmdhTitleLanguage::Russian),
+            _ => None,
+        }
+    }
+}
+
+/// Each title entry has a short description (0x80 bytes), a long description (0x100 bytes)
+/// and a publisher (0x80 bytes), all of them in UTF-16
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmdhTitle {
+    pub language: SmdhTitleLanguage,
+    pub short_description: String,
+    pub long_description: String,
+    pub publisher: String,
+}
+
+impl SmdhTitle {
+    pub fn from_bytes(language: SmdhTitleLanguage, title_bytes: &[u8; 0x200]) -> Self {
+        SmdhTitle {
+            language,
+            short_description: utf16_string(&title_bytes[0x000..0x080]),
+            long_description: utf16_string(&title_bytes[0x080..0x180]),
+            publisher: utf16_string(&title_bytes[0x180..0x200]),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.short_description.is_empty() && self.long_description.is_empty()
+    }
+}
+
+impl LocalizedTitle for SmdhTitle {
+    type Language = SmdhTitleLanguage;
+
+    fn language(&self) -> SmdhTitleLanguage {
+        self.language
+    }
+
+    fn is_empty(&self) -> bool {
+        SmdhTitle::is_empty(self)
+    }
+}
+
+fn utf16_string(bytes: &[u8]) -> String {
+    let chars: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes(chunk.try_into().unwrap()))
+        .take_while(|c| *c != 0)
+        .collect();
+    String::from_utf16_lossy(&chars).trim().to_string()
+}
+
+/// The SMDH age ratings are 16 bytes, one per rating board.
+/// Each byte has bit 7 set if the rating is enabled, bit 6 set if the rating is pending,
+/// bit 5 set if there's no age restriction and the minimum age on bits 0-4
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmdhAgeRating {
+    pub board: SmdhRatingBoard,
+    pub age: u8,
+    pub is_pending: bool,
+    pub is_unrestricted: bool,
+}
+
+impl SmdhAgeRating {
+    pub fn from_byte(index: usize, rating: u8) -> Option<Self> {
+        if rating & 0x80 == 0 {
+            return None;
+        }
+        let board = SmdhRatingBoard::from_index(index)?;
+
+        Some(SmdhAgeRating {
+            board,
+            age: rating & 0x1F,
+            is_pending: rating & 0x40 != 0,
+            is_unrestricted: rating & 0x20 != 0,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmdhRatingBoard {
+    Cero,
+    Esrb,
+    Usk,
+    PegiGeneral,
+    PegiPortugal,
+    PegiBbfc,
+    Cob,
+    Grb,
+    Cgsrr,
+}
+
+impl SmdhRatingBoard {
+    pub fn from_index(index: usize) -> Option<Self> {
+        match index {
+            0 => Some(SmdhRatingBoard::Cero),
+            1 => Some(SmdhRatingBoard::Esrb),
+            3 => Some(SmdhRatingBoard::Usk),
+            4 => Some(SmdhRatingBoard::PegiGeneral),
+            6 => Some(SmdhRatingBoard::PegiPortugal),
+            7 => Some(SmdhRatingBoard::PegiBbfc),
+            8 => Some(SmdhRatingBoard::Cob),
+            9 => Some(SmdhRatingBoard::Grb),
+            10 => Some(SmdhRatingBoard::Cgsrr),
+            _ => None,
+        }
+    }
+}
+
+bitflags! {
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct SmdhRegionLockout: u32 {
+        const JAPAN = 0x01;
+        const NORTH_AMERICA = 0x02;
+        const EUROPE = 0x04;
+        const AUSTRALIA = 0x08;
+        const CHINA = 0x10;
+        const KOREA = 0x20;
+        const TAIWAN = 0x40;
+
+        const REGION_FREE = 0x7FFF_FFFF;
+    }
+}
+
+impl SmdhRegionLockout {
+    pub fn is_region_free(&self) -> bool {
+        self.contains(Self::REGION_FREE)
+    }
+}
+
+bitflags! {
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct SmdhFlags: u32 {
+        const VISIBLE = 0x0001;
+        const AUTO_BOOT = 0x0002;
+        const ALLOW_3D = 0x0004;
+        const REQUIRE_EULA = 0x0008;
+        const AUTO_SAVE_ON_EXIT = 0x0010;
+        const USES_EXTENDED_BANNER = 0x0020;
+        const REQUIRE_RATING = 0x0040;
+        const USES_SAVE_DATA = 0x0080;
+        const RECORD_USAGE = 0x0100;
+        const DISABLE_SAVE_DATA_BACKUP = 0x0400;
+        const NEW_3DS_EXCLUSIVE = 0x1000;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chinese_title_languages() {
+        let language = |locale: &str| SmdhTitleLanguage::from_locale(&Locale::parse(locale)?);
+
+        for locale in ["zh_TW.UTF-8", "zh_HK", "zh_MO"] {
+            assert_eq!(
+                language(locale),
+                Some(SmdhTitleLanguage::TraditionalChinese)
+            );
+        }
+        for locale in ["zh_CN.UTF-8", "zh_SG", "zh"] {
+            assert_eq!(language(locale), Some(SmdhTitleLanguage::SimplifiedChinese));
+        }
+        assert_eq!(language("pt_BR"), Some(SmdhTitleLanguage::Portuguese));
+        assert_eq!(language("sv_SE"), None);
+    }
+}
@@ -0,0 +1,251 @@
+use std::io::Read;
+
+use image::{imageops, RgbaImage};
+
+use crate::n3ds::errors::{N3DSParsingError, TextureParsingError};
+use crate::utils::compression::{decompress_with_header, CompressionFormat};
+use crate::utils::pica::{decode_texture, PicaTextureFormat};
+
+/*
+ * T3X is the texture format written by tex3ds, the homebrew texture converter,
+ * usually holding a spritesheet (atlas) of sub-textures.
+ *
+ * The header holds the amount of sub-textures, the texture dimensions as powers of two
+ * (minus 3) and whether it's a cube map, the texture format (numbered like the GPU does)
+ * and the amount of mipmap levels.
+ * Each sub-texture has its dimensions followed by its left, top, right and bottom
+ * texture coordinates, multiplied by 1024.
+ * Sub-textures packed rotated in the atlas have their top coordinate below the bottom one.
+ *
+ * The texture data follows, behind a compression header as read by libctru:
+ * it may be uncompressed or compressed with LZ10, LZ11, Huffman or RLE.
+ * As usual for textures, the first row is the bottom one, following OpenGL conventions.
+ *
+ * See https://github.com/devkitPro/tex3ds for more info
+*/
+
+#[derive(Debug, Clone, Copy)]
+pub struct T3xSubTexture {
+    pub width: u16,
+    pub height: u16,
+    pub left: u16,
+    pub top: u16,
+    pub right: u16,
+    pub bottom: u16,
+}
+
+impl T3xSubTexture {
+    const COORDINATE_SCALE: u32 = 1024;
+
+    pub fn is_rotated(&self) -> bool {
+        self.top < self.bottom
+    }
+
+    /// Converts a texture coordinate to a pixel position along a texture dimension
+    fn to_pixels(coordinate: u16, size: u32) -> u32 {
+        (u32::from(coordinate) * size + Self::COORDINATE_SCALE / 2) / Self::COORDINATE_SCALE
+    }
+}
+
+#[derive(Debug)]
+pub struct T3x {
+    pub width: usize,
+    pub height: usize,
+    pub is_cube_map: bool,
+    pub format: PicaTextureFormat,
+    pub mipmap_levels: u8,
+    pub compression: Option<CompressionFormat>,
+    pub sub_textures: Vec<T3xSubTexture>,
+    data: Vec<u8>,
+}
+
+impl T3x {
+    const HEADER_SIZE: usize = 0x5;
+    const SUB_TEXTURE_SIZE: usize = 0xC;
+
+    pub fn from_t3x<T: Read>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        let mut header = [0u8; Self::HEADER_SIZE];
+        f.read_exact(&mut header)?;
+
+        let sub_texture_count = usize::from(u16::from_le_bytes([header[0], header[1]]));
+        let width = 8 << (header[2] & 0x7);
+        let height = 8 << ((header[2] >> 3) & 0x7);
+        let is_cube_map = header[2] & 0x40 != 0;
+        let format = PicaTextureFormat::try_from(u32::from(header[3]))
+            .map_err(TextureParsingError::UnsupportedTextureFormat)?;
+        let mipmap_levels = header[4];
+
+        let mut sub_textures = vec![0u8; sub_texture_count * Self::SUB_TEXTURE_SIZE];
+        f.read_exact(&mut sub_textures)?;
+        let sub_textures = sub_textures
+            .chunks_exact(Self::SUB_TEXTURE_SIZE)
+            .map(|sub_texture| {
+                let u16_at = |offset: usize| {
+                    u16::from_le_bytes([sub_texture[offset], sub_texture[offset + 1]])
+                };
+                T3xSubTexture {
+                    width: u16_at(0x0),
+                    height: u16_at(0x2),
+                    left: u16_at(0x4),
+                    top: u16_at(0x6),
+                    right: u16_at(0x8),
+                    bottom: u16_at(0xA),
+                }
+            })
+            .collect();
+
+        // Cube maps hold 6 faces, and mipmaps add up to a third of the texture size
+        let face_count = if is_cube_map { 6 } else { 1 };
+        let max_size = format.texture_size(width, height) * face_count * 4 / 3;
+
+        let mut compressed_data = Vec::new();
+        f.read_to_end(&mut compressed_data)?;
+        let (compression, data) = decompress_with_header(&compressed_data, max_size)
+            .map_err(TextureParsingError::from)?;
+
+        Ok(T3x {
+            width,
+            height,
+            is_cube_map,
+            format,
+            mipmap_levels,
+            compression,
+            sub_textures,
+            data,
+        })
+    }
+
+    /// Decodes the whole texture, only the first face is used for cube maps
+    pub fn sheet(&self) -> Result<RgbaImage, TextureParsingError> {
+        let mut img = decode_texture(&self.data, self.width, self.height, self.format).ok_or(
+            TextureParsingError::TextureTooLarge(self.width, self.height, self.data.len()),
+        )?;
+        imageops::flip_vertical_in_place(&mut img);
+        Ok(img)
+    }
+
+    /// Decodes a single sub-texture, undoing its rotation in the atlas
+    pub fn sub_texture(&self, index: usize) -> Result<RgbaImage, TextureParsingError> {
+        let sub_texture =
+            self.sub_textures
+                .get(index)
+                .ok_or(TextureParsingError::SubTextureNotFound(
+                    index,
+                    self.sub_textures.len(),
+                ))?;
+        let sheet = self.sheet()?;
+
+        // Rotated sub-textures swap the texture coordinates of both axes,
+        // being stored transposed in the atlas
+        let (u, v, width, height) = if sub_texture.is_rotated() {
+            (
+                sub_texture.top,
+                sub_texture.left,
+                sub_texture.height,
+                sub_texture.width,
+            )
+        } else {
+            (
+                sub_texture.left,
+                sub_texture.top,
+                sub_texture.width,
+                sub_texture.height,
+            )
+        };
+        let x = T3xSubTexture::to_pixels(u, sheet.width());
+        // Texture coordinates start from the bottom
+        let y = sheet
+            .height()
+            .saturating_sub(T3xSubTexture::to_pixels(v, sheet.height()));
+        let (width, height) = (u32::from(width), u32::from(height));
+        if x + width > sheet.width() || y + height > sheet.height() {
+            return Err(TextureParsingError::SubTextureOutOfBounds(index));
+        }
+
+        let img = imageops::crop_imm(&sheet, x, y, width, height).to_image();
+        if sub_texture.is_rotated() {
+            Ok(imageops::flip_horizontal(&imageops::rotate90(&img)))
+        } else {
+            Ok(img)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a 16x16 L8 T3X, each pixel holding its own offset in the texture data, with:
+    /// a 8x4 sub-texture on the top left of the sheet,
+    /// a 4x8 sub-texture stored rotated as 8x4 right below the center of the sheet,
+    /// and a 16x4 sub-texture that doesn't fit in the sheet
+    fn synthetic_t3x(compression: Option<CompressionFormat>) -> Vec<u8> {
+        let mut t3x = vec![0x03, 0x00, 0x09, 0x07, 0x01];
+        let sub_textures: [[u16; 6]; 3] = [
+            [8, 4, 0, 1024, 512, 768],
+            [4, 8, 512, 512, 256, 1024],
+            [16, 4, 512, 1024, 1536, 768],
+        ];
+        for value in sub_textures.as_flattened() {
+            t3x.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let data = (0..=u8::MAX).collect::<Vec<_>>();
+        match compression {
+            Some(format) => t3x.extend_from_slice(&format.compress(&data)),
+            None => {
+                t3x.extend_from_slice(&[0x00, 0x00, 0x01, 0x00]);
+                t3x.extend_from_slice(&data);
+            }
+        }
+        t3x
+    }
+
+    fn check_sub_textures(t3x: &T3x) {
+        let sheet = t3x.sheet().unwrap();
+        // The first row is the bottom one
+        assert_eq!(sheet.get_pixel(0, 15).0[0], 0);
+        assert_eq!(sheet.get_pixel(15, 0).0[0], u8::MAX);
+
+        let sub_texture = t3x.sub_texture(0).unwrap();
+        assert_eq!(sub_texture.dimensions(), (8, 4));
+        for (x, y, pixel) in sub_texture.enumerate_pixels() {
+            assert_eq!(pixel, sheet.get_pixel(x, y));
+        }
+
+        let sub_texture = t3x.sub_texture(1).unwrap();
+        assert_eq!(sub_texture.dimensions(), (4, 8));
+        for (x, y, pixel) in sub_texture.enumerate_pixels() {
+            assert_eq!(pixel, sheet.get_pixel(8 + y, 8 + x));
+        }
+
+        assert!(matches!(
+            t3x.sub_texture(2),
+            Err(TextureParsingError::SubTextureOutOfBounds(2))
+        ));
+        assert!(matches!(
+            t3x.sub_texture(3),
+            Err(TextureParsingError::SubTextureNotFound(3, 3))
+        ));
+    }
+
+    #[test]
+    fn uncompressed_t3x() {
+        let t3x = T3x::from_t3x(&mut synthetic_t3x(None).as_slice()).unwrap();
+        assert_eq!((t3x.width, t3x.height), (16, 16));
+        assert!(!t3x.is_cube_map);
+        assert_eq!(t3x.format, PicaTextureFormat::L8);
+        assert_eq!(t3x.compression, None);
+        assert!(!t3x.sub_textures[0].is_rotated());
+        assert!(t3x.sub_textures[1].is_rotated());
+        check_sub_textures(&t3x);
+    }
+
+    #[test]
+    fn lz11_compressed_t3x() {
+        let t3x = synthetic_t3x(Some(CompressionFormat::Lz11));
+        let t3x = T3x::from_t3x(&mut t3x.as_slice()).unwrap();
+        assert_eq!(t3x.compression, Some(CompressionFormat::Lz11));
+        check_sub_textures(&t3x);
+    }
+}
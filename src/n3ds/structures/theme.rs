@@ -0,0 +1,233 @@
+use std::io::{BufReader, Cursor, Read, Seek};
+
+use image::{imageops, ImageFormat, RgbaImage};
+use zip::ZipArchive;
+
+use crate::n3ds::errors::{N3DSParsingError, ThemeParsingError};
+use crate::utils::compression::lz11_decompress;
+use crate::utils::pica::{decode_texture, PicaTextureFormat};
+
+/*
+ * HOME Menu themes are made of a LZ11 compressed body (body_LZ.bin) and optional BGM,
+ * the body starting with a header describing how each screen is drawn,
+ * followed by the textures it points to.
+ *
+ * Screens drawn with a texture use a RGB565 texture, 512x256 when it's static
+ * or 1024x256 when it scrolls, of which only the top-left corner is visible.
+ *
+ * Themes are usually shared as zip files holding body_LZ.bin next to the preview.png
+ * (both screens) and icon.png (48x48) generated by theme editors.
+ *
+ * See https://www.3dbrew.org/wiki/Home_Menu#Themes for more info
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThemeDrawType {
+    None,
+    SolidColor,
+    SolidColorTexture,
+    Texture,
+}
+
+impl TryFrom<u32> for ThemeDrawType {
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ThemeDrawType::None),
+            1 => Ok(ThemeDrawType::SolidColor),
+            2 => Ok(ThemeDrawType::SolidColorTexture),
+            3 => Ok(ThemeDrawType::Texture),
+            _ => Err(value),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Theme {
+    pub version: u32,
+    pub has_bgm: bool,
+    pub top_draw_type: ThemeDrawType,
+    pub top_frame_type: u32,
+    pub top_solid_color_offset: u32,
+    pub top_texture_offset: u32,
+    pub top_additional_texture_offset: u32,
+    pub bottom_draw_type: ThemeDrawType,
+    pub bottom_frame_type: u32,
+    pub bottom_texture_offset: u32,
+    body: Vec<u8>,
+}
+
+impl Theme {
+    /// The top screen is 400px wide, plus some margin for the 3D effect
+    const TOP_SCREEN_WIDTH: u32 = 412;
+    const SCREEN_HEIGHT: u32 = 240;
+    const TEXTURE_HEIGHT: usize = 256;
+    /// Only this frame type uses a static 512x256 texture, the other ones scroll
+    const STATIC_FRAME_TYPE: u32 = 1;
+    /// The Home Menu keeps decompressed theme bodies in a 0x150000 bytes buffer,
+    /// bigger bodies can't be used as themes anyway
+    const MAX_BODY_SIZE: usize = 0x15_0000;
+
+    pub fn from_body_lz<T: Read>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        let body = lz11_decompress(&mut BufReader::new(f), Self::MAX_BODY_SIZE)
+            .map_err(ThemeParsingError::from)?;
+        Self::from_body(body)
+    }
+
+    pub fn from_body(body: Vec<u8>) -> Result<Self, N3DSParsingError> {
+        let mut f = Cursor::new(&body);
+
+        let mut version = [0u8; 4];
+        f.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != 1 {
+            return Err(ThemeParsingError::VersionInvalidValue(version))?;
+        }
+
+        let mut flags = [0u8; 8];
+        f.read_exact(&mut flags)?;
+        let has_bgm = flags[1] != 0;
+
+        let mut top_screen = [0u8; 20];
+        f.read_exact(&mut top_screen)?;
+        let mut bottom_screen = [0u8; 12];
+        f.read_exact(&mut bottom_screen)?;
+        let u32_at = |bytes: &[u8], offset: usize| {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+        };
+        let draw_type_at = |bytes: &[u8], offset: usize| {
+            ThemeDrawType::try_from(u32_at(bytes, offset))
+                .map_err(ThemeParsingError::DrawTypeInvalidValue)
+        };
+
+        Ok(Theme {
+            version,
+            has_bgm,
+            top_draw_type: draw_type_at(&top_screen, 0x0)?,
+            top_frame_type: u32_at(&top_screen, 0x4),
+            top_solid_color_offset: u32_at(&top_screen, 0x8),
+            top_texture_offset: u32_at(&top_screen, 0xC),
+            top_additional_texture_offset: u32_at(&top_screen, 0x10),
+            bottom_draw_type: draw_type_at(&bottom_screen, 0x0)?,
+            bottom_frame_type: u32_at(&bottom_screen, 0x4),
+            bottom_texture_offset: u32_at(&bottom_screen, 0x8),
+            body,
+        })
+    }
+
+    /// Renders the visible part of the top screen texture
+    pub fn top_screen(&self) -> Result<RgbaImage, ThemeParsingError> {
+        if self.top_draw_type != ThemeDrawType::Texture {
+            return Err(ThemeParsingError::TopScreenHasNoTexture(self.top_draw_type));
+        }
+
+        let texture_width = if self.top_frame_type == Self::STATIC_FRAME_TYPE {
+            512
+        } else {
+            1024
+        };
+        let texture_size = texture_width * Self::TEXTURE_HEIGHT * 2;
+        let texture_offset = self.top_texture_offset as usize;
+        let texture = self
+            .body
+            .get(texture_offset..texture_offset + texture_size)
+            .ok_or(ThemeParsingError::TextureOutOfBounds(
+                self.top_texture_offset,
+            ))?;
+
+        // this unwrap will never fail: the texture size is a multiple of 8 and the input is sized
+        let texture = decode_texture(
+            texture,
+            texture_width,
+            Self::TEXTURE_HEIGHT,
+            PicaTextureFormat::Rgb565,
+        )
+        .unwrap();
+        Ok(
+            imageops::crop_imm(&texture, 0, 0, Self::TOP_SCREEN_WIDTH, Self::SCREEN_HEIGHT)
+                .to_image(),
+        )
+    }
+
+    pub fn from_zip<T: Read + Seek>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        let mut archive = ZipArchive::new(f).map_err(ThemeParsingError::from)?;
+        let body_name = find_zip_body(&archive)?;
+        let body_lz = read_zip_entry(&mut archive, &body_name)?;
+        Self::from_body_lz(&mut body_lz.as_slice())
+    }
+
+    /// Prefers the preview.png or icon.png packaged next to body_LZ.bin,
+    /// otherwise the top screen is rendered from the body itself
+    pub fn preview_from_zip<T: Read + Seek>(f: &mut T) -> Result<RgbaImage, N3DSParsingError> {
+        let mut archive = ZipArchive::new(f).map_err(ThemeParsingError::from)?;
+        let body_name = find_zip_body(&archive)?;
+        let theme_dir = &body_name[..body_name.len() - THEME_BODY_FILE_NAME.len()];
+
+        for preview_name in ["preview.png", "icon.png"] {
+            let Some(preview_name) = find_zip_entry(&archive, theme_dir, preview_name) else {
+                continue;
+            };
+            let preview = read_zip_entry(&mut archive, &preview_name)?;
+            match image::load_from_memory_with_format(&preview, ImageFormat::Png) {
+                Ok(preview) => return Ok(preview.into_rgba8()),
+                Err(err) => eprintln!("Failed to decode {preview_name}: {err}"),
+            }
+        }
+
+        let body_lz = read_zip_entry(&mut archive, &body_name)?;
+        Ok(Self::from_body_lz(&mut body_lz.as_slice())?.top_screen()?)
+    }
+}
+
+const THEME_BODY_FILE_NAME: &str = "body_LZ.bin";
+/// Theme bodies and previews are a few MiB at most, bigger entries are refused
+/// instead of trusting the sizes stored in the zip file
+const MAX_ZIP_ENTRY_SIZE: u64 = 0x100_0000;
+
+/// Finds a file by name, ignoring case, directly inside `dir`
+fn find_zip_entry<T: Read + Seek>(
+    archive: &ZipArchive<T>,
+    dir: &str,
+    file_name: &str,
+) -> Option<String> {
+    archive
+        .file_names()
+        .find(|name| {
+            name.strip_prefix(dir)
+                .is_some_and(|name| name.eq_ignore_ascii_case(file_name))
+        })
+        .map(str::to_string)
+}
+
+/// Theme collections hold one theme per directory, the first one is used
+fn find_zip_body<T: Read + Seek>(archive: &ZipArchive<T>) -> Result<String, ThemeParsingError> {
+    let mut body_names = archive
+        .file_names()
+        .filter(|name| {
+            let file_name = name.rsplit('/').next().unwrap_or(name);
+            file_name.eq_ignore_ascii_case(THEME_BODY_FILE_NAME)
+        })
+        .collect::<Vec<_>>();
+    body_names.sort_unstable();
+    body_names
+        .first()
+        .map(|name| name.to_string())
+        .ok_or(ThemeParsingError::NoThemeInZip)
+}
+
+fn read_zip_entry<T: Read + Seek>(
+    archive: &mut ZipArchive<T>,
+    name: &str,
+) -> Result<Vec<u8>, ThemeParsingError> {
+    let entry = archive.by_name(name)?;
+    let mut data = Vec::new();
+    entry
+        .take(MAX_ZIP_ENTRY_SIZE + 1)
+        .read_to_end(&mut data)
+        .map_err(zip::result::ZipError::from)?;
+    if data.len() as u64 > MAX_ZIP_ENTRY_SIZE {
+        return Err(ThemeParsingError::ZipEntryTooLarge(name.to_string()));
+    }
+    Ok(data)
+}
@@ -0,0 +1,174 @@
+use bitflags::bitflags;
+use std::fmt;
+
+/*
+ * Title IDs (called program IDs on NCCH headers) are 64 bits long:
+ * the upper 16 bits are the platform, the next 16 bits are the category
+ * and the lower 32 bits hold the unique ID followed by a variation byte.
+ *
+ * Updates and DLCs share the unique ID of the title they belong to,
+ * only the category differs.
+ *
+ * See https://www.3dbrew.org/wiki/Titles for more info
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TitlePlatform {
+    Wii,
+    Twl,
+    Ctr,
+    WiiU,
+    Unknown(u16),
+}
+
+impl From<u16> for TitlePlatform {
+    fn from(value: u16) -> Self {
+        match value {
+            0x0001 => TitlePlatform::Wii,
+            0x0003 => TitlePlatform::Twl,
+            0x0004 => TitlePlatform::Ctr,
+            0x0005 => TitlePlatform::WiiU,
+            _ => TitlePlatform::Unknown(value),
+        }
+    }
+}
+
+bitflags! {
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct TitleCategory: u16 {
+        const DOWNLOAD_PLAY_CHILD = 0x0001;
+        const DEMO = 0x0002;
+        const CONTENTS = 0x0003;
+        const ADD_ON_CONTENTS = 0x0004;
+        const PATCH = 0x0006;
+        const CANNOT_EXECUTION = 0x0008;
+        const SYSTEM = 0x0010;
+        const REQUIRE_BATCH_UPDATE = 0x0020;
+        const NOT_REQUIRE_USER_APPROVAL = 0x0040;
+        const NOT_REQUIRE_RIGHT_FOR_MOUNT = 0x0080;
+        const CAN_SKIP_CONVERT_JUMP_ID = 0x0100;
+        const TWL = 0x8000;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TitleKind {
+    Application,
+    DownloadPlayChild,
+    Demo,
+    UpdatePatch,
+    Dlc,
+    SystemApplication,
+    SystemApplet,
+    SystemModule,
+    SystemFirmware,
+    SystemData,
+    SharedData,
+    DSiWare,
+    DSiSystemApplication,
+    DSiSystemData,
+    VirtualConsole,
+    Unknown,
+}
+
+impl TitleKind {
+    /// Updates and DLCs are installed on top of a base title and don't come with an icon
+    pub fn is_base_title_dependent(&self) -> bool {
+        matches!(self, TitleKind::UpdatePatch | TitleKind::Dlc)
+    }
+}
+
+impl fmt::Display for TitleKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TitleKind::Application => "application",
+            TitleKind::DownloadPlayChild => "download play child",
+            TitleKind::Demo => "demo",
+            TitleKind::UpdatePatch => "update patch",
+            TitleKind::Dlc => "DLC",
+            TitleKind::SystemApplication => "system application",
+            TitleKind::SystemApplet => "system applet",
+            TitleKind::SystemModule => "system module",
+            TitleKind::SystemFirmware => "system firmware",
+            TitleKind::SystemData => "system data archive",
+            TitleKind::SharedData => "shared data archive",
+            TitleKind::DSiWare => "DSiWare",
+            TitleKind::DSiSystemApplication => "DSi system application",
+            TitleKind::DSiSystemData => "DSi system data",
+            TitleKind::VirtualConsole => "Virtual Console",
+            TitleKind::Unknown => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TitleId(pub u64);
+
+impl fmt::Display for TitleId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016X}", self.0)
+    }
+}
+
+impl TitleId {
+    pub fn platform(&self) -> TitlePlatform {
+        TitlePlatform::from((self.0 >> 48) as u16)
+    }
+
+    pub fn category(&self) -> TitleCategory {
+        TitleCategory::from_bits_retain((self.0 >> 32) as u16)
+    }
+
+    pub fn unique_id(&self) -> u32 {
+        (self.0 as u32) >> 8
+    }
+
+    pub fn variation(&self) -> u8 {
+        self.0 as u8
+    }
+
+    /// Classifies the title from its platform and category alone,
+    /// Virtual Console titles can only be told apart by their product code
+    pub fn kind(&self) -> TitleKind {
+        match (self.platform(), self.category().bits()) {
+            (TitlePlatform::Ctr, 0x0000) => TitleKind::Application,
+            (TitlePlatform::Ctr, 0x0001) => TitleKind::DownloadPlayChild,
+            (TitlePlatform::Ctr, 0x0002) => TitleKind::Demo,
+            (TitlePlatform::Ctr, 0x000E) => TitleKind::UpdatePatch,
+            (TitlePlatform::Ctr, 0x008C) => TitleKind::Dlc,
+            (TitlePlatform::Ctr, 0x0010) => TitleKind::SystemApplication,
+            (TitlePlatform::Ctr, 0x0030) => TitleKind::SystemApplet,
+            (TitlePlatform::Ctr, 0x0130) => TitleKind::SystemModule,
+            (TitlePlatform::Ctr, 0x0138) => TitleKind::SystemFirmware,
+            (TitlePlatform::Ctr, 0x001B | 0x00DB) => TitleKind::SystemData,
+            (TitlePlatform::Ctr, 0x009B) => TitleKind::SharedData,
+            (TitlePlatform::Ctr, 0x8004) | (TitlePlatform::Twl, 0x0004) => TitleKind::DSiWare,
+            (TitlePlatform::Ctr, 0x8005) | (TitlePlatform::Twl, 0x0005 | 0x0015 | 0x0017) => {
+                TitleKind::DSiSystemApplication
+            }
+            (TitlePlatform::Ctr, 0x800F) | (TitlePlatform::Twl, 0x000F) => TitleKind::DSiSystemData,
+            _ => TitleKind::Unknown,
+        }
+    }
+
+    /// Refines `kind` with the product code found on NCCH headers (e.g. CTR-N-TAAE):
+    /// eShop Virtual Console releases use dedicated game code prefixes
+    /// for each emulated system (T for NES, R for Game Boy, Q for Game Boy Color,
+    /// M for Game Gear, P for Game Boy Advance and F for Super NES)
+    pub fn kind_with_product_code(&self, product_code: &str) -> TitleKind {
+        let kind = self.kind();
+        if kind != TitleKind::Application {
+            return kind;
+        }
+
+        let game_code = product_code
+            .strip_prefix("CTR-N-")
+            .or_else(|| product_code.strip_prefix("KTR-N-"));
+        match game_code.and_then(|game_code| game_code.chars().next()) {
+            Some('T' | 'R' | 'Q' | 'M' | 'P' | 'F') => TitleKind::VirtualConsole,
+            _ => kind,
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::n3ds::errors::{N3DSParsingError, UnistoreParsingError};
+use crate::n3ds::structures::T3x;
+
+/*
+ * Universal-Updater stores (.unistore) are JSON files listing homebrew apps,
+ * with a storeInfo object describing the store and a storeContent array with the entries.
+ *
+ * Entry icons come from one or more T3X spritesheets, named by the storeInfo sheet field
+ * (a string or an array of strings) and downloaded next to the store,
+ * each entry pointing to its sheet and sub-texture with sheet_index and icon_index.
+ *
+ * See https://github.com/Universal-Team/Universal-Updater/wiki for more info
+*/
+
+#[derive(Debug)]
+pub struct Unistore {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub revision: Option<u64>,
+    pub entry_count: usize,
+    pub sheets: Vec<String>,
+}
+
+impl Unistore {
+    pub fn from_unistore<T: Read>(f: &mut T) -> Result<Self, N3DSParsingError> {
+        let store: Value = serde_json::from_reader(f).map_err(UnistoreParsingError::from)?;
+        let store_info = &store["storeInfo"];
+        let string_at = |key: &str| store_info[key].as_str().map(str::to_string);
+
+        let sheets = match &store_info["sheet"] {
+            Value::String(sheet) => vec![sheet.clone()],
+            Value::Array(sheets) => sheets
+                .iter()
+                .filter_map(|sheet| sheet.as_str().map(str::to_string))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(Unistore {
+            title: string_at("title"),
+            author: string_at("author"),
+            revision: store_info["revision"].as_u64(),
+            entry_count: store["storeContent"].as_array().map_or(0, Vec::len),
+            sheets,
+        })
+    }
+
+    /// Loads the first icon sheet, from the same directory as the store at `path`
+    pub fn first_sheet(&self, path: &Path) -> Result<T3x, N3DSParsingError> {
+        let sheet = self.sheets.first().ok_or(UnistoreParsingError::NoSheet)?;
+        // Only the file name is used, sheets are always downloaded next to the store
+        let sheet_name = Path::new(sheet)
+            .file_name()
+            .ok_or(UnistoreParsingError::NoSheet)?;
+        let sheet_path = path.with_file_name(sheet_name);
+
+        let mut sheet_file = File::open(&sheet_path)
+            .map_err(|_| UnistoreParsingError::SheetNotFound(sheet_path.clone()))?;
+        T3x::from_t3x(&mut sheet_file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_sheet() {
+        let store = r#"{
+            "storeInfo": {
+                "title": "Store",
+                "author": "Author",
+                "revision": 3,
+                "sheet": "store.t3x"
+            },
+            "storeContent": [{}, {}]
+        }"#;
+        let unistore = Unistore::from_unistore(&mut store.as_bytes()).unwrap();
+        assert_eq!(unistore.title.as_deref(), Some("Store"));
+        assert_eq!(unistore.author.as_deref(), Some("Author"));
+        assert_eq!(unistore.revision, Some(3));
+        assert_eq!(unistore.entry_count, 2);
+        assert_eq!(unistore.sheets, ["store.t3x"]);
+    }
+
+    #[test]
+    fn multiple_sheets() {
+        let store = r#"{
+            "storeInfo": { "sheet": ["first.t3x", 2, "second.t3x"] }
+        }"#;
+        let unistore = Unistore::from_unistore(&mut store.as_bytes()).unwrap();
+        assert_eq!(unistore.title, None);
+        assert_eq!(unistore.entry_count, 0);
+        assert_eq!(unistore.sheets, ["first.t3x", "second.t3x"]);
+
+        let unistore = Unistore::from_unistore(&mut "{}".as_bytes()).unwrap();
+        assert!(unistore.sheets.is_empty());
+        assert!(matches!(
+            unistore.first_sheet(Path::new("store.unistore")),
+            Err(N3DSParsingError::UnistoreParsingError(
+                UnistoreParsingError::NoSheet
+            ))
+        ));
+    }
+}
@@ -1,12 +1,19 @@
 pub mod errors;
-mod structures;
+pub mod structures;
 
-use crate::utils::rgb888::{Bgr555, Rgb888};
+use crate::utils::{
+    crc16::crc16_modbus,
+    rgb888::{Bgr555, Rgb888},
+};
 
 use self::errors::NDSParsingError;
 use image::{ImageBuffer, Rgba, RgbaImage};
-use std::io::{Read, Seek, SeekFrom};
-use structures::{NDSBannerDetails, NDSIconVersion, PaletteColor};
+use std::io::{Read, Seek, SeekFrom, Write};
+use structures::{
+    NDSAnimatedIcon, NDSAnimatedIconFrame, NDSAnimationSequenceEntry, NDSBannerChecksumMismatch,
+    NDSBannerChecksumMode, NDSBannerDetails, NDSHeader, NDSHeaderVerification, NDSIconVersion,
+    NDSTitle, NDSTitleLanguage, PaletteColor, TadHeader, TwlHeader,
+};
 
 /*
  * Consider the following links for more info about the .nds file structure:
@@ -15,35 +22,244 @@ use structures::{NDSBannerDetails, NDSIconVersion, PaletteColor};
  * NDS banner: https://problemkaputt.de/gbatek.htm#dscartridgeicontitle
  *
  * Do note that while animated icons might be available if the version of the icon
- * matches the NDSIconVersion::DSi version, the static icon will be used instead
+ * matches the NDSIconVersion::DSi version, the static icon will be used for thumbnails
  * as the thumbnailer specification doesn't support animations.
+ * The animated icon is still parsed and can be exported as an APNG or GIF.
 */
 
-pub fn extract_nds_banner<T: Read + Seek>(f: &mut T) -> Result<NDSBannerDetails, NDSParsingError> {
+pub fn extract_nds_header<T: Read + Seek>(f: &mut T) -> Result<NDSHeader, NDSParsingError> {
+    let nds_start_pos = f.stream_position()?;
+
+    let header = NDSHeader::from_file(f)?;
+    f.seek(SeekFrom::Start(nds_start_pos))?;
+
+    Ok(header)
+}
+
+/// Whether the stream contains a TWL SRL (a DSi rom, as used by DSiWare),
+/// detected by its unit code and by the logo CRC that must be present on any valid header
+pub fn is_twl_srl<T: Read + Seek>(f: &mut T) -> Result<bool, NDSParsingError> {
+    let nds_start_pos = f.stream_position()?;
+
+    let mut header_bytes = [0u8; NDSHeader::SIZE];
+    f.read_exact(&mut header_bytes)?;
+    f.seek(SeekFrom::Start(nds_start_pos))?;
+
+    let Ok(header) = NDSHeader::from_bytes(&header_bytes) else {
+        return Ok(false);
+    };
+    let verification = NDSHeaderVerification::from_bytes(&header_bytes);
+
+    Ok(header.unit_code.has_twl_header() && verification.logo_crc.is_valid())
+}
+
+/// Extracts the banner of the TWL SRL contained in a TAD archive
+pub fn extract_tad_banner<T: Read + Seek>(
+    f: &mut T,
+    checksum_mode: NDSBannerChecksumMode,
+) -> Result<NDSBannerDetails, NDSParsingError> {
+    let tad_start_pos = f.stream_position()?;
+
+    let mut tad_header = [0u8; TadHeader::SIZE];
+    f.read_exact(&mut tad_header)?;
+    let tad_header = TadHeader::from_bytes(&tad_header)?;
+
+    f.seek(SeekFrom::Start(tad_start_pos + tad_header.content_offset()))?;
+    if !is_twl_srl(f)? {
+        return Err(NDSParsingError::TadContentNotSrl);
+    }
+    extract_nds_banner(f, checksum_mode)
+}
+
+/// Returns the DSi extended header, only present on DSi-enhanced and DSi-exclusive roms
+pub fn extract_twl_header<T: Read + Seek>(
+    f: &mut T,
+    header: &NDSHeader,
+) -> Result<Option<TwlHeader>, NDSParsingError> {
+    if !header.unit_code.has_twl_header() {
+        return Ok(None);
+    }
+
+    let nds_start_pos = f.stream_position()?;
+
+    f.seek(SeekFrom::Start(nds_start_pos + TwlHeader::OFFSET))?;
+    let mut twl_header_bytes = [0u8; TwlHeader::SIZE];
+    f.read_exact(&mut twl_header_bytes)?;
+    f.seek(SeekFrom::Start(nds_start_pos))?;
+
+    Ok(Some(TwlHeader::from_bytes(&twl_header_bytes)))
+}
+
+pub fn verify_nds_header<T: Read + Seek>(
+    f: &mut T,
+) -> Result<NDSHeaderVerification, NDSParsingError> {
+    let nds_start_pos = f.stream_position()?;
+
+    let mut header_bytes = [0u8; NDSHeader::SIZE];
+    f.read_exact(&mut header_bytes)?;
+    f.seek(SeekFrom::Start(nds_start_pos))?;
+
+    Ok(NDSHeaderVerification::from_bytes(&header_bytes))
+}
+
+/// Rewrites the logo CRC and the header CRC in place, returning the updated verification.
+/// The logo CRC is calculated from the logo present on the file, so a modified logo
+/// will still fail verification afterwards
+pub fn fix_nds_header<T: Read + Write + Seek>(
+    f: &mut T,
+) -> Result<NDSHeaderVerification, NDSParsingError> {
+    let nds_start_pos = f.stream_position()?;
+
+    let mut header_bytes = [0u8; NDSHeader::SIZE];
+    f.read_exact(&mut header_bytes)?;
+
+    let logo_crc = crc16_modbus(&header_bytes[NDSHeader::LOGO_RANGE]);
+    header_bytes[NDSHeader::LOGO_CRC_OFFSET..NDSHeader::LOGO_CRC_OFFSET + 2]
+        .copy_from_slice(&logo_crc.to_le_bytes());
+
+    let header_crc = crc16_modbus(&header_bytes[..NDSHeader::HEADER_CRC_OFFSET]);
+    header_bytes[NDSHeader::HEADER_CRC_OFFSET..NDSHeader::HEADER_CRC_OFFSET + 2]
+        .copy_from_slice(&header_crc.to_le_bytes());
+
+    let crc_offset: u64 = NDSHeader::LOGO_CRC_OFFSET.try_into().unwrap();
+    f.seek(SeekFrom::Start(nds_start_pos + crc_offset))?;
+    f.write_all(&header_bytes[NDSHeader::LOGO_CRC_OFFSET..NDSHeader::HEADER_CRC_OFFSET + 2])?;
+    f.flush()?;
+    f.seek(SeekFrom::Start(nds_start_pos))?;
+
+    Ok(NDSHeaderVerification::from_bytes(&header_bytes))
+}
+
+/// In lenient mode, checksum mismatches don't fail the parsing and are recorded instead
+pub fn extract_nds_banner<T: Read + Seek>(
+    f: &mut T,
+    checksum_mode: NDSBannerChecksumMode,
+) -> Result<NDSBannerDetails, NDSParsingError> {
     const NDS_HEADER_BANNER_OFFSET_OFFSET: u64 = 0x068;
-    const NDS_BANNER_SIZE: usize = 0x240;
 
-    f.seek(SeekFrom::Start(NDS_HEADER_BANNER_OFFSET_OFFSET))?;
+    let nds_start_pos = f.stream_position()?;
+
+    f.seek(SeekFrom::Start(
+        nds_start_pos + NDS_HEADER_BANNER_OFFSET_OFFSET,
+    ))?;
     let mut banner_offset = [0u8; 4];
     f.read_exact(&mut banner_offset)?;
     let banner_offset = u32::from_le_bytes(banner_offset);
+    let banner_pos = nds_start_pos + u64::from(banner_offset);
+
+    f.seek(SeekFrom::Start(banner_pos))?;
+    let mut icon_version = [0u8; 2];
+    f.read_exact(&mut icon_version)?;
+    let icon_version = u16::from_le_bytes(icon_version);
+    let icon_version = NDSIconVersion::try_from(icon_version)?;
 
-    f.seek(SeekFrom::Start(banner_offset.into()))?;
-    let mut banner_bytes = [0u8; NDS_BANNER_SIZE];
+    f.seek(SeekFrom::Start(banner_pos))?;
+    let mut banner_bytes = vec![0u8; icon_version.banner_size()];
     f.read_exact(&mut banner_bytes)?;
 
-    let icon_version = u16::from_le_bytes(banner_bytes[..2].try_into().unwrap());
-    let icon_version = NDSIconVersion::try_from(icon_version)?;
+    let checksum_mismatches = verify_banner_checksums(&banner_bytes, icon_version);
+    if let (NDSBannerChecksumMode::Strict, Some(mismatch)) =
+        (checksum_mode, checksum_mismatches.first())
+    {
+        return Err(NDSParsingError::BannerChecksumMismatch(*mismatch));
+    }
 
     let logo_bytes: &[u8; 0x200] = &banner_bytes[0x020..0x220].try_into().unwrap();
     let palette_bytes: &[u8; 0x20] = &banner_bytes[0x220..0x240].try_into().unwrap();
     let palette = extract_palette_colors(palette_bytes);
 
-    let banner_details =
-        NDSBannerDetails::new(icon_version, generate_nds_icon(logo_bytes, &palette));
+    let titles = banner_bytes[0x240..]
+        .chunks_exact(0x100)
+        .zip(NDSTitleLanguage::ALL)
+        .take(icon_version.title_count())
+        .map(|(chunk, language)| NDSTitle::from_bytes(language, chunk.try_into().unwrap()))
+        .collect();
+
+    let animated_icon = if icon_version.has_animated_icon() {
+        extract_animated_icon(&banner_bytes[0x1240..0x23C0].try_into().unwrap())
+    } else {
+        None
+    };
+
+    let banner_details = NDSBannerDetails::new(
+        icon_version,
+        generate_nds_icon(logo_bytes, &palette),
+        animated_icon,
+        titles,
+        checksum_mismatches,
+    );
     Ok(banner_details)
 }
 
+fn verify_banner_checksums(
+    banner_bytes: &[u8],
+    icon_version: NDSIconVersion,
+) -> Vec<NDSBannerChecksumMismatch> {
+    NDSIconVersion::ALL
+        .into_iter()
+        .filter(|region| *region <= icon_version)
+        .filter_map(|region| {
+            let offset = region.checksum_offset();
+            let expected = u16::from_le_bytes(banner_bytes[offset..offset + 2].try_into().unwrap());
+            let calculated = crc16_modbus(&banner_bytes[region.checksum_range()]);
+
+            (expected != calculated).then_some(NDSBannerChecksumMismatch {
+                region,
+                expected,
+                calculated,
+            })
+        })
+        .collect()
+}
+
+fn extract_animated_icon(animation_bytes: &[u8; 0x1180]) -> Option<NDSAnimatedIcon> {
+    /*
+     * The DSi animated icon area is composed of:
+     * 8 bitmaps of 0x200 bytes each, using the same layout as the static icon
+     * 8 palettes of 0x20 bytes each, using the same encoding as the static palette
+     * 64 sequence entries of 2 bytes each, see NDSAnimationSequenceEntry
+     */
+
+    let (bitmaps_bytes, remaining) = animation_bytes.split_at(0x1000);
+    let (palettes_bytes, sequence_bytes) = remaining.split_at(0x100);
+
+    let bitmaps: Vec<&[u8; 0x200]> = bitmaps_bytes
+        .chunks_exact(0x200)
+        .map(|chunk| chunk.try_into().unwrap())
+        .collect();
+    let palettes: Vec<[PaletteColor; 0x20 / 2]> = palettes_bytes
+        .chunks_exact(0x20)
+        .map(|chunk| extract_palette_colors(chunk.try_into().unwrap()))
+        .collect();
+
+    let frames: Vec<NDSAnimatedIconFrame> = sequence_bytes
+        .chunks_exact(2)
+        .map_while(|chunk| NDSAnimationSequenceEntry::from_bytes(chunk.try_into().unwrap()))
+        .map(|sequence_entry| {
+            let bitmap = bitmaps[usize::from(sequence_entry.bitmap_index)];
+            let palette = &palettes[usize::from(sequence_entry.palette_index)];
+
+            let mut icon = generate_nds_icon(bitmap, palette);
+            if sequence_entry.flip_horizontal {
+                image::imageops::flip_horizontal_in_place(&mut icon);
+            }
+            if sequence_entry.flip_vertical {
+                image::imageops::flip_vertical_in_place(&mut icon);
+            }
+
+            NDSAnimatedIconFrame {
+                sequence_entry,
+                icon,
+            }
+        })
+        .collect();
+
+    if frames.is_empty() {
+        return None;
+    }
+    Some(NDSAnimatedIcon { frames })
+}
+
 fn extract_palette_colors(palette_raw: &[u8; 0x20]) -> [PaletteColor; 0x20 / 2] {
     // this unwrap will never fail: there's even length input.
     let colors_converted = palette_raw.chunks_exact(2).map(|chunk| {
@@ -116,3 +332,160 @@ fn generate_nds_icon(
 
     img
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const BANNER_OFFSET: usize = 0x200;
+
+    /// Builds a NDS rom with a banner of `icon_version` holding an English title,
+    /// with every checksum of the banner being valid
+    fn synthetic_nds(icon_version_value: u16) -> Vec<u8> {
+        let icon_version = NDSIconVersion::try_from(icon_version_value).unwrap();
+        let mut banner = vec![0u8; icon_version.banner_size()];
+        banner[..2].copy_from_slice(&icon_version_value.to_le_bytes());
+        for (i, byte) in banner[0x20..0x240].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let english_title = &mut banner[0x340..0x440];
+        for (i, c) in "Title\nPublisher".encode_utf16().enumerate() {
+            english_title[i * 2..i * 2 + 2].copy_from_slice(&c.to_le_bytes());
+        }
+        if icon_version.has_animated_icon() {
+            banner[0x1240..0x23C0].fill(0x11);
+        }
+        update_checksums(&mut banner, icon_version);
+
+        let mut nds = vec![0u8; BANNER_OFFSET];
+        nds[0x68..0x6C].copy_from_slice(&(BANNER_OFFSET as u32).to_le_bytes());
+        nds.extend_from_slice(&banner);
+        nds
+    }
+
+    fn update_checksums(banner: &mut [u8], icon_version: NDSIconVersion) {
+        for region in NDSIconVersion::ALL
+            .into_iter()
+            .filter(|region| *region <= icon_version)
+        {
+            let offset = region.checksum_offset();
+            let crc = crc16_modbus(&banner[region.checksum_range()]);
+            banner[offset..offset + 2].copy_from_slice(&crc.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn valid_banner() {
+        let nds = synthetic_nds(0x0001);
+        let banner_details =
+            extract_nds_banner(&mut Cursor::new(nds), NDSBannerChecksumMode::Strict).unwrap();
+        assert!(!banner_details.is_suspect());
+        assert_eq!(banner_details.titles.len(), 6);
+        assert_eq!(banner_details.titles[1].title, "Title");
+        assert_eq!(
+            banner_details.titles[1].publisher.as_deref(),
+            Some("Publisher")
+        );
+    }
+
+    #[test]
+    fn banner_with_bad_checksum() {
+        let mut nds = synthetic_nds(0x0001);
+        nds[BANNER_OFFSET + 0x100] ^= 0xFF;
+
+        let result = extract_nds_banner(&mut Cursor::new(&nds), NDSBannerChecksumMode::Strict);
+        assert!(matches!(
+            result,
+            Err(NDSParsingError::BannerChecksumMismatch(mismatch))
+                if mismatch.region == NDSIconVersion::V1
+        ));
+
+        let banner_details =
+            extract_nds_banner(&mut Cursor::new(&nds), NDSBannerChecksumMode::Lenient).unwrap();
+        assert!(banner_details.is_suspect());
+        let [mismatch] = banner_details.checksum_mismatches.as_slice() else {
+            panic!("expected a single checksum mismatch");
+        };
+        assert_eq!(mismatch.region, NDSIconVersion::V1);
+        assert_ne!(mismatch.expected, mismatch.calculated);
+    }
+
+    #[test]
+    fn dsi_banner_checks_the_animated_icon() {
+        let nds = synthetic_nds(0x0103);
+        let banner_details =
+            extract_nds_banner(&mut Cursor::new(&nds), NDSBannerChecksumMode::Strict).unwrap();
+        assert!(!banner_details.is_suspect());
+        assert_eq!(banner_details.titles.len(), 8);
+
+        // Only the region of the animated icon is covered by the DSi checksum
+        let mut nds = nds;
+        nds[BANNER_OFFSET + 0x2000] ^= 0xFF;
+        assert!(matches!(
+            extract_nds_banner(&mut Cursor::new(&nds), NDSBannerChecksumMode::Strict),
+            Err(NDSParsingError::BannerChecksumMismatch(mismatch))
+                if mismatch.region == NDSIconVersion::DSi
+        ));
+        let banner_details =
+            extract_nds_banner(&mut Cursor::new(&nds), NDSBannerChecksumMode::Lenient).unwrap();
+        let regions = banner_details
+            .checksum_mismatches
+            .iter()
+            .map(|mismatch| mismatch.region)
+            .collect::<Vec<_>>();
+        assert_eq!(regions, [NDSIconVersion::DSi]);
+    }
+
+    #[test]
+    fn fixed_header_passes_verification() {
+        let mut nds = synthetic_nds(0x0001);
+        nds[NDSHeader::LOGO_RANGE].copy_from_slice(&NDSHeader::NINTENDO_LOGO);
+
+        let verification = verify_nds_header(&mut Cursor::new(&nds)).unwrap();
+        assert!(verification.is_logo_valid);
+        assert!(!verification.logo_crc.is_valid());
+        assert!(!verification.header_crc.is_valid());
+
+        let mut cursor = Cursor::new(nds);
+        assert!(fix_nds_header(&mut cursor).unwrap().is_valid());
+        assert_eq!(cursor.position(), 0);
+        assert!(verify_nds_header(&mut cursor).unwrap().is_valid());
+
+        // A modified logo gets a matching CRC, which still isn't the expected one
+        let mut nds = cursor.into_inner();
+        nds[NDSHeader::LOGO_RANGE.start] ^= 0xFF;
+        let verification = fix_nds_header(&mut Cursor::new(&mut nds)).unwrap();
+        assert!(!verification.is_logo_valid);
+        assert!(!verification.logo_crc.is_valid());
+        assert!(verification.header_crc.is_valid());
+    }
+
+    #[test]
+    fn tad_banner() {
+        let mut srl = synthetic_nds(0x0103);
+        srl[0x012] = 0x03;
+        srl[NDSHeader::LOGO_CRC_OFFSET..NDSHeader::LOGO_CRC_OFFSET + 2]
+            .copy_from_slice(&NDSHeader::NINTENDO_LOGO_CRC.to_le_bytes());
+
+        // The SRL follows the aligned header, certificate chain and TMD at 0x100
+        let mut tad = vec![0u8; 0x100];
+        tad[0x00..0x04].copy_from_slice(&0x20u32.to_be_bytes());
+        tad[0x08..0x0C].copy_from_slice(&0x30u32.to_be_bytes());
+        tad[0x14..0x18].copy_from_slice(&0x50u32.to_be_bytes());
+        let mut nds_tad = tad.clone();
+        nds_tad.extend_from_slice(&srl);
+
+        let banner_details =
+            extract_tad_banner(&mut Cursor::new(&nds_tad), NDSBannerChecksumMode::Strict).unwrap();
+        assert_eq!(banner_details.titles[1].title, "Title");
+
+        // DS roms aren't TWL SRLs, so they are rejected as TAD contents
+        srl[0x012] = 0x00;
+        tad.extend_from_slice(&srl);
+        assert!(matches!(
+            extract_tad_banner(&mut Cursor::new(&tad), NDSBannerChecksumMode::Strict),
+            Err(NDSParsingError::TadContentNotSrl)
+        ));
+    }
+}
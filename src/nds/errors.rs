@@ -1,9 +1,19 @@
 use thiserror::Error;
 
+use super::structures::NDSBannerChecksumMismatch;
+
 #[derive(Error, Debug)]
 pub enum NDSParsingError {
     #[error("Unknown Or Invalid NDS icon version. Found {0:#06x}")]
     UnknownOrInvalidNDSIconVersion(u16),
+    #[error("Unknown Or Invalid NDS unit code. Found {0:#04x}")]
+    UnknownOrInvalidNDSUnitCode(u8),
+    #[error("NDS banner is likely corrupt: {0}")]
+    BannerChecksumMismatch(NDSBannerChecksumMismatch),
+    #[error("TAD header size is invalid. Found {0:#X}")]
+    InvalidTadHeaderSize(u32),
+    #[error("TAD content is not a plain TWL SRL, it's likely encrypted.")]
+    TadContentNotSrl,
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 }
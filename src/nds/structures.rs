@@ -1,6 +1,17 @@
-use image::{ImageBuffer, Rgba};
+mod header;
+mod tad;
+mod twl;
+
+pub use header::{NDSHeader, NDSHeaderVerification};
+pub use tad::TadHeader;
+pub use twl::{TwlHeader, TwlRegionFlags};
+
+use image::{Delay, Frame, ImageBuffer, Rgba};
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
 
 use super::errors::NDSParsingError;
+use crate::utils::locale::{self, Locale, LocalizedTitle, TitleLanguage};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PaletteColor {
@@ -14,31 +25,250 @@ pub struct PaletteColor {
 pub struct NDSBannerDetails {
     pub icon_version: NDSIconVersion,
     pub icon: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    pub animated_icon: Option<NDSAnimatedIcon>,
+    pub titles: Vec<NDSTitle>,
+    pub checksum_mismatches: Vec<NDSBannerChecksumMismatch>,
 }
 
 impl NDSBannerDetails {
     pub fn new(
         icon_version: NDSIconVersion,
         icon: ImageBuffer<Rgba<u8>, Vec<u8>>,
+        animated_icon: Option<NDSAnimatedIcon>,
+        titles: Vec<NDSTitle>,
+        checksum_mismatches: Vec<NDSBannerChecksumMismatch>,
     ) -> NDSBannerDetails {
         NDSBannerDetails {
             icon_version,
             icon,
+            animated_icon,
+            titles,
+            checksum_mismatches,
+        }
+    }
+
+    /// Whether the banner failed checksum verification and was only parsed in lenient mode
+    pub fn is_suspect(&self) -> bool {
+        !self.checksum_mismatches.is_empty()
+    }
+
+    /// Picks the title matching the user locale, see `locale::best_title`.
+    ///
+    /// If the DSi region flags are known, only languages expected on those regions are
+    /// considered before falling back
+    pub fn best_title(&self, region_flags: Option<TwlRegionFlags>) -> Option<&NDSTitle> {
+        self.best_title_for(locale::user_title_language(), region_flags)
+    }
+
+    pub fn best_title_for(
+        &self,
+        language: Option<NDSTitleLanguage>,
+        region_flags: Option<TwlRegionFlags>,
+    ) -> Option<&NDSTitle> {
+        // Region free titles give no hint about which languages are expected
+        let region_flags = region_flags.filter(|region_flags| !region_flags.is_region_free());
+        let region_languages = region_flags
+            .map(|region_flags| region_flags.languages())
+            .unwrap_or_default();
+
+        let language = language
+            .filter(|language| region_flags.is_none() || region_languages.contains(language));
+        locale::best_title(&self.titles, language.into_iter().chain(region_languages))
+    }
+}
+
+/// The NDS banner titles are ordered this way, with the Chinese title only being present
+/// from icon version 0002h onwards and the Korean title from 0003h onwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NDSTitleLanguage {
+    Japanese,
+    English,
+    French,
+    German,
+    Italian,
+    Spanish,
+    Chinese,
+    Korean,
+}
+
+impl NDSTitleLanguage {
+    pub const ALL: [NDSTitleLanguage; 8] = [
+        NDSTitleLanguage::Japanese,
+        NDSTitleLanguage::English,
+        NDSTitleLanguage::French,
+        NDSTitleLanguage::German,
+        NDSTitleLanguage::Italian,
+        NDSTitleLanguage::Spanish,
+        NDSTitleLanguage::Chinese,
+        NDSTitleLanguage::Korean,
+    ];
+}
+
+impl TitleLanguage for NDSTitleLanguage {
+    const ENGLISH: Self = NDSTitleLanguage::English;
+
+    fn from_locale(locale: &Locale) -> Option<Self> {
+        match &locale.language[..] {
+            "ja" => Some(NDSTitleLanguage::Japanese),
+            "en" => Some(NDSTitleLanguage::English),
+            "fr" => Some(NDSTitleLanguage::French),
+            "de" => Some(NDSTitleLanguage::German),
+            "it" => Some(NDSTitleLanguage::Italian),
+            "es" => Some(NDSTitleLanguage::Spanish),
+            "zh" => Some(NDSTitleLanguage::Chinese),
+            "ko" => Some(NDSTitleLanguage::Korean),
+            _ => None,
+        }
+    }
+}
+
+/// Each title is up to 128 UTF-16 characters, split in lines by a line feed:
+/// two lines mean title and publisher, three lines mean title, subtitle and publisher
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NDSTitle {
+    pub language: NDSTitleLanguage,
+    pub title: String,
+    pub subtitle: Option<String>,
+    pub publisher: Option<String>,
+}
+
+impl NDSTitle {
+    pub fn from_bytes(language: NDSTitleLanguage, title_bytes: &[u8; 0x100]) -> Self {
+        let title_chars: Vec<u16> = title_bytes
+            .chunks_exact(2)
+            .map(|chunk| u16::from_le_bytes(chunk.try_into().unwrap()))
+            .take_while(|c| *c != 0)
+            .collect();
+        let full_title = String::from_utf16_lossy(&title_chars);
+
+        let mut lines = full_title.lines().map(|line| line.trim().to_string());
+        let title = lines.next().unwrap_or_default();
+        let (subtitle, publisher) = match (lines.next(), lines.next()) {
+            (Some(subtitle), Some(publisher)) => (Some(subtitle), Some(publisher)),
+            (Some(publisher), None) => (None, Some(publisher)),
+            _ => (None, None),
+        };
+
+        NDSTitle {
+            language,
+            title,
+            subtitle,
+            publisher,
         }
     }
 }
 
+impl LocalizedTitle for NDSTitle {
+    type Language = NDSTitleLanguage;
+
+    fn language(&self) -> NDSTitleLanguage {
+        self.language
+    }
+
+    fn is_empty(&self) -> bool {
+        self.title.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NDSBannerChecksumMode {
+    Strict,
+    Lenient,
+}
+
+/// Each NDS icon version adds a CRC16 covering the banner region introduced by that version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NDSBannerChecksumMismatch {
+    pub region: NDSIconVersion,
+    pub expected: u16,
+    pub calculated: u16,
+}
+
+impl Display for NDSBannerChecksumMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} region CRC16 mismatch, expected {:#06x} but calculated {:#06x}",
+            self.region, self.expected, self.calculated
+        )
+    }
+}
+
+/// The DSi animated icon, already rendered following its animation sequence
+#[derive(Debug)]
+pub struct NDSAnimatedIcon {
+    pub frames: Vec<NDSAnimatedIconFrame>,
+}
+
+impl NDSAnimatedIcon {
+    pub fn to_frames(&self) -> Vec<Frame> {
+        self.frames
+            .iter()
+            .map(|frame| {
+                let duration = u32::from(frame.sequence_entry.duration);
+                let delay = Delay::from_numer_denom_ms(duration * 1000, 60);
+                Frame::from_parts(frame.icon.clone(), 0, 0, delay)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct NDSAnimatedIconFrame {
+    pub sequence_entry: NDSAnimationSequenceEntry,
+    pub icon: ImageBuffer<Rgba<u8>, Vec<u8>>,
+}
+
+/// Each entry of the DSi icon animation sequence is 16 bits:
+///
+/// Bit 0-7 = Frame duration (in 60Hz units),
+/// Bit 8-10 = Bitmap index,
+/// Bit 11-13 = Palette index,
+/// Bit 14 = Flip horizontally,
+/// Bit 15 = Flip vertically
+///
+/// An entry with a duration of zero marks the end of the sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NDSAnimationSequenceEntry {
+    pub duration: u8,
+    pub bitmap_index: u8,
+    pub palette_index: u8,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+impl NDSAnimationSequenceEntry {
+    pub fn from_bytes(entry_bytes: [u8; 2]) -> Option<Self> {
+        let entry = u16::from_le_bytes(entry_bytes);
+
+        let duration = u8::try_from(entry & 0x00FF).unwrap();
+        if duration == 0 {
+            return None;
+        }
+
+        let bitmap_index = u8::try_from((entry & 0x0700) >> 8).unwrap();
+        let palette_index = u8::try_from((entry & 0x3800) >> 11).unwrap();
+        let flip_horizontal = entry & 0x4000 != 0;
+        let flip_vertical = entry & 0x8000 != 0;
+
+        Some(NDSAnimationSequenceEntry {
+            duration,
+            bitmap_index,
+            palette_index,
+            flip_horizontal,
+            flip_vertical,
+        })
+    }
+}
+
 /// The NDS icon versions map to this:
 ///
 /// 0001h = Original,
 /// 0002h = With Chinese Title,
 /// 0003h = With Chinese+Korean Titles,
 /// 0103h = With Chinese+Korean Titles and animated DSi icon
-///
-/// Do note that the animated DSi icon is not supported by this thumbnailer
 #[allow(clippy::doc_markdown)]
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum NDSIconVersion {
     V1,
     V2,
@@ -59,3 +289,55 @@ impl TryFrom<u16> for NDSIconVersion {
         }
     }
 }
+
+impl NDSIconVersion {
+    /// Size of the whole banner, as present on the ROM, for each icon version
+    pub fn banner_size(&self) -> usize {
+        match self {
+            NDSIconVersion::V1 => 0x840,
+            NDSIconVersion::V2 => 0x940,
+            NDSIconVersion::V3 => 0xA40,
+            NDSIconVersion::DSi => 0x23C0,
+        }
+    }
+
+    pub const ALL: [NDSIconVersion; 4] = [
+        NDSIconVersion::V1,
+        NDSIconVersion::V2,
+        NDSIconVersion::V3,
+        NDSIconVersion::DSi,
+    ];
+
+    /// Offset of the CRC16 introduced by this icon version
+    pub fn checksum_offset(&self) -> usize {
+        match self {
+            NDSIconVersion::V1 => 0x02,
+            NDSIconVersion::V2 => 0x04,
+            NDSIconVersion::V3 => 0x06,
+            NDSIconVersion::DSi => 0x08,
+        }
+    }
+
+    /// Banner region covered by the CRC16 introduced by this icon version
+    pub fn checksum_range(&self) -> Range<usize> {
+        match self {
+            NDSIconVersion::V1 => 0x020..0x840,
+            NDSIconVersion::V2 => 0x020..0x940,
+            NDSIconVersion::V3 => 0x020..0xA40,
+            NDSIconVersion::DSi => 0x1240..0x23C0,
+        }
+    }
+
+    /// Amount of titles present on the banner, see NDSTitleLanguage
+    pub fn title_count(&self) -> usize {
+        match self {
+            NDSIconVersion::V1 => 6,
+            NDSIconVersion::V2 => 7,
+            NDSIconVersion::V3 | NDSIconVersion::DSi => 8,
+        }
+    }
+
+    pub fn has_animated_icon(&self) -> bool {
+        *self == NDSIconVersion::DSi
+    }
+}
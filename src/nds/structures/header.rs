@@ -0,0 +1,339 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    io::{Read, Seek},
+    ops::Range,
+};
+
+use crate::{nds::errors::NDSParsingError, utils::crc16::crc16_modbus};
+
+/*
+ * The NDS header is 0x200 bytes long on DS roms (padded to 0x4000 on retail carts)
+ * and 0x1000 bytes long on DSi-enhanced or DSi-exclusive roms.
+ *
+ * Only the fields shared by both are parsed here, see
+ * https://problemkaputt.de/gbatek.htm#dscartridgeheader
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NDSHeader {
+    pub game_title: String,
+    pub game_code: String,
+    pub maker_code: String,
+    pub unit_code: NDSUnitCode,
+    pub device_capacity: u64,
+    pub rom_version: u8,
+    pub arm9: NDSBinaryInfo,
+    pub arm7: NDSBinaryInfo,
+    pub fnt_offset: u32,
+    pub fnt_size: u32,
+    pub fat_offset: u32,
+    pub fat_size: u32,
+    pub banner_offset: u32,
+    pub total_used_rom_size: u32,
+    pub header_size: u32,
+}
+
+impl NDSHeader {
+    pub const SIZE: usize = 0x200;
+    pub const LOGO_RANGE: Range<usize> = 0x0C0..0x15C;
+    pub const LOGO_CRC_OFFSET: usize = 0x15C;
+    pub const HEADER_CRC_OFFSET: usize = 0x15E;
+
+    /// The Nintendo logo bitmap, which must be present unmodified on every NDS header
+    pub const NINTENDO_LOGO: [u8; 0x9C] = [
+        0x24, 0xFF, 0xAE, 0x51, 0x69, 0x9A, 0xA2, 0x21, 0x3D, 0x84, 0x82, 0x0A, 0x84, 0xE4, 0x09,
+        0xAD, 0x11, 0x24, 0x8B, 0x98, 0xC0, 0x81, 0x7F, 0x21, 0xA3, 0x52, 0xBE, 0x19, 0x93, 0x09,
+        0xCE, 0x20, 0x10, 0x46, 0x4A, 0x4A, 0xF8, 0x27, 0x31, 0xEC, 0x58, 0xC7, 0xE8, 0x33, 0x82,
+        0xE3, 0xCE, 0xBF, 0x85, 0xF4, 0xDF, 0x94, 0xCE, 0x4B, 0x09, 0xC1, 0x94, 0x56, 0x8A, 0xC0,
+        0x13, 0x72, 0xA7, 0xFC, 0x9F, 0x84, 0x4D, 0x73, 0xA3, 0xCA, 0x9A, 0x61, 0x58, 0x97, 0xA3,
+        0x27, 0xFC, 0x03, 0x98, 0x76, 0x23, 0x1D, 0xC7, 0x61, 0x03, 0x04, 0xAE, 0x56, 0xBF, 0x38,
+        0x84, 0x00, 0x40, 0xA7, 0x0E, 0xFD, 0xFF, 0x52, 0xFE, 0x03, 0x6F, 0x95, 0x30, 0xF1, 0x97,
+        0xFB, 0xC0, 0x85, 0x60, 0xD6, 0x80, 0x25, 0xA9, 0x63, 0xBE, 0x03, 0x01, 0x4E, 0x38, 0xE2,
+        0xF9, 0xA2, 0x34, 0xFF, 0xBB, 0x3E, 0x03, 0x44, 0x78, 0x00, 0x90, 0xCB, 0x88, 0x11, 0x3A,
+        0x94, 0x65, 0xC0, 0x7C, 0x63, 0x87, 0xF0, 0x3C, 0xAF, 0xD6, 0x25, 0xE4, 0x8B, 0x38, 0x0A,
+        0xAC, 0x72, 0x21, 0xD4, 0xF8, 0x07,
+    ];
+    pub const NINTENDO_LOGO_CRC: u16 = 0xCF56;
+
+    pub fn from_file<T: Read + Seek>(f: &mut T) -> Result<Self, NDSParsingError> {
+        let mut header_bytes = [0u8; Self::SIZE];
+        f.read_exact(&mut header_bytes)?;
+        Self::from_bytes(&header_bytes)
+    }
+
+    pub fn from_bytes(header_bytes: &[u8; Self::SIZE]) -> Result<Self, NDSParsingError> {
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes(header_bytes[offset..offset + 4].try_into().unwrap())
+        };
+
+        let game_title = ascii_string(&header_bytes[0x000..0x00C]);
+        let game_code = ascii_string(&header_bytes[0x00C..0x010]);
+        let maker_code = ascii_string(&header_bytes[0x010..0x012]);
+        let unit_code = NDSUnitCode::try_from(header_bytes[0x012])?;
+
+        // Chip size is 128KB shifted left by the device capacity value
+        let device_capacity = 0x20000u64 << header_bytes[0x014].min(0x20);
+        let rom_version = header_bytes[0x01E];
+
+        let arm9 = NDSBinaryInfo::from_bytes(header_bytes[0x020..0x030].try_into().unwrap());
+        let arm7 = NDSBinaryInfo::from_bytes(header_bytes[0x030..0x040].try_into().unwrap());
+
+        Ok(NDSHeader {
+            game_title,
+            game_code,
+            maker_code,
+            unit_code,
+            device_capacity,
+            rom_version,
+            arm9,
+            arm7,
+            fnt_offset: read_u32(0x040),
+            fnt_size: read_u32(0x044),
+            fat_offset: read_u32(0x048),
+            fat_size: read_u32(0x04C),
+            banner_offset: read_u32(0x068),
+            total_used_rom_size: read_u32(0x080),
+            header_size: read_u32(0x084),
+        })
+    }
+
+    /// The region is derived from the last letter of the game code
+    pub fn region(&self) -> Option<NDSRegion> {
+        self.game_code
+            .as_bytes()
+            .get(3)
+            .and_then(|letter| NDSRegion::from_game_code_letter(*letter))
+    }
+}
+
+/// Result of checking the header checksums and the Nintendo logo,
+/// which are verified by the console and by most flashcarts before booting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NDSHeaderVerification {
+    pub header_crc: NDSHeaderChecksum,
+    pub logo_crc: NDSHeaderChecksum,
+    pub is_logo_valid: bool,
+}
+
+impl NDSHeaderVerification {
+    pub fn from_bytes(header_bytes: &[u8; NDSHeader::SIZE]) -> Self {
+        let read_u16 = |offset: usize| {
+            u16::from_le_bytes(header_bytes[offset..offset + 2].try_into().unwrap())
+        };
+
+        let header_crc = NDSHeaderChecksum {
+            stored: read_u16(NDSHeader::HEADER_CRC_OFFSET),
+            expected: crc16_modbus(&header_bytes[..NDSHeader::HEADER_CRC_OFFSET]),
+        };
+        let logo_crc = NDSHeaderChecksum {
+            stored: read_u16(NDSHeader::LOGO_CRC_OFFSET),
+            expected: NDSHeader::NINTENDO_LOGO_CRC,
+        };
+        let is_logo_valid = header_bytes[NDSHeader::LOGO_RANGE] == NDSHeader::NINTENDO_LOGO;
+
+        NDSHeaderVerification {
+            header_crc,
+            logo_crc,
+            is_logo_valid,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.header_crc.is_valid() && self.logo_crc.is_valid() && self.is_logo_valid
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NDSHeaderChecksum {
+    pub stored: u16,
+    pub expected: u16,
+}
+
+impl NDSHeaderChecksum {
+    pub fn is_valid(&self) -> bool {
+        self.stored == self.expected
+    }
+}
+
+impl Display for NDSHeaderChecksum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_valid() {
+            write!(f, "OK ({:#06x})", self.stored)
+        } else {
+            write!(
+                f,
+                "INVALID, found {:#06x} but expected {:#06x}",
+                self.stored, self.expected
+            )
+        }
+    }
+}
+
+fn ascii_string(bytes: &[u8]) -> String {
+    let len = bytes
+        .iter()
+        .position(|p| *p == b'\0')
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len])
+        .trim_end()
+        .to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NDSBinaryInfo {
+    pub rom_offset: u32,
+    pub entry_address: u32,
+    pub ram_address: u32,
+    pub size: u32,
+}
+
+impl NDSBinaryInfo {
+    pub fn from_bytes(binary_info_bytes: &[u8; 16]) -> Self {
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes(binary_info_bytes[offset..offset + 4].try_into().unwrap())
+        };
+
+        NDSBinaryInfo {
+            rom_offset: read_u32(0x0),
+            entry_address: read_u32(0x4),
+            ram_address: read_u32(0x8),
+            size: read_u32(0xC),
+        }
+    }
+}
+
+/// The NDS unit codes map to this:
+///
+/// 00h = NDS,
+/// 02h = NDS+DSi (DSi-enhanced),
+/// 03h = DSi (DSi-exclusive)
+#[allow(clippy::doc_markdown)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NDSUnitCode {
+    NintendoDS,
+    DSiEnhanced,
+    DSiExclusive,
+}
+
+impl NDSUnitCode {
+    pub fn has_twl_header(&self) -> bool {
+        matches!(self, NDSUnitCode::DSiEnhanced | NDSUnitCode::DSiExclusive)
+    }
+}
+
+impl TryFrom<u8> for NDSUnitCode {
+    type Error = NDSParsingError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(NDSUnitCode::NintendoDS),
+            0x02 => Ok(NDSUnitCode::DSiEnhanced),
+            0x03 => Ok(NDSUnitCode::DSiExclusive),
+            _ => Err(Self::Error::UnknownOrInvalidNDSUnitCode(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NDSRegion {
+    Asia,
+    China,
+    Germany,
+    UnitedStates,
+    France,
+    Netherlands,
+    Italy,
+    Japan,
+    Korea,
+    Sweden,
+    Norway,
+    International,
+    Europe,
+    Denmark,
+    Russia,
+    Spain,
+    UnitedStatesAndAustralia,
+    Australia,
+    EuropeAndAustralia,
+}
+
+impl NDSRegion {
+    pub fn from_game_code_letter(letter: u8) -> Option<Self> {
+        match letter {
+            b'A' => Some(NDSRegion::Asia),
+            b'C' => Some(NDSRegion::China),
+            b'D' => Some(NDSRegion::Germany),
+            b'E' | b'L' => Some(NDSRegion::UnitedStates),
+            b'F' => Some(NDSRegion::France),
+            b'H' => Some(NDSRegion::Netherlands),
+            b'I' => Some(NDSRegion::Italy),
+            b'J' => Some(NDSRegion::Japan),
+            b'K' => Some(NDSRegion::Korea),
+            b'M' => Some(NDSRegion::Sweden),
+            b'N' => Some(NDSRegion::Norway),
+            b'O' => Some(NDSRegion::International),
+            b'P' | b'W' | b'X' | b'Y' | b'Z' => Some(NDSRegion::Europe),
+            b'Q' => Some(NDSRegion::Denmark),
+            b'R' => Some(NDSRegion::Russia),
+            b'S' => Some(NDSRegion::Spain),
+            b'T' => Some(NDSRegion::UnitedStatesAndAustralia),
+            b'U' => Some(NDSRegion::Australia),
+            b'V' => Some(NDSRegion::EuropeAndAustralia),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn synthetic_header(unit_code: u8) -> [u8; NDSHeader::SIZE] {
+        let mut header_bytes = [0u8; NDSHeader::SIZE];
+        header_bytes[0x000..0x00C].copy_from_slice(b"GAME TITLE\0\0");
+        header_bytes[0x00C..0x010].copy_from_slice(b"ABCE");
+        header_bytes[0x010..0x012].copy_from_slice(b"01");
+        header_bytes[0x012] = unit_code;
+        header_bytes[0x014] = 0x09;
+        header_bytes[0x01E] = 2;
+        header_bytes[0x068..0x06C].copy_from_slice(&0x0123_4600u32.to_le_bytes());
+        header_bytes[0x084..0x088].copy_from_slice(&0x4000u32.to_le_bytes());
+        header_bytes
+    }
+
+    #[test]
+    fn header_fields() {
+        let header = NDSHeader::from_bytes(&synthetic_header(0x02)).unwrap();
+        assert_eq!(header.game_title, "GAME TITLE");
+        assert_eq!(header.game_code, "ABCE");
+        assert_eq!(header.maker_code, "01");
+        assert_eq!(header.unit_code, NDSUnitCode::DSiEnhanced);
+        assert!(header.unit_code.has_twl_header());
+        assert_eq!(header.device_capacity, 64 * 1024 * 1024);
+        assert_eq!(header.rom_version, 2);
+        assert_eq!(header.banner_offset, 0x0123_4600);
+        assert_eq!(header.header_size, 0x4000);
+        assert_eq!(header.region(), Some(NDSRegion::UnitedStates));
+    }
+
+    #[test]
+    fn invalid_unit_code() {
+        assert!(matches!(
+            NDSHeader::from_bytes(&synthetic_header(0x01)),
+            Err(NDSParsingError::UnknownOrInvalidNDSUnitCode(0x01))
+        ));
+    }
+
+    #[test]
+    fn game_code_regions() {
+        assert_eq!(
+            NDSRegion::from_game_code_letter(b'J'),
+            Some(NDSRegion::Japan)
+        );
+        assert_eq!(
+            NDSRegion::from_game_code_letter(b'X'),
+            Some(NDSRegion::Europe)
+        );
+        assert_eq!(NDSRegion::from_game_code_letter(b'B'), None);
+    }
+}
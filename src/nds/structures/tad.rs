@@ -0,0 +1,75 @@
+use crate::nds::errors::NDSParsingError;
+
+/*
+ * TAD files are DSiWare title archives, using the same layout as Wii WAD files:
+ * a 0x20 bytes header followed by the certificate chain, ticket, TMD and contents,
+ * each section aligned to 0x40 bytes. All values are big endian.
+ *
+ * See https://dsibrew.org/wiki/TAD and https://wiibrew.org/wiki/WAD_files
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TadHeader {
+    pub header_size: u32,
+    pub certificate_chain_size: u32,
+    pub ticket_size: u32,
+    pub tmd_size: u32,
+}
+
+impl TadHeader {
+    pub const SIZE: usize = 0x20;
+    const ALIGNMENT: u64 = 0x40;
+
+    pub fn from_bytes(header_bytes: &[u8; Self::SIZE]) -> Result<Self, NDSParsingError> {
+        let read_u32 = |offset: usize| {
+            u32::from_be_bytes(header_bytes[offset..offset + 4].try_into().unwrap())
+        };
+
+        let header_size = read_u32(0x00);
+        if header_size != 0x20 {
+            return Err(NDSParsingError::InvalidTadHeaderSize(header_size));
+        }
+
+        Ok(TadHeader {
+            header_size,
+            certificate_chain_size: read_u32(0x08),
+            ticket_size: read_u32(0x10),
+            tmd_size: read_u32(0x14),
+        })
+    }
+
+    /// Offset of the first content, which is the SRL for DSiWare
+    pub fn content_offset(&self) -> u64 {
+        [
+            self.header_size,
+            self.certificate_chain_size,
+            self.ticket_size,
+            self.tmd_size,
+        ]
+        .iter()
+        .map(|size| u64::from(*size).next_multiple_of(Self::ALIGNMENT))
+        .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_offset_is_aligned() {
+        let mut header_bytes = [0u8; TadHeader::SIZE];
+        for (offset, size) in [(0x00, 0x20), (0x08, 0xA00), (0x10, 0x2A4), (0x14, 0x208u32)] {
+            header_bytes[offset..offset + 4].copy_from_slice(&size.to_be_bytes());
+        }
+
+        let tad_header = TadHeader::from_bytes(&header_bytes).unwrap();
+        assert_eq!(tad_header.content_offset(), 0x40 + 0xA00 + 0x2C0 + 0x240);
+
+        header_bytes[..4].copy_from_slice(&0x40u32.to_be_bytes());
+        assert!(matches!(
+            TadHeader::from_bytes(&header_bytes),
+            Err(NDSParsingError::InvalidTadHeaderSize(0x40))
+        ));
+    }
+}
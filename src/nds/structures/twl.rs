@@ -0,0 +1,288 @@
+use bitflags::bitflags;
+
+/*
+ * The DSi (TWL) extended header follows the NDS header on DSi-enhanced and DSi-exclusive roms.
+ *
+ * Only the parts relevant for identifying the title are parsed here, see
+ * https://problemkaputt.de/gbatek.htm#dsicartridgeheader
+*/
+
+use super::NDSTitleLanguage;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TwlHeader {
+    pub region_flags: TwlRegionFlags,
+    pub arm9i: TwlBinaryInfo,
+    pub arm7i: TwlBinaryInfo,
+    pub modcrypt_areas: [TwlModcryptArea; 2],
+    pub title_id: u64,
+    pub public_save_size: u32,
+    pub private_save_size: u32,
+    pub age_ratings: Vec<TwlAgeRating>,
+}
+
+impl TwlHeader {
+    /// Offset of the TWL fields inside the header
+    pub const OFFSET: u64 = 0x180;
+    /// Size of the TWL fields parsed, up to the end of the age ratings
+    pub const SIZE: usize = 0x180;
+
+    /// DSiWare title IDs always have this value on their upper half
+    const DSIWARE_TITLE_ID_HIGH: u32 = 0x0003_0004;
+
+    pub fn from_bytes(twl_header_bytes: &[u8; Self::SIZE]) -> Self {
+        // Offsets on the TWL header documentation are relative to the start of the NDS header
+        let read_u32 = |offset: usize| {
+            let offset = offset - 0x180;
+            u32::from_le_bytes(twl_header_bytes[offset..offset + 4].try_into().unwrap())
+        };
+
+        let region_flags = TwlRegionFlags::from_bits_retain(read_u32(0x1B0));
+
+        let arm9i = TwlBinaryInfo {
+            rom_offset: read_u32(0x1C0),
+            ram_address: read_u32(0x1C8),
+            size: read_u32(0x1CC),
+        };
+        let arm7i = TwlBinaryInfo {
+            rom_offset: read_u32(0x1D0),
+            ram_address: read_u32(0x1D8),
+            size: read_u32(0x1DC),
+        };
+
+        let modcrypt_areas = [
+            TwlModcryptArea {
+                offset: read_u32(0x220),
+                size: read_u32(0x224),
+            },
+            TwlModcryptArea {
+                offset: read_u32(0x228),
+                size: read_u32(0x22C),
+            },
+        ];
+
+        let title_id = u64::from(read_u32(0x230)) | (u64::from(read_u32(0x234)) << 32);
+
+        let age_ratings = twl_header_bytes[0x2F0 - 0x180..0x300 - 0x180]
+            .iter()
+            .enumerate()
+            .filter_map(|(index, rating)| TwlAgeRating::from_byte(index, *rating))
+            .collect();
+
+        TwlHeader {
+            region_flags,
+            arm9i,
+            arm7i,
+            modcrypt_areas,
+            title_id,
+            public_save_size: read_u32(0x238),
+            private_save_size: read_u32(0x23C),
+            age_ratings,
+        }
+    }
+
+    pub fn is_dsiware(&self) -> bool {
+        (self.title_id >> 32) == u64::from(Self::DSIWARE_TITLE_ID_HIGH)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TwlBinaryInfo {
+    pub rom_offset: u32,
+    pub ram_address: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TwlModcryptArea {
+    pub offset: u32,
+    pub size: u32,
+}
+
+bitflags! {
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct TwlRegionFlags: u32 {
+        const JAPAN = 0x01;
+        const USA = 0x02;
+        const EUROPE = 0x04;
+        const AUSTRALIA = 0x08;
+        const CHINA = 0x10;
+        const KOREA = 0x20;
+
+        const REGION_FREE = 0xFFFF_FFFF;
+    }
+}
+
+impl TwlRegionFlags {
+    pub fn is_region_free(&self) -> bool {
+        *self == Self::REGION_FREE
+    }
+
+    /// Languages that titles sold on these regions are expected to be localized to
+    pub fn languages(&self) -> Vec<NDSTitleLanguage> {
+        if self.is_region_free() {
+            return NDSTitleLanguage::ALL.to_vec();
+        }
+
+        let region_languages: [(TwlRegionFlags, &[NDSTitleLanguage]); 6] = [
+            (Self::JAPAN, &[NDSTitleLanguage::Japanese]),
+            (
+                Self::USA,
+                &[
+                    NDSTitleLanguage::English,
+                    NDSTitleLanguage::French,
+                    NDSTitleLanguage::Spanish,
+                ],
+            ),
+            (
+                Self::EUROPE,
+                &[
+                    NDSTitleLanguage::English,
+                    NDSTitleLanguage::French,
+                    NDSTitleLanguage::German,
+                    NDSTitleLanguage::Italian,
+                    NDSTitleLanguage::Spanish,
+                ],
+            ),
+            (Self::AUSTRALIA, &[NDSTitleLanguage::English]),
+            (Self::CHINA, &[NDSTitleLanguage::Chinese]),
+            (Self::KOREA, &[NDSTitleLanguage::Korean]),
+        ];
+
+        let mut languages = Vec::new();
+        for (region, region_languages) in region_languages {
+            if !self.contains(region) {
+                continue;
+            }
+            for language in region_languages {
+                if !languages.contains(language) {
+                    languages.push(*language);
+                }
+            }
+        }
+        languages
+    }
+}
+
+/// The DSi age ratings are 16 bytes, one per rating board.
+/// Each byte has bit 7 set if the rating is enabled, bit 6 set if the rating is pending,
+/// and the minimum age on bits 0-4
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TwlAgeRating {
+    pub board: TwlRatingBoard,
+    pub age: u8,
+    pub is_pending: bool,
+}
+
+impl TwlAgeRating {
+    pub fn from_byte(index: usize, rating: u8) -> Option<Self> {
+        if rating & 0x80 == 0 {
+            return None;
+        }
+        let board = TwlRatingBoard::from_index(index)?;
+
+        Some(TwlAgeRating {
+            board,
+            age: rating & 0x1F,
+            is_pending: rating & 0x40 != 0,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TwlRatingBoard {
+    Cero,
+    Esrb,
+    Usk,
+    PegiEurope,
+    PegiPortugal,
+    PegiAndBbfc,
+    Agcb,
+    Grb,
+}
+
+impl TwlRatingBoard {
+    pub fn from_index(index: usize) -> Option<Self> {
+        match index {
+            0 => Some(TwlRatingBoard::Cero),
+            1 => Some(TwlRatingBoard::Esrb),
+            3 => Some(TwlRatingBoard::Usk),
+            4 => Some(TwlRatingBoard::PegiEurope),
+            6 => Some(TwlRatingBoard::PegiPortugal),
+            7 => Some(TwlRatingBoard::PegiAndBbfc),
+            8 => Some(TwlRatingBoard::Agcb),
+            9 => Some(TwlRatingBoard::Grb),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn twl_header_fields() {
+        let mut twl_header_bytes = [0u8; TwlHeader::SIZE];
+        let mut write_u32 = |offset: usize, value: u32| {
+            let offset = offset - 0x180;
+            twl_header_bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        write_u32(0x1B0, 0x06);
+        write_u32(0x230, 0x4B41_4241);
+        write_u32(0x234, 0x0003_0004);
+        write_u32(0x238, 0x4000);
+        // CERO 12 (pending), USK 6 and an unassigned rating board
+        twl_header_bytes[0x2F0 - 0x180] = 0xC0 | 12;
+        twl_header_bytes[0x2F3 - 0x180] = 0x80 | 6;
+        twl_header_bytes[0x2F2 - 0x180] = 0x80 | 3;
+
+        let twl_header = TwlHeader::from_bytes(&twl_header_bytes);
+        assert_eq!(
+            twl_header.region_flags,
+            TwlRegionFlags::USA | TwlRegionFlags::EUROPE
+        );
+        assert_eq!(twl_header.title_id, 0x0003_0004_4B41_4241);
+        assert!(twl_header.is_dsiware());
+        assert_eq!(twl_header.public_save_size, 0x4000);
+        assert_eq!(
+            twl_header.age_ratings,
+            [
+                TwlAgeRating {
+                    board: TwlRatingBoard::Cero,
+                    age: 12,
+                    is_pending: true,
+                },
+                TwlAgeRating {
+                    board: TwlRatingBoard::Usk,
+                    age: 6,
+                    is_pending: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn region_languages() {
+        assert_eq!(
+            (TwlRegionFlags::USA | TwlRegionFlags::EUROPE).languages(),
+            [
+                NDSTitleLanguage::English,
+                NDSTitleLanguage::French,
+                NDSTitleLanguage::Spanish,
+                NDSTitleLanguage::German,
+                NDSTitleLanguage::Italian,
+            ]
+        );
+        assert_eq!(
+            (TwlRegionFlags::JAPAN | TwlRegionFlags::KOREA).languages(),
+            [NDSTitleLanguage::Japanese, NDSTitleLanguage::Korean]
+        );
+        assert_eq!(
+            TwlRegionFlags::REGION_FREE.languages(),
+            NDSTitleLanguage::ALL
+        );
+        assert!(TwlRegionFlags::empty().languages().is_empty());
+    }
+}
@@ -1,3 +1,9 @@
+pub mod animation;
+pub mod compression;
+pub mod crc16;
+pub mod emblem;
+pub mod locale;
+pub mod pica;
 pub mod rgb888;
 
 use std::path::Path;
@@ -0,0 +1,77 @@
+use std::{fs::File, io::BufWriter, path::Path, str::FromStr};
+
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Frame,
+};
+
+use crate::error::ThumbnailerError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Apng,
+    Gif,
+}
+
+impl FromStr for AnimationFormat {
+    type Err = ThumbnailerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "apng" | "png" => Ok(AnimationFormat::Apng),
+            "gif" => Ok(AnimationFormat::Gif),
+            _ => Err(ThumbnailerError::UnknownAnimationFormat(s.to_string())),
+        }
+    }
+}
+
+pub fn save_animation(
+    frames: Vec<Frame>,
+    format: AnimationFormat,
+    output: &Path,
+) -> Result<(), ThumbnailerError> {
+    let output = BufWriter::new(File::create(output)?);
+
+    match format {
+        AnimationFormat::Apng => save_apng(frames, output),
+        AnimationFormat::Gif => save_gif(frames, output),
+    }
+}
+
+fn save_apng(frames: Vec<Frame>, output: BufWriter<File>) -> Result<(), ThumbnailerError> {
+    let Some(first_frame) = frames.first() else {
+        return Err(ThumbnailerError::NoAnimationFrames);
+    };
+    let (width, height) = first_frame.buffer().dimensions();
+
+    let mut encoder = png::Encoder::new(output, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // Zero plays means the animation loops forever
+    encoder.set_animated(u32::try_from(frames.len()).unwrap(), 0)?;
+
+    let mut writer = encoder.write_header()?;
+    writer.set_blend_op(png::BlendOp::Source)?;
+    writer.set_dispose_op(png::DisposeOp::None)?;
+
+    for frame in frames {
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        let delay_ms = u16::try_from(numerator / denominator).unwrap_or(u16::MAX);
+        writer.set_frame_delay(delay_ms, 1000)?;
+        writer.write_image_data(frame.buffer())?;
+    }
+
+    writer.finish()?;
+    Ok(())
+}
+
+fn save_gif(frames: Vec<Frame>, output: BufWriter<File>) -> Result<(), ThumbnailerError> {
+    if frames.is_empty() {
+        return Err(ThumbnailerError::NoAnimationFrames);
+    }
+
+    let mut encoder = GifEncoder::new(output);
+    encoder.set_repeat(Repeat::Infinite)?;
+    encoder.encode_frames(frames)?;
+    Ok(())
+}
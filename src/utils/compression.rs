@@ -0,0 +1,422 @@
+mod blz;
+mod huffman;
+mod lz;
+mod rle;
+
+use std::{fmt, io, io::Read, str::FromStr};
+
+use thiserror::Error;
+
+pub use blz::{blz_compress, blz_decompress};
+pub use huffman::{huffman_compress, huffman_decompress};
+pub use lz::{lz10_compress, lz10_decompress, lz11_compress, lz11_decompress};
+pub use rle::{rle_compress, rle_decompress};
+
+use crate::error::ThumbnailerError;
+
+/*
+ * Nintendo handhelds share the compression formats of the GBA/DS BIOS,
+ * each one starting with a header made of a type byte and the decompressed size:
+ * LZ10 (0x10), LZ11 (0x11), Huffman (0x24 and 0x28, for 4 and 8 bits data units) and RLE (0x30).
+ * A 24 bits size of 0 means the actual size follows as 32 bits, an extension used on 3DS.
+ *
+ * BLZ is a LZ10 variant decompressed backwards and in place, used for DS ARM9 binaries
+ * and 3DS ExeFS .code files, so it has a footer instead of a header.
+ *
+ * Back-references let a small file claim and actually produce gigabytes of data,
+ * so decompression takes the largest size expected by the caller and refuses anything bigger.
+ *
+ * See https://problemkaputt.de/gbatek.htm#biosdecompressionfunctions for more info
+*/
+
+#[derive(Error, Debug)]
+pub enum DecompressionError {
+    #[error("Unknown compression type {0:#04X}, and no BLZ footer found either.")]
+    UnknownType(u8),
+    #[error("Unexpected compression type {0:#04X}, expected {1:#04X}.")]
+    UnexpectedType(u8, u8),
+    #[error("Compressed data ended before reaching the decompressed size.")]
+    UnexpectedEnd,
+    #[error("Back-reference to {0} bytes behind, but only {1} bytes were decompressed.")]
+    InvalidDisplacement(usize, usize),
+    #[error("Huffman tree node {0} is outside of the tree.")]
+    InvalidHuffmanTree(usize),
+    #[error("Invalid BLZ footer.")]
+    InvalidBlzFooter,
+    #[error("Decompressed size of {0} bytes is over the limit of {1} bytes.")]
+    SizeLimitExceeded(usize, usize),
+    #[error(transparent)]
+    IoError(io::Error),
+}
+
+impl From<io::Error> for DecompressionError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            DecompressionError::UnexpectedEnd
+        } else {
+            DecompressionError::IoError(err)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionFormat {
+    Lz10,
+    Lz11,
+    Huffman4,
+    Huffman8,
+    Rle,
+    Blz,
+}
+
+impl FromStr for CompressionFormat {
+    type Err = ThumbnailerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lz10" | "lz" => Ok(CompressionFormat::Lz10),
+            "lz11" => Ok(CompressionFormat::Lz11),
+            "huff4" => Ok(CompressionFormat::Huffman4),
+            "huff8" | "huff" => Ok(CompressionFormat::Huffman8),
+            "rle" => Ok(CompressionFormat::Rle),
+            "blz" => Ok(CompressionFormat::Blz),
+            _ => Err(ThumbnailerError::UnknownCompressionFormat(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for CompressionFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CompressionFormat::Lz10 => "lz10",
+            CompressionFormat::Lz11 => "lz11",
+            CompressionFormat::Huffman4 => "huff4",
+            CompressionFormat::Huffman8 => "huff8",
+            CompressionFormat::Rle => "rle",
+            CompressionFormat::Blz => "blz",
+        };
+        f.write_str(name)
+    }
+}
+
+impl CompressionFormat {
+    fn from_type(compression_type: u8) -> Option<Self> {
+        match compression_type {
+            lz::LZ10_TYPE => Some(CompressionFormat::Lz10),
+            lz::LZ11_TYPE => Some(CompressionFormat::Lz11),
+            huffman::HUFFMAN4_TYPE => Some(CompressionFormat::Huffman4),
+            huffman::HUFFMAN8_TYPE => Some(CompressionFormat::Huffman8),
+            rle::RLE_TYPE => Some(CompressionFormat::Rle),
+            _ => None,
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            CompressionFormat::Lz10 => lz10_compress(data),
+            CompressionFormat::Lz11 => lz11_compress(data),
+            CompressionFormat::Huffman4 => huffman_compress(data, 4),
+            CompressionFormat::Huffman8 => huffman_compress(data, 8),
+            CompressionFormat::Rle => rle_compress(data),
+            CompressionFormat::Blz => blz_compress(data),
+        }
+    }
+
+    pub fn decompress(
+        self,
+        mut data: &[u8],
+        max_size: usize,
+    ) -> Result<Vec<u8>, DecompressionError> {
+        match self {
+            CompressionFormat::Lz10 => lz10_decompress(&mut data, max_size),
+            CompressionFormat::Lz11 => lz11_decompress(&mut data, max_size),
+            CompressionFormat::Huffman4 | CompressionFormat::Huffman8 => {
+                huffman_decompress(&mut data, max_size)
+            }
+            CompressionFormat::Rle => rle_decompress(&mut data, max_size),
+            CompressionFormat::Blz => blz_decompress(data, max_size),
+        }
+    }
+}
+
+/// Detects the compression format from the header type byte, falling back to BLZ
+/// as its footer can't be told apart from uncompressed data
+pub fn decompress(
+    data: &[u8],
+    max_size: usize,
+) -> Result<(CompressionFormat, Vec<u8>), DecompressionError> {
+    let compression_type = *data.first().ok_or(DecompressionError::UnexpectedEnd)?;
+    let result = match CompressionFormat::from_type(compression_type) {
+        Some(format) => format
+            .decompress(data, max_size)
+            .map(|output| (format, output)),
+        None => Err(DecompressionError::UnknownType(compression_type)),
+    };
+
+    result.or_else(|err| {
+        blz_decompress(data, max_size)
+            .map(|output| (CompressionFormat::Blz, output))
+            .map_err(|_| err)
+    })
+}
+
+/// Header type libctru uses for uncompressed data, which is stored right after the header
+const UNCOMPRESSED_TYPE: u8 = 0x00;
+
+/// Decompresses data behind a compression header like libctru does, which unlike
+/// [`decompress`] also accepts uncompressed data but doesn't fall back to BLZ.
+/// The format is `None` when the data wasn't compressed
+pub fn decompress_with_header(
+    data: &[u8],
+    max_size: usize,
+) -> Result<(Option<CompressionFormat>, Vec<u8>), DecompressionError> {
+    let mut f = data;
+    let (compression_type, size) = read_header(&mut f, max_size)?;
+    if compression_type == UNCOMPRESSED_TYPE {
+        let output = f.get(..size).ok_or(DecompressionError::UnexpectedEnd)?;
+        return Ok((None, output.to_vec()));
+    }
+
+    let format = CompressionFormat::from_type(compression_type)
+        .ok_or(DecompressionError::UnknownType(compression_type))?;
+    Ok((Some(format), format.decompress(data, max_size)?))
+}
+
+/// Decompressed sizes come from untrusted headers, so only this much is allocated upfront
+const MAX_PREALLOCATED_SIZE: usize = 0x100_0000;
+
+fn output_buffer(size: usize) -> Vec<u8> {
+    Vec::with_capacity(size.min(MAX_PREALLOCATED_SIZE))
+}
+
+fn read_u8<R: Read>(f: &mut R) -> Result<u8, DecompressionError> {
+    let mut byte = [0u8; 1];
+    f.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// Reads the compression header, returning the compression type and the decompressed size,
+/// which can't be over `max_size`
+fn read_header<R: Read>(f: &mut R, max_size: usize) -> Result<(u8, usize), DecompressionError> {
+    let mut header = [0u8; 4];
+    f.read_exact(&mut header)?;
+
+    let mut size = u32::from_le_bytes([header[1], header[2], header[3], 0]);
+    if size == 0 {
+        let mut extended_size = [0u8; 4];
+        f.read_exact(&mut extended_size)?;
+        size = u32::from_le_bytes(extended_size);
+    }
+
+    let size = size as usize;
+    if size > max_size {
+        return Err(DecompressionError::SizeLimitExceeded(size, max_size));
+    }
+    Ok((header[0], size))
+}
+
+/// Writes the compression header, using the extended size when it doesn't fit in 24 bits
+fn write_header(output: &mut Vec<u8>, compression_type: u8, size: usize) {
+    let size = u32::try_from(size).expect("Data is too large to be compressed");
+    output.push(compression_type);
+    if size == 0 || size > 0xFF_FFFF {
+        output.extend_from_slice(&[0, 0, 0]);
+        output.extend_from_slice(&size.to_le_bytes());
+    } else {
+        output.extend_from_slice(&size.to_le_bytes()[..3]);
+    }
+}
+
+/// Compressed data is padded to 4 bytes, as the BIOS functions read it by words
+fn pad_to_word(output: &mut Vec<u8>) {
+    output.resize(output.len().next_multiple_of(4), 0);
+}
+
+/// Copies `length` bytes starting `displacement` bytes behind the end of `output`,
+/// byte by byte as the source and destination may overlap
+fn copy_back_reference(
+    output: &mut Vec<u8>,
+    displacement: usize,
+    length: usize,
+) -> Result<(), DecompressionError> {
+    if displacement == 0 || displacement > output.len() {
+        return Err(DecompressionError::InvalidDisplacement(
+            displacement,
+            output.len(),
+        ));
+    }
+
+    let start = output.len() - displacement;
+    for index in start..start + length {
+        output.push(output[index]);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Larger than any test data
+    pub(super) const MAX_SIZE: usize = 0x100_0000;
+
+    /// Deterministic xorshift bytes, which barely compress
+    pub(super) fn pseudo_random(len: usize, mut state: u32) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    /// Data mixing runs, repeated phrases and noise, so every codec gets to use all its tokens
+    pub(super) fn mixed_data() -> Vec<u8> {
+        let mut data = b"Nintendo handheld thumbnailer ".repeat(20);
+        data.extend(std::iter::repeat_n(0xAA, 0x300));
+        data.extend(pseudo_random(0x400, 0x1234_5678));
+        data.extend((0..=u8::MAX).cycle().take(0x2000));
+        data.extend(std::iter::repeat_n(0x00, 0x12000));
+        data
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let formats = [
+            CompressionFormat::Lz10,
+            CompressionFormat::Lz11,
+            CompressionFormat::Huffman4,
+            CompressionFormat::Huffman8,
+            CompressionFormat::Rle,
+            CompressionFormat::Blz,
+        ];
+        let data = mixed_data();
+        for format in formats {
+            let compressed = format.compress(&data);
+            assert!(compressed.len() < data.len(), "{format} didn't compress");
+            assert_eq!(
+                format.decompress(&compressed, MAX_SIZE).unwrap(),
+                data,
+                "{format}"
+            );
+            assert_eq!(
+                format.to_string().parse::<CompressionFormat>().unwrap(),
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn detects_the_format() {
+        let data = mixed_data();
+        for format in [
+            CompressionFormat::Lz10,
+            CompressionFormat::Lz11,
+            CompressionFormat::Huffman4,
+            CompressionFormat::Huffman8,
+            CompressionFormat::Rle,
+            CompressionFormat::Blz,
+        ] {
+            let (detected_format, output) = decompress(&format.compress(&data), MAX_SIZE).unwrap();
+            assert_eq!(detected_format, format);
+            assert_eq!(output, data);
+        }
+
+        assert!(matches!(
+            decompress(&[0x42, 0x00, 0x00, 0x00], MAX_SIZE),
+            Err(DecompressionError::UnknownType(0x42))
+        ));
+        assert!(matches!(
+            decompress(&[], MAX_SIZE),
+            Err(DecompressionError::UnexpectedEnd)
+        ));
+    }
+
+    #[test]
+    fn decompress_with_header_accepts_uncompressed_data() {
+        let (format, output) =
+            decompress_with_header(&[UNCOMPRESSED_TYPE, 0x03, 0x00, 0x00, 1, 2, 3, 4], MAX_SIZE)
+                .unwrap();
+        assert_eq!(format, None);
+        assert_eq!(output, [1, 2, 3]);
+
+        assert!(matches!(
+            decompress_with_header(&[UNCOMPRESSED_TYPE, 0x08, 0x00, 0x00, 1, 2, 3, 4], MAX_SIZE),
+            Err(DecompressionError::UnexpectedEnd)
+        ));
+
+        let data = mixed_data();
+        let (format, output) = decompress_with_header(&lz11_compress(&data), MAX_SIZE).unwrap();
+        assert_eq!(format, Some(CompressionFormat::Lz11));
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn extended_header_size() {
+        let mut header = Vec::new();
+        write_header(&mut header, 0x10, 0x100_0000);
+        assert_eq!(header, [0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(
+            read_header(&mut header.as_slice(), MAX_SIZE).unwrap(),
+            (0x10, 0x100_0000)
+        );
+
+        let mut header = Vec::new();
+        write_header(&mut header, 0x10, 0xFF_FFFF);
+        assert_eq!(header, [0x10, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(
+            read_header(&mut header.as_slice(), MAX_SIZE).unwrap(),
+            (0x10, 0xFF_FFFF)
+        );
+    }
+
+    #[test]
+    fn refuses_sizes_over_the_limit() {
+        let data = mixed_data();
+        for format in [
+            CompressionFormat::Lz10,
+            CompressionFormat::Lz11,
+            CompressionFormat::Huffman4,
+            CompressionFormat::Huffman8,
+            CompressionFormat::Rle,
+            CompressionFormat::Blz,
+        ] {
+            let compressed = format.compress(&data);
+            assert_eq!(format.decompress(&compressed, data.len()).unwrap(), data);
+            assert!(matches!(
+                format.decompress(&compressed, data.len() - 1),
+                Err(DecompressionError::SizeLimitExceeded(size, max_size))
+                    if size == data.len() && max_size == data.len() - 1
+            ));
+        }
+
+        // A LZ11 header claiming 4 GiB, checked before decompressing anything
+        let lz11 = [0x11, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert!(matches!(
+            decompress(&lz11, MAX_SIZE),
+            Err(DecompressionError::SizeLimitExceeded(0xFFFF_FFFF, MAX_SIZE))
+        ));
+
+        let uncompressed = [UNCOMPRESSED_TYPE, 0x04, 0x00, 0x00, 1, 2, 3, 4];
+        assert!(matches!(
+            decompress_with_header(&uncompressed, 3),
+            Err(DecompressionError::SizeLimitExceeded(4, 3))
+        ));
+    }
+
+    #[test]
+    fn back_reference_bounds() {
+        let mut output = vec![1, 2, 3];
+        copy_back_reference(&mut output, 2, 5).unwrap();
+        assert_eq!(output, [1, 2, 3, 2, 3, 2, 3, 2]);
+
+        for displacement in [0, 9] {
+            assert!(matches!(
+                copy_back_reference(&mut output, displacement, 3),
+                Err(DecompressionError::InvalidDisplacement(found, 8)) if found == displacement
+            ));
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use super::{
+    lz::{find_tokens, write_blocks, Token},
+    DecompressionError,
+};
+
+/*
+ * BLZ is decompressed backwards and in place, from the end of the compressed data,
+ * so it ends with a footer instead of starting with a header:
+ * - 0x0: compressed data size in bits 0-23 (counting the footer), footer size in bits 24-31
+ * - 0x4: decompressed size minus the size of the whole compressed file
+ *
+ * Data before the compressed part is left uncompressed, and the footer may be preceded
+ * by 0xFF padding bytes. The compressed part is read backwards like LZ10 blocks,
+ * but back-references are little endian halfwords holding the length minus 3 in bits 12-15
+ * and the displacement minus 3 in bits 0-11, relative to the end of the data being copied.
+ *
+ * See https://www.3dbrew.org/wiki/ExeFS#.code for more info
+*/
+
+const FOOTER_SIZE: usize = 8;
+const MIN_DISPLACEMENT: usize = 3;
+const MAX_DISPLACEMENT: usize = 0xFFF + MIN_DISPLACEMENT;
+const MAX_MATCH_LENGTH: usize = 0xF + 3;
+/// A flag byte followed by 8 back-references of 2 bytes decompresses to at most 144 bytes
+const MAX_COMPRESSION_RATIO: usize = 9;
+
+/// Decompresses the whole data, as it is read backwards it can't be streamed
+pub fn blz_decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>, DecompressionError> {
+    let footer_start = data
+        .len()
+        .checked_sub(FOOTER_SIZE)
+        .ok_or(DecompressionError::InvalidBlzFooter)?;
+    let footer = &data[footer_start..];
+    let sizes = u32::from_le_bytes(footer[..4].try_into().unwrap());
+    let extra_size = u32::from_le_bytes(footer[4..].try_into().unwrap()) as usize;
+
+    let compressed_size = (sizes & 0xFF_FFFF) as usize;
+    let footer_size = (sizes >> 24) as usize;
+    if footer_size < FOOTER_SIZE
+        || footer_size > compressed_size
+        || compressed_size > data.len()
+        || extra_size > compressed_size * MAX_COMPRESSION_RATIO
+    {
+        return Err(DecompressionError::InvalidBlzFooter);
+    }
+
+    let decompressed_size = data.len() + extra_size;
+    if decompressed_size > max_size {
+        return Err(DecompressionError::SizeLimitExceeded(
+            decompressed_size,
+            max_size,
+        ));
+    }
+    let mut output = data.to_vec();
+    output.resize(decompressed_size, 0);
+
+    let compressed_start = data.len() - compressed_size;
+    let mut input_position = data.len() - footer_size;
+    let mut output_position = decompressed_size;
+    let mut next_byte = || {
+        if input_position <= compressed_start {
+            return Err(DecompressionError::UnexpectedEnd);
+        }
+        input_position -= 1;
+        Ok(data[input_position])
+    };
+
+    while output_position > compressed_start {
+        let flags = next_byte()?;
+        for bit in (0..8).rev() {
+            if output_position <= compressed_start {
+                break;
+            }
+
+            if flags & (1 << bit) == 0 {
+                output_position -= 1;
+                output[output_position] = next_byte()?;
+                continue;
+            }
+
+            let high = usize::from(next_byte()?);
+            let low = usize::from(next_byte()?);
+            let length = (high >> 4) + 3;
+            let displacement = (((high & 0xF) << 8) | low) + MIN_DISPLACEMENT;
+            // Copies can neither overwrite the uncompressed part nor read past the end
+            if length > output_position - compressed_start
+                || output_position + displacement > decompressed_size
+            {
+                return Err(DecompressionError::InvalidDisplacement(
+                    displacement,
+                    decompressed_size - output_position,
+                ));
+            }
+
+            for _ in 0..length {
+                output_position -= 1;
+                output[output_position] = output[output_position + displacement];
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+/// Compresses data, which is returned as is when compression doesn't make it smaller
+pub fn blz_compress(data: &[u8]) -> Vec<u8> {
+    // Compressing the reversed data turns BLZ into plain LZ77
+    let reversed = data.iter().rev().copied().collect::<Vec<_>>();
+    let tokens = find_tokens(
+        &reversed,
+        MIN_DISPLACEMENT,
+        MAX_DISPLACEMENT,
+        MAX_MATCH_LENGTH,
+    );
+
+    // Decompressing in place must never overwrite compressed data not read yet,
+    // so only the tokens up to where compression saves the most are kept,
+    // leaving the start of the data uncompressed
+    let mut decompressed_size = 0;
+    let mut compressed_size = 0;
+    let mut best = (0, 0, 0);
+    for (index, token) in tokens.iter().enumerate() {
+        if index % 8 == 0 {
+            compressed_size += 1;
+        }
+        compressed_size += match token {
+            Token::Literal(_) => 1,
+            Token::BackReference { .. } => 2,
+        };
+        decompressed_size += token.length();
+
+        let (_, best_decompressed_size, best_compressed_size) = best;
+        if decompressed_size + best_compressed_size > best_decompressed_size + compressed_size {
+            best = (index + 1, decompressed_size, compressed_size);
+        }
+    }
+    let (token_count, decompressed_size, _) = best;
+
+    let mut stream = Vec::new();
+    write_blocks(
+        &tokens[..token_count],
+        &mut stream,
+        |stream, length, displacement| {
+            let value = ((length - 3) << 12) | (displacement - MIN_DISPLACEMENT);
+            stream.push((value >> 8) as u8);
+            stream.push((value & 0xFF) as u8);
+        },
+    );
+
+    let mut output = data[..data.len() - decompressed_size].to_vec();
+    output.extend(stream.iter().rev());
+    let padding = output.len().next_multiple_of(4) - output.len();
+    output.resize(output.len() + padding, 0xFF);
+
+    let footer_size = FOOTER_SIZE + padding;
+    let compressed_size = stream.len() + footer_size;
+    let total_size = output.len() + FOOTER_SIZE;
+    if total_size >= data.len() || compressed_size > 0xFF_FFFF {
+        return data.to_vec();
+    }
+
+    let sizes = compressed_size as u32 | ((footer_size as u32) << 24);
+    output.extend_from_slice(&sizes.to_le_bytes());
+    output.extend_from_slice(&((data.len() - total_size) as u32).to_le_bytes());
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::compression::tests::{mixed_data, pseudo_random, MAX_SIZE};
+
+    #[test]
+    fn round_trip() {
+        for data in [
+            b"abcabcabcabcabcabcabcabcabcabcabcabcabcabc".to_vec(),
+            vec![0x55; 0x1000],
+            mixed_data(),
+        ] {
+            let compressed = blz_compress(&data);
+            assert!(compressed.len() < data.len());
+            assert_eq!(blz_decompress(&compressed, MAX_SIZE).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn incompressible_data_is_returned_uncompressed() {
+        for data in [b"abc".to_vec(), pseudo_random(0x1000, 5)] {
+            assert_eq!(blz_compress(&data), data);
+        }
+    }
+
+    #[test]
+    fn keeps_the_uncompressed_start() {
+        // Noise followed by a run, only the run gets compressed
+        let mut data = pseudo_random(0x100, 6);
+        data.extend(std::iter::repeat_n(0x00, 0x400));
+        let compressed = blz_compress(&data);
+        assert_eq!(compressed[..0x100], data[..0x100]);
+        assert_eq!(blz_decompress(&compressed, MAX_SIZE).unwrap(), data);
+    }
+
+    fn footer(compressed_size: u32, footer_size: u32, extra_size: u32) -> [u8; 8] {
+        let mut footer = [0u8; 8];
+        footer[..4].copy_from_slice(&(compressed_size | (footer_size << 24)).to_le_bytes());
+        footer[4..].copy_from_slice(&extra_size.to_le_bytes());
+        footer
+    }
+
+    #[test]
+    fn rejects_bad_footers() {
+        let data = [0x00; 0x10];
+        for footer in [
+            // Footer smaller than itself
+            footer(0x10, 4, 0),
+            // Footer larger than the compressed data
+            footer(0x08, 0x0C, 0),
+            // Compressed data larger than the whole file
+            footer(0x20, 8, 0),
+            // More decompressed data than the compressed data can possibly hold
+            footer(0x10, 8, 0x10 * 9 + 1),
+        ] {
+            let mut compressed = data.to_vec();
+            compressed.extend_from_slice(&footer);
+            assert!(matches!(
+                blz_decompress(&compressed, MAX_SIZE),
+                Err(DecompressionError::InvalidBlzFooter)
+            ));
+        }
+
+        assert!(matches!(
+            blz_decompress(&[0x00; 7], MAX_SIZE),
+            Err(DecompressionError::InvalidBlzFooter)
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        // The footer claims more decompressed data than the compressed data holds
+        let mut compressed = vec![0x00, 0x00];
+        compressed.extend_from_slice(&footer(0x0A, 8, 0x10));
+        assert!(matches!(
+            blz_decompress(&compressed, MAX_SIZE),
+            Err(DecompressionError::UnexpectedEnd)
+        ));
+    }
+
+    #[test]
+    fn rejects_back_references_past_the_end() {
+        // The first token copies from 3 bytes after the end of the data
+        let mut compressed = vec![0x00, 0x00, 0x80];
+        compressed.extend_from_slice(&footer(0x0B, 8, 3));
+        assert!(matches!(
+            blz_decompress(&compressed, MAX_SIZE),
+            Err(DecompressionError::InvalidDisplacement(3, 0))
+        ));
+    }
+}
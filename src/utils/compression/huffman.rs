@@ -0,0 +1,406 @@
+use std::{cmp::Reverse, collections::BinaryHeap, io::Read};
+
+use super::{output_buffer, read_header, read_u8, write_header, DecompressionError};
+
+/*
+ * Huffman compressed data is made of a tree table followed by a bitstream
+ * of little endian 32 bits words, read from their most significant bit.
+ *
+ * The tree table starts with its size in halfwords minus one, followed by the root node.
+ * Each node holds an offset to its pair of children in bits 0-5,
+ * the children being at (node address & !1) + offset * 2 + 2 for bit 0 and the next byte for bit 1,
+ * while bits 7 and 6 flag whether each child is a leaf holding a data unit instead of another node.
+ *
+ * Data units are 4 or 8 bits, with 4 bits units filling bytes from their low nibble.
+*/
+
+pub(super) const HUFFMAN4_TYPE: u8 = 0x24;
+pub(super) const HUFFMAN8_TYPE: u8 = 0x28;
+
+const NODE_OFFSET_MASK: u8 = 0x3F;
+const CHILD0_IS_LEAF: u8 = 0x80;
+const CHILD1_IS_LEAF: u8 = 0x40;
+const ROOT_INDEX: usize = 1;
+
+fn first_child_index(node_index: usize, node: u8) -> usize {
+    (node_index & !1) + usize::from(node & NODE_OFFSET_MASK) * 2 + 2
+}
+
+pub fn huffman_decompress<R: Read>(
+    f: &mut R,
+    max_size: usize,
+) -> Result<Vec<u8>, DecompressionError> {
+    let (compression_type, size) = read_header(f, max_size)?;
+    let unit_bits = match compression_type {
+        HUFFMAN4_TYPE => 4,
+        HUFFMAN8_TYPE => 8,
+        _ => {
+            return Err(DecompressionError::UnexpectedType(
+                compression_type,
+                HUFFMAN8_TYPE,
+            ));
+        }
+    };
+
+    let tree_size_byte = read_u8(f)?;
+    let mut tree = vec![0u8; (usize::from(tree_size_byte) + 1) * 2];
+    tree[0] = tree_size_byte;
+    f.read_exact(&mut tree[1..])?;
+
+    let mut output = output_buffer(size);
+    let mut pending_nibble = None;
+    let mut node_index = ROOT_INDEX;
+    'bitstream: while output.len() < size {
+        let mut word = [0u8; 4];
+        f.read_exact(&mut word)?;
+        let word = u32::from_le_bytes(word);
+
+        for bit in (0..32).rev() {
+            let node = tree[node_index];
+            let child_index = first_child_index(node_index, node) + ((word >> bit) & 1) as usize;
+            let is_leaf =
+                node & (if child_index & 1 == 0 {
+                    CHILD0_IS_LEAF
+                } else {
+                    CHILD1_IS_LEAF
+                }) != 0;
+            let child = *tree
+                .get(child_index)
+                .ok_or(DecompressionError::InvalidHuffmanTree(child_index))?;
+            if !is_leaf {
+                node_index = child_index;
+                continue;
+            }
+
+            node_index = ROOT_INDEX;
+            if unit_bits == 8 {
+                output.push(child);
+            } else if let Some(low_nibble) = pending_nibble.take() {
+                output.push(low_nibble | ((child & 0xF) << 4));
+            } else {
+                pending_nibble = Some(child & 0xF);
+            }
+
+            if output.len() >= size {
+                break 'bitstream;
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum HuffmanNode {
+    Leaf(u8),
+    Internal(usize, usize),
+}
+
+/// Builds the Huffman tree of the data units, returning its nodes and the root index.
+/// The root always has two children, even when there is a single data unit value
+fn build_tree(units: &[u8]) -> (Vec<HuffmanNode>, usize) {
+    let mut frequencies = [0usize; 256];
+    for unit in units {
+        frequencies[usize::from(*unit)] += 1;
+    }
+
+    let mut nodes = Vec::new();
+    let mut heap = BinaryHeap::new();
+    for (unit, frequency) in frequencies.iter().enumerate() {
+        if *frequency > 0 {
+            heap.push(Reverse((*frequency, nodes.len())));
+            nodes.push(HuffmanNode::Leaf(unit as u8));
+        }
+    }
+    while heap.len() < 2 {
+        let unused_unit = (0..=u8::MAX)
+            .find(|unit| {
+                !nodes
+                    .iter()
+                    .any(|node| matches!(node, HuffmanNode::Leaf(leaf) if leaf == unit))
+            })
+            .unwrap();
+        heap.push(Reverse((0, nodes.len())));
+        nodes.push(HuffmanNode::Leaf(unused_unit));
+    }
+
+    while heap.len() > 1 {
+        let Reverse((frequency0, child0)) = heap.pop().unwrap();
+        let Reverse((frequency1, child1)) = heap.pop().unwrap();
+        heap.push(Reverse((frequency0 + frequency1, nodes.len())));
+        nodes.push(HuffmanNode::Internal(child0, child1));
+    }
+
+    let root = nodes.len() - 1;
+    (nodes, root)
+}
+
+/// An internal node waiting for its children pair to be placed
+#[derive(Debug, Clone, Copy)]
+struct PendingNode {
+    node: usize,
+    table_index: usize,
+    code: u64,
+    code_length: u8,
+}
+
+/// Lays the tree out in the table format, returning the table and the code of each data unit.
+///
+/// A node can only point to a children pair up to 63 pairs after its own.
+/// Laying a subtree out depth first, starting with the child holding fewer internal nodes,
+/// keeps every pair in reach unless both children of a node hold 64 internal nodes or more,
+/// which only happens close to the root of the largest trees.
+/// So whole subtrees are laid out depth first as long as no other waiting node runs out of room,
+/// otherwise the children pair of the node waiting the longest is placed on its own
+fn write_tree(nodes: &[HuffmanNode], root: usize) -> (Vec<u8>, Vec<Option<(u64, u8)>>) {
+    const MAX_PAIR_DISTANCE: usize = (NODE_OFFSET_MASK as usize) + 1;
+
+    // Children are always built before their parent
+    let mut internal_counts = vec![0usize; nodes.len()];
+    let mut fits_depth_first = vec![true; nodes.len()];
+    for (index, node) in nodes.iter().enumerate() {
+        if let HuffmanNode::Internal(child0, child1) = *node {
+            internal_counts[index] = 1 + internal_counts[child0] + internal_counts[child1];
+            fits_depth_first[index] = fits_depth_first[child0]
+                && fits_depth_first[child1]
+                && internal_counts[child0].min(internal_counts[child1]) < MAX_PAIR_DISTANCE;
+        }
+    }
+
+    let mut table = vec![0u8; 2];
+    let mut codes = vec![None; 256];
+
+    let in_reach = |pending_node: &PendingNode, pair_index: usize| {
+        pair_index < pending_node.table_index / 2 + MAX_PAIR_DISTANCE
+    };
+
+    // Sorted by table index, so the first node is the one waiting the longest
+    let mut pending = vec![PendingNode {
+        node: root,
+        table_index: ROOT_INDEX,
+        code: 0,
+        code_length: 0,
+    }];
+    while !pending.is_empty() {
+        let pair_index = table.len() / 2 - 1;
+        // The other nodes must still be in reach when placing their pairs one after the other
+        let subtree = pending.iter().position(|candidate| {
+            let pair_index_after = pair_index + internal_counts[candidate.node];
+            fits_depth_first[candidate.node]
+                && in_reach(candidate, pair_index)
+                && pending
+                    .iter()
+                    .filter(|other| other.table_index != candidate.table_index)
+                    .enumerate()
+                    .all(|(order, other)| in_reach(other, pair_index_after + order))
+        });
+
+        match subtree {
+            Some(position) => {
+                let mut stack = vec![pending.remove(position)];
+                while let Some(pending_node) = stack.pop() {
+                    let mut children =
+                        write_children_pair(nodes, pending_node, &mut table, &mut codes);
+                    // The child with fewer internal nodes ends up on top of the stack
+                    children.sort_by_key(|child| Reverse(internal_counts[child.node]));
+                    stack.extend(children);
+                }
+            }
+            None => {
+                let pending_node = pending.remove(0);
+                pending.extend(write_children_pair(
+                    nodes,
+                    pending_node,
+                    &mut table,
+                    &mut codes,
+                ));
+            }
+        }
+    }
+
+    // The bitstream starts word aligned
+    table.resize(table.len().next_multiple_of(4), 0);
+    table[0] = (table.len() / 2 - 1) as u8;
+    (table, codes)
+}
+
+/// Appends the children pair of a node to the table, returning its internal children
+fn write_children_pair(
+    nodes: &[HuffmanNode],
+    pending_node: PendingNode,
+    table: &mut Vec<u8>,
+    codes: &mut [Option<(u64, u8)>],
+) -> Vec<PendingNode> {
+    let PendingNode {
+        node,
+        table_index,
+        code,
+        code_length,
+    } = pending_node;
+    let HuffmanNode::Internal(child0, child1) = nodes[node] else {
+        unreachable!("Only internal nodes have children pairs");
+    };
+
+    let offset = table.len() / 2 - 1 - table_index / 2;
+    assert!(
+        offset <= usize::from(NODE_OFFSET_MASK),
+        "Huffman tree is too wide"
+    );
+    table[table_index] |= offset as u8;
+
+    let mut internal_children = Vec::new();
+    for (bit, child) in [child0, child1].into_iter().enumerate() {
+        let child_index = table.len();
+        let child_code = (code << 1) | bit as u64;
+        match nodes[child] {
+            HuffmanNode::Leaf(unit) => {
+                table[table_index] |= if bit == 0 {
+                    CHILD0_IS_LEAF
+                } else {
+                    CHILD1_IS_LEAF
+                };
+                table.push(unit);
+                codes[usize::from(unit)] = Some((child_code, code_length + 1));
+            }
+            HuffmanNode::Internal(..) => {
+                table.push(0);
+                internal_children.push(PendingNode {
+                    node: child,
+                    table_index: child_index,
+                    code: child_code,
+                    code_length: code_length + 1,
+                });
+            }
+        }
+    }
+    internal_children
+}
+
+/// Compresses data with 4 or 8 bits data units
+pub fn huffman_compress(data: &[u8], unit_bits: u8) -> Vec<u8> {
+    let units = if unit_bits == 4 {
+        data.iter()
+            .flat_map(|byte| [byte & 0xF, byte >> 4])
+            .collect::<Vec<_>>()
+    } else {
+        data.to_vec()
+    };
+
+    let (nodes, root) = build_tree(&units);
+    let (table, codes) = write_tree(&nodes, root);
+
+    let mut output = Vec::new();
+    let compression_type = if unit_bits == 4 {
+        HUFFMAN4_TYPE
+    } else {
+        HUFFMAN8_TYPE
+    };
+    write_header(&mut output, compression_type, data.len());
+    output.extend_from_slice(&table);
+
+    let mut word = 0u32;
+    let mut word_bits = 0;
+    for unit in units {
+        let (code, code_length) = codes[usize::from(unit)].unwrap();
+        for bit in (0..code_length).rev() {
+            word = (word << 1) | ((code >> bit) & 1) as u32;
+            word_bits += 1;
+            if word_bits == 32 {
+                output.extend_from_slice(&word.to_le_bytes());
+                word = 0;
+                word_bits = 0;
+            }
+        }
+    }
+    if word_bits > 0 {
+        output.extend_from_slice(&(word << (32 - word_bits)).to_le_bytes());
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::compression::tests::{mixed_data, pseudo_random, MAX_SIZE};
+
+    fn round_trip(data: &[u8], unit_bits: u8) {
+        let compressed = huffman_compress(data, unit_bits);
+        assert_eq!(
+            huffman_decompress(&mut compressed.as_slice(), MAX_SIZE).unwrap(),
+            data,
+            "{unit_bits} bits units"
+        );
+    }
+
+    #[test]
+    fn round_trip_both_unit_sizes() {
+        for data in [
+            b"abracadabra".to_vec(),
+            pseudo_random(0x1000, 3),
+            mixed_data(),
+        ] {
+            round_trip(&data, 4);
+            round_trip(&data, 8);
+        }
+    }
+
+    #[test]
+    fn single_symbol_tree() {
+        // The root still gets two children, one of them never used
+        round_trip(&[0x41; 100], 8);
+        round_trip(&[0x00; 100], 4);
+        round_trip(&[0x41], 8);
+    }
+
+    #[test]
+    fn full_256_symbol_tree() {
+        // Skewed frequencies make a deep tree, testing the children pair placement
+        let data: Vec<u8> = (0..=u8::MAX)
+            .flat_map(|byte| std::iter::repeat_n(byte, usize::from(byte) * 8 + 1))
+            .collect();
+        let (nodes, _) = build_tree(&data);
+        assert_eq!(
+            nodes
+                .iter()
+                .filter(|node| matches!(node, HuffmanNode::Leaf(_)))
+                .count(),
+            256
+        );
+        round_trip(&data, 8);
+
+        let fibonacci_like: Vec<u8> = (0..=u8::MAX)
+            .flat_map(|byte| std::iter::repeat_n(byte, 1 << (byte / 16)))
+            .collect();
+        round_trip(&fibonacci_like, 8);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let compressed = huffman_compress(&mixed_data(), 8);
+        for len in [0, 4, 6, compressed.len() / 2, compressed.len() - 4] {
+            assert!(matches!(
+                huffman_decompress(&mut &compressed[..len], MAX_SIZE),
+                Err(DecompressionError::UnexpectedEnd)
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_child_outside_of_the_tree() {
+        // A 2 bytes tree whose root points its children 63 pairs further
+        let compressed = [HUFFMAN8_TYPE, 0x01, 0x00, 0x00, 0x00, 0x3F, 0, 0, 0, 0];
+        assert!(matches!(
+            huffman_decompress(&mut compressed.as_slice(), MAX_SIZE),
+            Err(DecompressionError::InvalidHuffmanTree(128))
+        ));
+    }
+
+    #[test]
+    fn rejects_unexpected_type() {
+        assert!(matches!(
+            huffman_decompress(&mut [0x20, 0x01, 0x00, 0x00].as_slice(), MAX_SIZE),
+            Err(DecompressionError::UnexpectedType(0x20, HUFFMAN8_TYPE))
+        ));
+    }
+}
@@ -0,0 +1,373 @@
+use std::io::Read;
+
+use super::{
+    copy_back_reference, output_buffer, pad_to_word, read_header, read_u8, write_header,
+    DecompressionError,
+};
+
+/*
+ * LZ10 and LZ11 split data in blocks of 8 entries, each one preceded by a flag byte
+ * read from its most significant bit: a clear bit means a literal byte,
+ * while a set bit means a back-reference to data up to 4096 bytes behind.
+ *
+ * LZ10 back-references take 2 bytes, for 3 to 18 bytes long copies.
+ * LZ11 back-references take 2 to 4 bytes depending on the high nibble of the first one,
+ * for copies up to 0x10110 bytes long.
+*/
+
+pub(super) const LZ10_TYPE: u8 = 0x10;
+pub(super) const LZ11_TYPE: u8 = 0x11;
+
+const MIN_MATCH_LENGTH: usize = 3;
+const LZ10_MAX_MATCH_LENGTH: usize = 0x12;
+const LZ11_MAX_MATCH_LENGTH: usize = 0x10110;
+const MAX_DISPLACEMENT: usize = 0x1000;
+
+pub fn lz10_decompress<R: Read>(f: &mut R, max_size: usize) -> Result<Vec<u8>, DecompressionError> {
+    let (compression_type, size) = read_header(f, max_size)?;
+    if compression_type != LZ10_TYPE {
+        return Err(DecompressionError::UnexpectedType(
+            compression_type,
+            LZ10_TYPE,
+        ));
+    }
+
+    decompress_blocks(f, size, |f| {
+        let first = usize::from(read_u8(f)?);
+        let second = usize::from(read_u8(f)?);
+        Ok(((first >> 4) + 3, ((first & 0xF) << 8) | second))
+    })
+}
+
+pub fn lz11_decompress<R: Read>(f: &mut R, max_size: usize) -> Result<Vec<u8>, DecompressionError> {
+    let (compression_type, size) = read_header(f, max_size)?;
+    if compression_type != LZ11_TYPE {
+        return Err(DecompressionError::UnexpectedType(
+            compression_type,
+            LZ11_TYPE,
+        ));
+    }
+
+    decompress_blocks(f, size, |f| {
+        let first = usize::from(read_u8(f)?);
+        let second = usize::from(read_u8(f)?);
+        Ok(match first >> 4 {
+            0 => {
+                let third = usize::from(read_u8(f)?);
+                let length = (((first & 0xF) << 4) | (second >> 4)) + 0x11;
+                (length, ((second & 0xF) << 8) | third)
+            }
+            1 => {
+                let third = usize::from(read_u8(f)?);
+                let fourth = usize::from(read_u8(f)?);
+                let length = (((first & 0xF) << 12) | (second << 4) | (third >> 4)) + 0x111;
+                (length, ((third & 0xF) << 8) | fourth)
+            }
+            indicator => (indicator + 1, ((first & 0xF) << 8) | second),
+        })
+    })
+}
+
+/// Decodes flag blocks, with `read_back_reference` returning the length
+/// and displacement (minus one) of each back-reference
+fn decompress_blocks<R: Read>(
+    f: &mut R,
+    size: usize,
+    read_back_reference: impl Fn(&mut R) -> Result<(usize, usize), DecompressionError>,
+) -> Result<Vec<u8>, DecompressionError> {
+    let mut output = output_buffer(size);
+    while output.len() < size {
+        let flags = read_u8(f)?;
+        for bit in (0..8).rev() {
+            if output.len() >= size {
+                break;
+            }
+
+            if flags & (1 << bit) == 0 {
+                output.push(read_u8(f)?);
+                continue;
+            }
+
+            let (length, displacement) = read_back_reference(f)?;
+            copy_back_reference(&mut output, displacement + 1, length)?;
+        }
+    }
+
+    output.truncate(size);
+    Ok(output)
+}
+
+pub fn lz10_compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    write_header(&mut output, LZ10_TYPE, data.len());
+
+    let tokens = find_tokens(data, 1, MAX_DISPLACEMENT, LZ10_MAX_MATCH_LENGTH);
+    write_blocks(&tokens, &mut output, |output, length, displacement| {
+        let length = length - 3;
+        let displacement = displacement - 1;
+        output.push(((length << 4) | (displacement >> 8)) as u8);
+        output.push((displacement & 0xFF) as u8);
+    });
+
+    pad_to_word(&mut output);
+    output
+}
+
+pub fn lz11_compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    write_header(&mut output, LZ11_TYPE, data.len());
+
+    let tokens = find_tokens(data, 1, MAX_DISPLACEMENT, LZ11_MAX_MATCH_LENGTH);
+    write_blocks(&tokens, &mut output, |output, length, displacement| {
+        let displacement = displacement - 1;
+        match length {
+            ..0x11 => {
+                output.push((((length - 1) << 4) | (displacement >> 8)) as u8);
+            }
+            0x11..0x111 => {
+                let length = length - 0x11;
+                output.push((length >> 4) as u8);
+                output.push((((length & 0xF) << 4) | (displacement >> 8)) as u8);
+            }
+            _ => {
+                let length = length - 0x111;
+                output.push((0x10 | (length >> 12)) as u8);
+                output.push(((length >> 4) & 0xFF) as u8);
+                output.push((((length & 0xF) << 4) | (displacement >> 8)) as u8);
+            }
+        }
+        output.push((displacement & 0xFF) as u8);
+    });
+
+    pad_to_word(&mut output);
+    output
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Token {
+    Literal(u8),
+    BackReference { length: usize, displacement: usize },
+}
+
+impl Token {
+    /// Amount of decompressed bytes produced by the token
+    pub(super) fn length(self) -> usize {
+        match self {
+            Token::Literal(_) => 1,
+            Token::BackReference { length, .. } => length,
+        }
+    }
+}
+
+/// Writes tokens in blocks of 8 preceded by their flag byte,
+/// with `write_back_reference` encoding the length and displacement of each back-reference
+pub(super) fn write_blocks(
+    tokens: &[Token],
+    output: &mut Vec<u8>,
+    write_back_reference: impl Fn(&mut Vec<u8>, usize, usize),
+) {
+    for block in tokens.chunks(8) {
+        let flags_position = output.len();
+        output.push(0);
+
+        for (index, token) in block.iter().enumerate() {
+            match *token {
+                Token::Literal(byte) => output.push(byte),
+                Token::BackReference {
+                    length,
+                    displacement,
+                } => {
+                    output[flags_position] |= 0x80 >> index;
+                    write_back_reference(output, length, displacement);
+                }
+            }
+        }
+    }
+}
+
+/// Hash chains of the positions starting with the same 3 bytes, most recent first
+struct HashChains {
+    heads: Vec<usize>,
+    previous: Vec<usize>,
+}
+
+impl HashChains {
+    const HASH_BITS: u32 = 16;
+    const NONE: usize = usize::MAX;
+
+    fn new(size: usize) -> Self {
+        HashChains {
+            heads: vec![Self::NONE; 1 << Self::HASH_BITS],
+            previous: vec![Self::NONE; size],
+        }
+    }
+
+    fn hash(data: &[u8], position: usize) -> usize {
+        let value = u32::from(data[position])
+            | (u32::from(data[position + 1]) << 8)
+            | (u32::from(data[position + 2]) << 16);
+        (value.wrapping_mul(0x9E37_79B1) >> (32 - Self::HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, data: &[u8], position: usize) {
+        if position + MIN_MATCH_LENGTH <= data.len() {
+            let hash = Self::hash(data, position);
+            self.previous[position] = self.heads[hash];
+            self.heads[hash] = position;
+        }
+    }
+
+    fn candidates(&self, data: &[u8], position: usize) -> impl Iterator<Item = usize> {
+        let head = if position + MIN_MATCH_LENGTH <= data.len() {
+            self.heads[Self::hash(data, position)]
+        } else {
+            Self::NONE
+        };
+        std::iter::successors(Some(head), |candidate| {
+            self.previous.get(*candidate).copied()
+        })
+        .take_while(|candidate| *candidate != Self::NONE)
+    }
+}
+
+/// Greedily splits data into literals and the longest back-references found
+pub(super) fn find_tokens(
+    data: &[u8],
+    min_displacement: usize,
+    max_displacement: usize,
+    max_length: usize,
+) -> Vec<Token> {
+    const MAX_CHAIN_LENGTH: usize = 256;
+
+    let mut chains = HashChains::new(data.len());
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let max_length = max_length.min(data.len() - position);
+        let mut best_length = 0;
+        let mut best_displacement = 0;
+
+        for candidate in chains
+            .candidates(data, position)
+            .take(MAX_CHAIN_LENGTH)
+            .take_while(|candidate| position - candidate <= max_displacement)
+            .filter(|candidate| position - candidate >= min_displacement)
+        {
+            let length = (0..max_length)
+                .take_while(|offset| data[candidate + offset] == data[position + offset])
+                .count();
+            if length > best_length {
+                best_length = length;
+                best_displacement = position - candidate;
+                if length == max_length {
+                    break;
+                }
+            }
+        }
+
+        let token = if best_length >= MIN_MATCH_LENGTH {
+            Token::BackReference {
+                length: best_length,
+                displacement: best_displacement,
+            }
+        } else {
+            Token::Literal(data[position])
+        };
+        for covered_position in position..position + token.length() {
+            chains.insert(data, covered_position);
+        }
+        position += token.length();
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::compression::tests::{mixed_data, pseudo_random, MAX_SIZE};
+
+    #[test]
+    fn lz10_round_trip() {
+        for data in [
+            Vec::new(),
+            b"a".to_vec(),
+            b"abcabcabcabcabcabcabcabcabc".to_vec(),
+            pseudo_random(0x1000, 1),
+            mixed_data(),
+        ] {
+            assert_eq!(
+                lz10_decompress(&mut lz10_compress(&data).as_slice(), MAX_SIZE).unwrap(),
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn lz11_round_trip() {
+        // Long runs need the 3 and 4 bytes back-references
+        for data in [
+            Vec::new(),
+            b"a".to_vec(),
+            vec![0x55; 0x20],
+            vec![0x55; 0x200],
+            vec![0x55; 0x20000],
+            pseudo_random(0x1000, 2),
+            mixed_data(),
+        ] {
+            assert_eq!(
+                lz11_decompress(&mut lz11_compress(&data).as_slice(), MAX_SIZE).unwrap(),
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unexpected_type() {
+        let compressed = lz10_compress(b"abcabcabc");
+        assert!(matches!(
+            lz11_decompress(&mut compressed.as_slice(), MAX_SIZE),
+            Err(DecompressionError::UnexpectedType(LZ10_TYPE, LZ11_TYPE))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let data = mixed_data();
+        for compressed in [lz10_compress(&data), lz11_compress(&data)] {
+            for len in [0, 3, 5, compressed.len() / 2, compressed.len() - 8] {
+                let mut truncated = &compressed[..len];
+                let result = if compressed[0] == LZ10_TYPE {
+                    lz10_decompress(&mut truncated, MAX_SIZE)
+                } else {
+                    lz11_decompress(&mut truncated, MAX_SIZE)
+                };
+                assert!(matches!(result, Err(DecompressionError::UnexpectedEnd)));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_back_references_before_the_start() {
+        // A literal followed by a copy of 3 bytes from 2 bytes behind
+        let lz10 = [LZ10_TYPE, 0x04, 0x00, 0x00, 0x40, b'a', 0x00, 0x01];
+        assert!(matches!(
+            lz10_decompress(&mut lz10.as_slice(), MAX_SIZE),
+            Err(DecompressionError::InvalidDisplacement(2, 1))
+        ));
+
+        let lz11 = [LZ11_TYPE, 0x04, 0x00, 0x00, 0x40, b'a', 0x20, 0x01];
+        assert!(matches!(
+            lz11_decompress(&mut lz11.as_slice(), MAX_SIZE),
+            Err(DecompressionError::InvalidDisplacement(2, 1))
+        ));
+
+        // The same copy from 1 byte behind is fine
+        let lz10 = [LZ10_TYPE, 0x04, 0x00, 0x00, 0x40, b'a', 0x00, 0x00];
+        assert_eq!(
+            lz10_decompress(&mut lz10.as_slice(), MAX_SIZE).unwrap(),
+            b"aaaa"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::io::Read;
+
+use super::{output_buffer, pad_to_word, read_header, read_u8, write_header, DecompressionError};
+
+/*
+ * RLE compressed data is made of blocks starting with a flag byte:
+ * when bit 7 is set, the next byte is repeated (flag & 0x7F) + 3 times,
+ * otherwise the next (flag & 0x7F) + 1 bytes are copied as is.
+*/
+
+pub(super) const RLE_TYPE: u8 = 0x30;
+
+const MIN_RUN_LENGTH: usize = 3;
+const MAX_RUN_LENGTH: usize = 0x7F + MIN_RUN_LENGTH;
+const MAX_LITERAL_LENGTH: usize = 0x80;
+
+pub fn rle_decompress<R: Read>(f: &mut R, max_size: usize) -> Result<Vec<u8>, DecompressionError> {
+    let (compression_type, size) = read_header(f, max_size)?;
+    if compression_type != RLE_TYPE {
+        return Err(DecompressionError::UnexpectedType(
+            compression_type,
+            RLE_TYPE,
+        ));
+    }
+
+    let mut output = output_buffer(size);
+    while output.len() < size {
+        let flag = read_u8(f)?;
+        let length = usize::from(flag & 0x7F);
+        if flag & 0x80 != 0 {
+            let byte = read_u8(f)?;
+            output.extend(std::iter::repeat_n(byte, length + MIN_RUN_LENGTH));
+        } else {
+            let start = output.len();
+            output.resize(start + length + 1, 0);
+            f.read_exact(&mut output[start..])?;
+        }
+    }
+
+    output.truncate(size);
+    Ok(output)
+}
+
+pub fn rle_compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    write_header(&mut output, RLE_TYPE, data.len());
+
+    let mut literals_start = 0;
+    let mut position = 0;
+    while position < data.len() {
+        let run_length = data[position..]
+            .iter()
+            .take(MAX_RUN_LENGTH)
+            .take_while(|byte| **byte == data[position])
+            .count();
+
+        if run_length < MIN_RUN_LENGTH {
+            position += 1;
+            if position - literals_start == MAX_LITERAL_LENGTH {
+                write_literals(&mut output, &data[literals_start..position]);
+                literals_start = position;
+            }
+            continue;
+        }
+
+        write_literals(&mut output, &data[literals_start..position]);
+        output.push(0x80 | (run_length - MIN_RUN_LENGTH) as u8);
+        output.push(data[position]);
+        position += run_length;
+        literals_start = position;
+    }
+    write_literals(&mut output, &data[literals_start..]);
+
+    pad_to_word(&mut output);
+    output
+}
+
+fn write_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    if !literals.is_empty() {
+        output.push((literals.len() - 1) as u8);
+        output.extend_from_slice(literals);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::compression::tests::{mixed_data, pseudo_random, MAX_SIZE};
+
+    #[test]
+    fn round_trip() {
+        // Runs and literal blocks longer than a single flag can hold
+        for data in [
+            Vec::new(),
+            b"ab".to_vec(),
+            b"aabbbcccc".to_vec(),
+            vec![0x55; MAX_RUN_LENGTH * 3 + 1],
+            pseudo_random(MAX_LITERAL_LENGTH * 3 + 1, 4),
+            mixed_data(),
+        ] {
+            assert_eq!(
+                rle_decompress(&mut rle_compress(&data).as_slice(), MAX_SIZE).unwrap(),
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let compressed = rle_compress(&mixed_data());
+        for len in [0, 3, 5, compressed.len() / 2, compressed.len() - 8] {
+            assert!(matches!(
+                rle_decompress(&mut &compressed[..len], MAX_SIZE),
+                Err(DecompressionError::UnexpectedEnd)
+            ));
+        }
+
+        // A literal block longer than the remaining data
+        assert!(matches!(
+            rle_decompress(
+                &mut [RLE_TYPE, 0x04, 0x00, 0x00, 0x03, 1, 2].as_slice(),
+                MAX_SIZE
+            ),
+            Err(DecompressionError::UnexpectedEnd)
+        ));
+    }
+}
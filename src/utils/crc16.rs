@@ -0,0 +1,26 @@
+/// CRC16 using the MODBUS parameters (reflected 0x8005 polynomial, 0xFFFF initial value),
+/// as used by the NDS header and banner checksums
+pub fn crc16_modbus(data: &[u8]) -> u16 {
+    const POLYNOMIAL_REFLECTED: u16 = 0xA001;
+
+    data.iter().fold(0xFFFF, |crc, byte| {
+        (0..8).fold(crc ^ u16::from(*byte), |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ POLYNOMIAL_REFLECTED
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_vectors() {
+        assert_eq!(crc16_modbus(b"123456789"), 0x4B37);
+        assert_eq!(crc16_modbus(&[]), 0xFFFF);
+    }
+}
@@ -0,0 +1,69 @@
+use image::{Rgba, RgbaImage};
+
+/// Small badge drawn on top of borrowed icons, telling apart updates and DLCs from their base title
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emblem {
+    Update,
+    Dlc,
+}
+
+impl Emblem {
+    const GLYPH_SIZE: u32 = 5;
+    const GLYPH_COLOR: Rgba<u8> = Rgba([0xFF, 0xFF, 0xFF, 0xFF]);
+
+    fn color(self) -> Rgba<u8> {
+        match self {
+            Emblem::Update => Rgba([0x1E, 0x88, 0xE5, 0xFF]),
+            Emblem::Dlc => Rgba([0x43, 0xA0, 0x47, 0xFF]),
+        }
+    }
+
+    /// 5x5 glyphs, one row per byte with the leftmost pixel on the highest bit
+    fn glyph(self) -> [u8; 5] {
+        match self {
+            // An arrow pointing up
+            Emblem::Update => [0b00100, 0b01110, 0b10101, 0b00100, 0b00100],
+            // A plus sign
+            Emblem::Dlc => [0b00100, 0b00100, 0b11111, 0b00100, 0b00100],
+        }
+    }
+}
+
+/// Draws the emblem as a round badge on the bottom right corner, sized relative to the icon
+pub fn draw_emblem(img: &mut RgbaImage, emblem: Emblem) {
+    let size = (img.width().min(img.height()) * 5 / 12).max(Emblem::GLYPH_SIZE + 2);
+    let (left, top) = (
+        img.width().saturating_sub(size),
+        img.height().saturating_sub(size),
+    );
+    let radius = size as f32 / 2.0;
+    let border = (size / 10).max(1) as f32;
+
+    let glyph = emblem.glyph();
+    let glyph_size = size * 3 / 5;
+    let glyph_offset = (size - glyph_size) / 2;
+
+    for y in 0..size.min(img.height()) {
+        for x in 0..size.min(img.width()) {
+            let distance = (x as f32 + 0.5 - radius).hypot(y as f32 + 0.5 - radius);
+            if distance > radius {
+                continue;
+            }
+
+            let is_glyph = (glyph_offset..glyph_offset + glyph_size).contains(&x)
+                && (glyph_offset..glyph_offset + glyph_size).contains(&y)
+                && {
+                    let glyph_x = (x - glyph_offset) * Emblem::GLYPH_SIZE / glyph_size;
+                    let glyph_y = (y - glyph_offset) * Emblem::GLYPH_SIZE / glyph_size;
+                    glyph[glyph_y as usize] & (0b10000 >> glyph_x) != 0
+                };
+
+            let pixel = if distance > radius - border || is_glyph {
+                Emblem::GLYPH_COLOR
+            } else {
+                emblem.color()
+            };
+            img.put_pixel(left + x, top + y, pixel);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::env;
+
+/// The user locale, e.g. language "zh" and territory "TW" for "zh_TW.UTF-8"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale {
+    /// ISO 639-1 language code, in lowercase
+    pub language: String,
+    /// ISO 3166-1 territory code, in uppercase
+    pub territory: Option<String>,
+}
+
+impl Locale {
+    /// Parses a locale following the language[_territory][.codeset][@modifier] format
+    pub fn parse(locale: &str) -> Option<Self> {
+        let locale = locale.split(['.', '@']).next()?;
+        let (language, territory) = match locale.split_once('_') {
+            Some((language, territory)) => (language, Some(territory)),
+            None => (locale, None),
+        };
+
+        let language = language.to_ascii_lowercase();
+        match &language[..] {
+            "" | "c" | "posix" => None,
+            _ => Some(Locale {
+                language,
+                territory: territory
+                    .filter(|territory| !territory.is_empty())
+                    .map(str::to_ascii_uppercase),
+            }),
+        }
+    }
+}
+
+/// Returns the user locale, following the usual precedence of the locale environment variables
+pub fn user_locale() -> Option<Locale> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|locale| Locale::parse(&locale))
+}
+
+/// A language titles are localized in, such as the NDS banner and SMDH title languages
+pub trait TitleLanguage: Copy + PartialEq + Sized {
+    const ENGLISH: Self;
+
+    fn from_locale(locale: &Locale) -> Option<Self>;
+}
+
+/// The title language matching the user locale (from `LC_ALL`, `LC_MESSAGES` or `LANG`)
+pub fn user_title_language<L: TitleLanguage>() -> Option<L> {
+    user_locale().as_ref().and_then(L::from_locale)
+}
+
+pub trait LocalizedTitle {
+    type Language: TitleLanguage;
+
+    fn language(&self) -> Self::Language;
+    fn is_empty(&self) -> bool;
+}
+
+/// Picks the first title available in `languages`, in order of preference,
+/// falling back to English and then to the first available title
+pub fn best_title<T: LocalizedTitle>(
+    titles: &[T],
+    languages: impl IntoIterator<Item = T::Language>,
+) -> Option<&T> {
+    let find_title = |language: T::Language| {
+        titles
+            .iter()
+            .find(|title| title.language() == language && !title.is_empty())
+    };
+
+    languages
+        .into_iter()
+        .find_map(find_title)
+        .or_else(|| find_title(T::Language::ENGLISH))
+        .or_else(|| titles.iter().find(|title| !title.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locale(language: &str, territory: Option<&str>) -> Option<Locale> {
+        Some(Locale {
+            language: language.to_string(),
+            territory: territory.map(str::to_string),
+        })
+    }
+
+    #[test]
+    fn locale_parsing() {
+        assert_eq!(Locale::parse("en_US.UTF-8"), locale("en", Some("US")));
+        assert_eq!(Locale::parse("zh_tw"), locale("zh", Some("TW")));
+        assert_eq!(Locale::parse("de_DE@euro"), locale("de", Some("DE")));
+        assert_eq!(Locale::parse("FR"), locale("fr", None));
+        assert_eq!(Locale::parse("ja.UTF-8"), locale("ja", None));
+        for locale in ["", "C", "C.UTF-8", "POSIX"] {
+            assert_eq!(Locale::parse(locale), None);
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Language {
+        Japanese,
+        English,
+        French,
+    }
+
+    impl TitleLanguage for Language {
+        const ENGLISH: Self = Language::English;
+
+        fn from_locale(_locale: &Locale) -> Option<Self> {
+            None
+        }
+    }
+
+    impl LocalizedTitle for (Language, &str) {
+        type Language = Language;
+
+        fn language(&self) -> Language {
+            self.0
+        }
+
+        fn is_empty(&self) -> bool {
+            self.1.is_empty()
+        }
+    }
+
+    #[test]
+    fn best_title_fallbacks() {
+        let titles = [
+            (Language::Japanese, "タイトル"),
+            (Language::English, "Title"),
+            (Language::French, ""),
+        ];
+        let best_title = |languages: &[Language]| best_title(&titles, languages.iter().copied());
+
+        assert_eq!(best_title(&[Language::Japanese]), Some(&titles[0]));
+        // Empty titles are skipped, falling back to the next preferred language, then English
+        assert_eq!(
+            best_title(&[Language::French, Language::Japanese]),
+            Some(&titles[0])
+        );
+        assert_eq!(best_title(&[Language::French]), Some(&titles[1]));
+        assert_eq!(best_title(&[]), Some(&titles[1]));
+
+        let titles = [(Language::English, ""), (Language::French, "Titre")];
+        assert_eq!(
+            super::best_title(&titles, [Language::Japanese]),
+            Some(&titles[1])
+        );
+    }
+}
@@ -0,0 +1,437 @@
+use image::RgbaImage;
+
+use crate::utils::rgb888::{
+    expand_4_to_8, expand_5_to_8, Rgb565, Rgb888, Rgba4444, Rgba5551, Rgba8888,
+};
+
+/*
+ * The 3DS GPU (PICA200) stores textures in 8x8 tiles, with the tiles laid out row by row
+ * and the pixels inside each tile following Morton (Z) order.
+ * ETC1 textures are the exception: each 8x8 tile holds four 4x4 compressed blocks,
+ * also in Z order, and the pixels of each block are stored column by column.
+ *
+ * See https://www.3dbrew.org/wiki/GPU/Textures for more info
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PicaTextureFormat {
+    Rgba8,
+    Rgb8,
+    Rgba5551,
+    Rgb565,
+    Rgba4444,
+    La8,
+    Hilo8,
+    L8,
+    A8,
+    La4,
+    L4,
+    A4,
+    Etc1,
+    Etc1A4,
+}
+
+impl TryFrom<u32> for PicaTextureFormat {
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0x0 => Ok(PicaTextureFormat::Rgba8),
+            0x1 => Ok(PicaTextureFormat::Rgb8),
+            0x2 => Ok(PicaTextureFormat::Rgba5551),
+            0x3 => Ok(PicaTextureFormat::Rgb565),
+            0x4 => Ok(PicaTextureFormat::Rgba4444),
+            0x5 => Ok(PicaTextureFormat::La8),
+            0x6 => Ok(PicaTextureFormat::Hilo8),
+            0x7 => Ok(PicaTextureFormat::L8),
+            0x8 => Ok(PicaTextureFormat::A8),
+            0x9 => Ok(PicaTextureFormat::La4),
+            0xA => Ok(PicaTextureFormat::L4),
+            0xB => Ok(PicaTextureFormat::A4),
+            0xC => Ok(PicaTextureFormat::Etc1),
+            0xD => Ok(PicaTextureFormat::Etc1A4),
+            _ => Err(value),
+        }
+    }
+}
+
+impl PicaTextureFormat {
+    pub fn bits_per_pixel(&self) -> usize {
+        match self {
+            PicaTextureFormat::Rgba8 => 32,
+            PicaTextureFormat::Rgb8 => 24,
+            PicaTextureFormat::Rgba5551
+            | PicaTextureFormat::Rgb565
+            | PicaTextureFormat::Rgba4444
+            | PicaTextureFormat::La8
+            | PicaTextureFormat::Hilo8 => 16,
+            PicaTextureFormat::L8
+            | PicaTextureFormat::A8
+            | PicaTextureFormat::La4
+            | PicaTextureFormat::Etc1A4 => 8,
+            PicaTextureFormat::L4 | PicaTextureFormat::A4 | PicaTextureFormat::Etc1 => 4,
+        }
+    }
+
+    /// Amount of bytes needed to store a texture, whose dimensions must be multiples of 8
+    pub fn texture_size(&self, width: usize, height: usize) -> usize {
+        width * height * self.bits_per_pixel() / 8
+    }
+}
+
+/// Decodes a tiled PICA200 texture, returning `None` if the dimensions aren't multiples of 8
+/// or if there isn't enough data for them.
+/// The image is returned as stored, callers are responsible for flipping it if needed
+pub fn decode_texture(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    format: PicaTextureFormat,
+) -> Option<RgbaImage> {
+    if !width.is_multiple_of(8)
+        || !height.is_multiple_of(8)
+        || data.len() < format.texture_size(width, height)
+    {
+        return None;
+    }
+
+    let mut img = RgbaImage::new(u32::try_from(width).ok()?, u32::try_from(height).ok()?);
+    let pixel_count = width * height;
+
+    match format {
+        PicaTextureFormat::Etc1 | PicaTextureFormat::Etc1A4 => {
+            let has_alpha = format == PicaTextureFormat::Etc1A4;
+            let block_size = if has_alpha { 16 } else { 8 };
+
+            for (block_index, block) in data[..pixel_count / 16 * block_size]
+                .chunks_exact(block_size)
+                .enumerate()
+            {
+                let (alpha, color) = if has_alpha {
+                    let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+                    (Some(alpha), &block[8..])
+                } else {
+                    (None, block)
+                };
+                let color = u64::from_le_bytes(color.try_into().unwrap());
+                let block_pixels = decode_etc1_block(color, alpha);
+
+                // Each 8x8 tile contains 2x2 blocks in Z order
+                let tile_index = block_index / 4;
+                let tile_x = (tile_index % (width / 8)) * 8 + (block_index % 2) * 4;
+                let tile_y = (tile_index / (width / 8)) * 8 + (block_index % 4 / 2) * 4;
+
+                for (pixel_index, pixel) in block_pixels.into_iter().enumerate() {
+                    // Pixels inside ETC1 blocks are stored column by column
+                    let x = tile_x + pixel_index / 4;
+                    let y = tile_y + pixel_index % 4;
+                    #[allow(clippy::cast_possible_truncation)]
+                    img.put_pixel(x as u32, y as u32, pixel.into());
+                }
+            }
+        }
+        _ => {
+            for y in 0..height {
+                for x in 0..width {
+                    let pixel_offset = (((y >> 3) * (width >> 3) + (x >> 3)) << 6)
+                        + ((x & 1)
+                            | ((y & 1) << 1)
+                            | ((x & 2) << 1)
+                            | ((y & 2) << 2)
+                            | ((x & 4) << 2)
+                            | ((y & 4) << 3));
+
+                    let pixel = decode_pixel(data, pixel_offset, format);
+                    #[allow(clippy::cast_possible_truncation)]
+                    img.put_pixel(x as u32, y as u32, pixel.into());
+                }
+            }
+        }
+    }
+
+    Some(img)
+}
+
+fn decode_pixel(data: &[u8], pixel_offset: usize, format: PicaTextureFormat) -> Rgba8888 {
+    let bytes_per_pixel = format.bits_per_pixel() / 8;
+    let bytes = &data[pixel_offset * bytes_per_pixel..];
+    let luminance = |l: u8, a: u8| Rgba8888 {
+        r: l,
+        g: l,
+        b: l,
+        a,
+    };
+
+    match format {
+        // Multi-byte formats are little endian, so the channels are stored in reverse
+        PicaTextureFormat::Rgba8 => Rgba8888 {
+            r: bytes[3],
+            g: bytes[2],
+            b: bytes[1],
+            a: bytes[0],
+        },
+        PicaTextureFormat::Rgb8 => Rgba8888 {
+            r: bytes[2],
+            g: bytes[1],
+            b: bytes[0],
+            a: 0xFF,
+        },
+        PicaTextureFormat::Rgba5551 => Rgba8888::from(Rgba5551::from([bytes[0], bytes[1]])),
+        PicaTextureFormat::Rgb565 => {
+            Rgba8888::from(Rgb888::from(Rgb565::from([bytes[0], bytes[1]])))
+        }
+        PicaTextureFormat::Rgba4444 => Rgba8888::from(Rgba4444::from([bytes[0], bytes[1]])),
+        PicaTextureFormat::La8 => luminance(bytes[1], bytes[0]),
+        // HILO8 stores two components of a normal map, shown as red and green
+        PicaTextureFormat::Hilo8 => Rgba8888 {
+            r: bytes[1],
+            g: bytes[0],
+            b: 0x00,
+            a: 0xFF,
+        },
+        PicaTextureFormat::L8 => luminance(bytes[0], 0xFF),
+        PicaTextureFormat::A8 => luminance(0xFF, bytes[0]),
+        PicaTextureFormat::La4 => luminance(expand_4_to_8(bytes[0] >> 4), expand_4_to_8(bytes[0])),
+        PicaTextureFormat::L4 | PicaTextureFormat::A4 => {
+            // The first pixel is stored in the lower 4 bits
+            let nibble = data[pixel_offset / 2] >> ((pixel_offset % 2) * 4);
+            let value = expand_4_to_8(nibble & 0x0F);
+            if format == PicaTextureFormat::L4 {
+                luminance(value, 0xFF)
+            } else {
+                luminance(0xFF, value)
+            }
+        }
+        PicaTextureFormat::Etc1 | PicaTextureFormat::Etc1A4 => {
+            unreachable!("ETC1 textures are decoded by blocks")
+        }
+    }
+}
+
+/// Decodes a 4x4 ETC1 block, returning its pixels column by column.
+/// ETC1A4 blocks carry an additional 4 bits of alpha per pixel, in the same order
+fn decode_etc1_block(block: u64, alpha: Option<u64>) -> [Rgba8888; 16] {
+    const ETC1_MODIFIERS: [[i16; 2]; 8] = [
+        [2, 8],
+        [5, 17],
+        [9, 29],
+        [13, 42],
+        [18, 60],
+        [24, 80],
+        [33, 106],
+        [47, 183],
+    ];
+
+    #[allow(clippy::cast_possible_truncation)]
+    let bits = |shift: u32, mask: u64| ((block >> shift) & mask) as u8;
+
+    let is_differential = bits(33, 0x1) != 0;
+    let is_flipped = bits(32, 0x1) != 0;
+    let tables = [bits(37, 0x7), bits(34, 0x7)];
+
+    let base_colors: [[u8; 3]; 2] = if is_differential {
+        let first = [bits(59, 0x1F), bits(51, 0x1F), bits(43, 0x1F)];
+        let deltas = [bits(56, 0x7), bits(48, 0x7), bits(40, 0x7)];
+        let second: [u8; 3] = std::array::from_fn(|i| {
+            // The deltas are signed 3 bit values
+            let delta = i16::from(deltas[i] ^ 0x4) - 0x4;
+            u8::try_from((i16::from(first[i]) + delta) & 0x1F).unwrap()
+        });
+        [first.map(expand_5_to_8), second.map(expand_5_to_8)]
+    } else {
+        [
+            [bits(60, 0xF), bits(52, 0xF), bits(44, 0xF)].map(expand_4_to_8),
+            [bits(56, 0xF), bits(48, 0xF), bits(40, 0xF)].map(expand_4_to_8),
+        ]
+    };
+
+    std::array::from_fn(|pixel_index| {
+        let (x, y) = (pixel_index / 4, pixel_index % 4);
+        let subblock = usize::from(if is_flipped { y >= 2 } else { x >= 2 });
+
+        let index_shift = u32::try_from(pixel_index).unwrap();
+        let is_large = (block >> index_shift) & 0x1 != 0;
+        let is_negative = (block >> (index_shift + 16)) & 0x1 != 0;
+
+        let modifier = ETC1_MODIFIERS[usize::from(tables[subblock])][usize::from(is_large)];
+        let modifier = if is_negative { -modifier } else { modifier };
+        let [r, g, b] = base_colors[subblock]
+            .map(|channel| u8::try_from((i16::from(channel) + modifier).clamp(0, 0xFF)).unwrap());
+
+        #[allow(clippy::cast_possible_truncation)]
+        let a = alpha.map_or(0xFF, |alpha| {
+            expand_4_to_8((alpha >> (index_shift * 4)) as u8 & 0x0F)
+        });
+
+        Rgba8888 { r, g, b, a }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes a single 8x8 tile, returning the pixel at (x, y)
+    fn decode_pixel_at(data: &[u8], format: PicaTextureFormat, x: u32, y: u32) -> [u8; 4] {
+        decode_texture(data, 8, 8, format)
+            .unwrap()
+            .get_pixel(x, y)
+            .0
+    }
+
+    #[test]
+    fn morton_tile_addressing() {
+        // Each L8 pixel holds its own offset in the texture data
+        let data = (0..=u8::MAX).collect::<Vec<_>>();
+        let img = decode_texture(&data, 16, 16, PicaTextureFormat::L8).unwrap();
+        let offset_at = |x, y| img.get_pixel(x, y).0[0];
+
+        assert_eq!(offset_at(0, 0), 0);
+        assert_eq!(offset_at(1, 0), 1);
+        assert_eq!(offset_at(0, 1), 2);
+        assert_eq!(offset_at(1, 1), 3);
+        assert_eq!(offset_at(2, 0), 4);
+        assert_eq!(offset_at(0, 2), 8);
+        assert_eq!(offset_at(4, 0), 16);
+        assert_eq!(offset_at(0, 4), 32);
+        assert_eq!(offset_at(7, 7), 63);
+        // Tiles are laid out row by row
+        assert_eq!(offset_at(8, 0), 64);
+        assert_eq!(offset_at(0, 8), 128);
+        assert_eq!(offset_at(15, 15), 255);
+    }
+
+    #[test]
+    fn rejects_unaligned_or_short_textures() {
+        assert!(decode_texture(&[0; 0x200], 12, 8, PicaTextureFormat::Rgba8).is_none());
+        assert!(decode_texture(&[0; 0xFF], 8, 8, PicaTextureFormat::Rgba8).is_none());
+        assert!(decode_texture(&[0; 0x100], 8, 8, PicaTextureFormat::Rgba8).is_some());
+    }
+
+    #[test]
+    fn known_pixel_per_format() {
+        let texture = |pixel: &[u8], format: PicaTextureFormat| {
+            let mut data = vec![0u8; format.texture_size(8, 8)];
+            data[..pixel.len()].copy_from_slice(pixel);
+            decode_pixel_at(&data, format, 0, 0)
+        };
+
+        // Multi-byte formats are stored with their channels reversed
+        assert_eq!(
+            texture(&[0x44, 0x33, 0x22, 0x11], PicaTextureFormat::Rgba8),
+            [0x11, 0x22, 0x33, 0x44]
+        );
+        assert_eq!(
+            texture(&[0x33, 0x22, 0x11], PicaTextureFormat::Rgb8),
+            [0x11, 0x22, 0x33, 0xFF]
+        );
+        assert_eq!(
+            texture(&[0x01, 0xF8], PicaTextureFormat::Rgba5551),
+            [0xFF, 0x00, 0x00, 0xFF]
+        );
+        assert_eq!(
+            texture(&[0x1F, 0x00], PicaTextureFormat::Rgb565),
+            [0x00, 0x00, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            texture(&[0x34, 0x12], PicaTextureFormat::Rgba4444),
+            [0x11, 0x22, 0x33, 0x44]
+        );
+        assert_eq!(
+            texture(&[0x80, 0x40], PicaTextureFormat::La8),
+            [0x40, 0x40, 0x40, 0x80]
+        );
+        assert_eq!(
+            texture(&[0x20, 0x10], PicaTextureFormat::Hilo8),
+            [0x10, 0x20, 0x00, 0xFF]
+        );
+        assert_eq!(
+            texture(&[0x42], PicaTextureFormat::L8),
+            [0x42, 0x42, 0x42, 0xFF]
+        );
+        assert_eq!(
+            texture(&[0x42], PicaTextureFormat::A8),
+            [0xFF, 0xFF, 0xFF, 0x42]
+        );
+        // LA4 holds the luminance in the high nibble
+        assert_eq!(
+            texture(&[0xA5], PicaTextureFormat::La4),
+            [0xAA, 0xAA, 0xAA, 0x55]
+        );
+    }
+
+    #[test]
+    fn four_bit_formats_start_from_the_low_nibble() {
+        let mut data = vec![0u8; PicaTextureFormat::L4.texture_size(8, 8)];
+        data[0] = 0x21;
+
+        assert_eq!(
+            decode_pixel_at(&data, PicaTextureFormat::L4, 0, 0),
+            [0x11, 0x11, 0x11, 0xFF]
+        );
+        assert_eq!(
+            decode_pixel_at(&data, PicaTextureFormat::L4, 1, 0),
+            [0x22, 0x22, 0x22, 0xFF]
+        );
+        assert_eq!(
+            decode_pixel_at(&data, PicaTextureFormat::A4, 0, 0),
+            [0xFF, 0xFF, 0xFF, 0x11]
+        );
+        assert_eq!(
+            decode_pixel_at(&data, PicaTextureFormat::A4, 1, 0),
+            [0xFF, 0xFF, 0xFF, 0x22]
+        );
+    }
+
+    /// Individual mode block: base colors 0x884422 (left half) and 0x112233 (right half),
+    /// both with modifier table 0, every pixel using +2 except the bottom right one using -8
+    const ETC1_INDIVIDUAL_BLOCK: u64 = 0x8142_2300_8000_8000;
+    /// Differential mode block: base red 0x10 for the left half, with a delta of -1
+    /// for the right half, every pixel using +2 except the bottom right one using -8
+    const ETC1_DIFFERENTIAL_BLOCK: u64 = 0x8700_0002_8000_8000;
+
+    #[test]
+    fn etc1_reference_blocks() {
+        let block = decode_etc1_block(ETC1_INDIVIDUAL_BLOCK, None);
+        // Pixels are stored column by column
+        let pixel = |index: usize| {
+            let Rgba8888 { r, g, b, a } = block[index];
+            [r, g, b, a]
+        };
+        assert_eq!(pixel(0), [0x8A, 0x46, 0x24, 0xFF]);
+        assert_eq!(pixel(7), [0x8A, 0x46, 0x24, 0xFF]);
+        assert_eq!(pixel(8), [0x13, 0x24, 0x35, 0xFF]);
+        assert_eq!(pixel(15), [0x09, 0x1A, 0x2B, 0xFF]);
+
+        let block = decode_etc1_block(ETC1_DIFFERENTIAL_BLOCK, None);
+        let Rgba8888 { r, g, b, .. } = block[0];
+        assert_eq!([r, g, b], [0x86, 0x02, 0x02]);
+        // The negative modifier is clamped to 0
+        let Rgba8888 { r, g, b, .. } = block[15];
+        assert_eq!([r, g, b], [0x73, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn etc1_blocks_follow_z_order_inside_tiles() {
+        let mut data = vec![0u8; PicaTextureFormat::Etc1.texture_size(8, 8)];
+        // Second block, the top right one
+        data[0x8..0x10].copy_from_slice(&ETC1_INDIVIDUAL_BLOCK.to_le_bytes());
+        let img = decode_texture(&data, 8, 8, PicaTextureFormat::Etc1).unwrap();
+
+        assert_eq!(img.get_pixel(4, 0).0, [0x8A, 0x46, 0x24, 0xFF]);
+        assert_eq!(img.get_pixel(7, 3).0, [0x09, 0x1A, 0x2B, 0xFF]);
+        assert_eq!(img.get_pixel(0, 0).0, [0x02, 0x02, 0x02, 0xFF]);
+    }
+
+    #[test]
+    fn etc1a4_alpha_follows_the_pixel_order() {
+        let mut data = vec![0u8; PicaTextureFormat::Etc1A4.texture_size(8, 8)];
+        data[..0x8].copy_from_slice(&0xF000_0000_0000_0005u64.to_le_bytes());
+        data[0x8..0x10].copy_from_slice(&ETC1_INDIVIDUAL_BLOCK.to_le_bytes());
+        let img = decode_texture(&data, 8, 8, PicaTextureFormat::Etc1A4).unwrap();
+
+        assert_eq!(img.get_pixel(0, 0).0, [0x8A, 0x46, 0x24, 0x55]);
+        assert_eq!(img.get_pixel(3, 3).0, [0x09, 0x1A, 0x2B, 0xFF]);
+        assert_eq!(img.get_pixel(1, 0).0[3], 0x00);
+    }
+}
@@ -1,3 +1,29 @@
+/*
+ * Colors with less than 8 bits per channel are expanded by bit replication,
+ * i.e. the most significant bits are copied into the new least significant bits.
+ * A plain left shift would turn a full intensity channel (e.g. 0x1F) into 0xF8 instead of 0xFF.
+ */
+
+pub fn expand_1_to_8(value: u8) -> u8 {
+    if value & 0x01 == 0 {
+        0x00
+    } else {
+        0xFF
+    }
+}
+
+pub fn expand_4_to_8(value: u8) -> u8 {
+    (value << 4) | (value & 0x0F)
+}
+
+pub fn expand_5_to_8(value: u8) -> u8 {
+    (value << 3) | (value >> 2)
+}
+
+pub fn expand_6_to_8(value: u8) -> u8 {
+    (value << 2) | (value >> 4)
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Bgr555(pub u16);
 
@@ -16,6 +42,24 @@ impl From<[u8; 2]> for Rgb565 {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct Rgba5551(pub u16);
+
+impl From<[u8; 2]> for Rgba5551 {
+    fn from(value: [u8; 2]) -> Self {
+        Self(u16::from_le_bytes(value))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rgba4444(pub u16);
+
+impl From<[u8; 2]> for Rgba4444 {
+    fn from(value: [u8; 2]) -> Self {
+        Self(u16::from_le_bytes(value))
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Rgb888 {
     pub r: u8,
@@ -40,9 +84,9 @@ impl From<Bgr555> for Rgb888 {
         let green_value = u8::try_from((color_bytes & 0x03E0) >> 5).unwrap();
         let red_value = u8::try_from(color_bytes & 0x001F).unwrap();
 
-        let b = blue_value << 3;
-        let g = green_value << 3;
-        let r = red_value << 3;
+        let b = expand_5_to_8(blue_value);
+        let g = expand_5_to_8(green_value);
+        let r = expand_5_to_8(red_value);
 
         Self { r, g, b }
     }
@@ -65,10 +109,142 @@ impl From<Rgb565> for Rgb888 {
         let green_value = u8::try_from((color_bytes & 0x07E0) >> 5).unwrap();
         let blue_value = u8::try_from(color_bytes & 0x001F).unwrap();
 
-        let r = red_value << 3;
-        let g = green_value << 2;
-        let b = blue_value << 3;
+        let r = expand_5_to_8(red_value);
+        let g = expand_6_to_8(green_value);
+        let b = expand_5_to_8(blue_value);
 
         Self { r, g, b }
     }
 }
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rgba8888 {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl From<Rgb888> for Rgba8888 {
+    fn from(value: Rgb888) -> Self {
+        Self {
+            r: value.r,
+            g: value.g,
+            b: value.b,
+            a: 0xFF,
+        }
+    }
+}
+
+impl From<Rgba5551> for Rgba8888 {
+    fn from(value: Rgba5551) -> Self {
+        let color_bytes = value.0;
+
+        let red_value = u8::try_from((color_bytes & 0xF800) >> 11).unwrap();
+        let green_value = u8::try_from((color_bytes & 0x07C0) >> 6).unwrap();
+        let blue_value = u8::try_from((color_bytes & 0x003E) >> 1).unwrap();
+        let alpha_value = u8::try_from(color_bytes & 0x0001).unwrap();
+
+        Self {
+            r: expand_5_to_8(red_value),
+            g: expand_5_to_8(green_value),
+            b: expand_5_to_8(blue_value),
+            a: expand_1_to_8(alpha_value),
+        }
+    }
+}
+
+impl From<Rgba4444> for Rgba8888 {
+    fn from(value: Rgba4444) -> Self {
+        let color_bytes = value.0;
+
+        let red_value = u8::try_from((color_bytes & 0xF000) >> 12).unwrap();
+        let green_value = u8::try_from((color_bytes & 0x0F00) >> 8).unwrap();
+        let blue_value = u8::try_from((color_bytes & 0x00F0) >> 4).unwrap();
+        let alpha_value = u8::try_from(color_bytes & 0x000F).unwrap();
+
+        Self {
+            r: expand_4_to_8(red_value),
+            g: expand_4_to_8(green_value),
+            b: expand_4_to_8(blue_value),
+            a: expand_4_to_8(alpha_value),
+        }
+    }
+}
+
+impl From<Rgba8888> for image::Rgba<u8> {
+    fn from(value: Rgba8888) -> Self {
+        image::Rgba([value.r, value.g, value.b, value.a])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bit_replication_fills_the_full_range() {
+        assert_eq!(expand_1_to_8(0x1), 0xFF);
+        assert_eq!(expand_4_to_8(0xF), 0xFF);
+        assert_eq!(expand_5_to_8(0x1F), 0xFF);
+        assert_eq!(expand_6_to_8(0x3F), 0xFF);
+
+        assert_eq!(expand_1_to_8(0x0), 0x00);
+        assert_eq!(expand_4_to_8(0x0), 0x00);
+        assert_eq!(expand_5_to_8(0x00), 0x00);
+        assert_eq!(expand_6_to_8(0x00), 0x00);
+
+        assert_eq!(expand_4_to_8(0x8), 0x88);
+        assert_eq!(expand_5_to_8(0x10), 0x84);
+        assert_eq!(expand_6_to_8(0x20), 0x82);
+    }
+
+    #[test]
+    fn bgr555_white_and_channel_order() {
+        let white = Rgb888::from(Bgr555(0x7FFF));
+        assert_eq!((white.r, white.g, white.b), (0xFF, 0xFF, 0xFF));
+
+        let red = Rgb888::from(Bgr555::from([0x1F, 0x00]));
+        assert_eq!((red.r, red.g, red.b), (0xFF, 0x00, 0x00));
+
+        let blue = Rgb888::from(Bgr555(0x7C00));
+        assert_eq!((blue.r, blue.g, blue.b), (0x00, 0x00, 0xFF));
+    }
+
+    #[test]
+    fn rgb565_white_and_channel_order() {
+        let white = Rgb888::from(Rgb565(0xFFFF));
+        assert_eq!((white.r, white.g, white.b), (0xFF, 0xFF, 0xFF));
+
+        let red = Rgb888::from(Rgb565::from([0x00, 0xF8]));
+        assert_eq!((red.r, red.g, red.b), (0xFF, 0x00, 0x00));
+
+        let green = Rgb888::from(Rgb565(0x07E0));
+        assert_eq!((green.r, green.g, green.b), (0x00, 0xFF, 0x00));
+    }
+
+    #[test]
+    fn rgba5551_and_rgba4444_alpha() {
+        let opaque_red = Rgba8888::from(Rgba5551(0xF801));
+        assert_eq!(
+            (opaque_red.r, opaque_red.g, opaque_red.b, opaque_red.a),
+            (0xFF, 0x00, 0x00, 0xFF)
+        );
+        let transparent_blue = Rgba8888::from(Rgba5551(0x003E));
+        assert_eq!(
+            (
+                transparent_blue.r,
+                transparent_blue.g,
+                transparent_blue.b,
+                transparent_blue.a
+            ),
+            (0x00, 0x00, 0xFF, 0x00)
+        );
+
+        let color = Rgba8888::from(Rgba4444(0x1234));
+        assert_eq!(
+            (color.r, color.g, color.b, color.a),
+            (0x11, 0x22, 0x33, 0x44)
+        );
+    }
+}